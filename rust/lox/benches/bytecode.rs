@@ -0,0 +1,111 @@
+// compares the size of the encoded bytecode of loop-heavy scripts with what the same
+// instructions would take as one `OpCode` and one line number each, and times running them.
+// run with `cargo bench`.
+extern crate lox;
+use lox::*;
+use std::mem::size_of;
+use std::time::{Duration, Instant};
+
+const SCRIPTS: [(&str, &str); 3] = [
+    (
+        "counting loop",
+        r#"
+var sum = 0;
+for (var i = 0; i < 100000; i = i + 1) {
+    sum = sum + i;
+}
+"#,
+    ),
+    (
+        "nested loops",
+        r#"
+var count = 0;
+for (var i = 0; i < 300; i = i + 1) {
+    for (var j = 0; j < 300; j = j + 1) {
+        if (i < j) count = count + 1;
+        else count = count - 1;
+    }
+}
+"#,
+    ),
+    (
+        "fib",
+        r#"
+fun fib(n) {
+    if (n < 2) return n;
+    return fib(n - 2) + fib(n - 1);
+}
+var i = 0;
+while (i < 5) {
+    fib(18);
+    i = i + 1;
+}
+"#,
+    ),
+];
+
+const RUNS: u32 = 10;
+
+// Size counts the bytecode of a function and every function declared inside it.
+#[derive(Default)]
+struct Size {
+    instructions: usize,
+    code: usize,
+    lines: usize,
+}
+
+impl Size {
+    fn add(&mut self, vm: &VM, func_id: Reference<Function>) {
+        let chunk = &vm.allocator.deref(&func_id).chunk;
+        let mut offset = 0;
+        while offset < chunk.code.len() {
+            offset = chunk.read(offset).1;
+            self.instructions += 1;
+        }
+        self.code += chunk.code.len();
+        self.lines += chunk.lines.len() * size_of::<LineStart>();
+        for value in &chunk.values {
+            if let ValueKind::Function(id) = value.kind() {
+                self.add(vm, id);
+            }
+        }
+    }
+
+    fn unencoded(&self) -> usize {
+        self.instructions * (size_of::<OpCode>() + size_of::<usize>())
+    }
+}
+
+fn main() {
+    println!(
+        "{:<16}{:>14}{:>10}{:>10}{:>12}{:>12}",
+        "script", "instructions", "code", "lines", "unencoded", "time/run"
+    );
+    for (name, source) in SCRIPTS.iter() {
+        let mut vm = VM::new();
+        let func_id = match vm.compile(source) {
+            Ok(func_id) => func_id,
+            Err(errors) => panic!("{} doesn't compile: {:?}", name, errors),
+        };
+        let mut size = Size::default();
+        size.add(&vm, func_id);
+
+        let mut total = Duration::default();
+        for _ in 0..RUNS {
+            let mut vm = VM::new();
+            let start = Instant::now();
+            assert_eq!(InterpretResult::Ok, vm.interpret(source));
+            total += start.elapsed();
+        }
+
+        println!(
+            "{:<16}{:>14}{:>9}B{:>9}B{:>11}B{:>12.2?}",
+            name,
+            size.instructions,
+            size.code,
+            size.lines,
+            size.unencoded(),
+            total / RUNS
+        );
+    }
+}
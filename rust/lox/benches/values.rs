@@ -0,0 +1,76 @@
+// times scripts which spend most of their time moving values around, to compare the
+// tagged enum with the nan-boxed representation. run it once with each:
+// `cargo bench --bench values` and `cargo bench --bench values --features nan_boxing`.
+extern crate lox;
+use lox::*;
+use std::mem::size_of;
+use std::time::{Duration, Instant};
+
+const SCRIPTS: [(&str, &str); 4] = [
+    (
+        "arithmetic",
+        r#"
+var x = 0;
+for (var i = 0; i < 200000; i = i + 1) {
+    x = (x + i * 3 - 1) / 2;
+}
+"#,
+    ),
+    (
+        "comparisons",
+        r#"
+var count = 0;
+for (var i = 0; i < 200000; i = i + 1) {
+    if (i < 100000 and i != 5 or i == nil) count = count + 1;
+}
+"#,
+    ),
+    (
+        "fib",
+        r#"
+fun fib(n) {
+    if (n < 2) return n;
+    return fib(n - 2) + fib(n - 1);
+}
+fib(22);
+"#,
+    ),
+    (
+        "lists",
+        r#"
+var xs = [];
+for (var i = 0; i < 50000; i = i + 1) push(xs, i);
+var sum = 0;
+for (var i = 0; i < len(xs); i = i + 1) sum = sum + xs[i];
+"#,
+    ),
+];
+
+const RUNS: u32 = 10;
+
+fn main() {
+    let representation = if cfg!(feature = "nan_boxing") {
+        "nan-boxed"
+    } else {
+        "tagged enum"
+    };
+    println!(
+        "values are a {} of {} bytes",
+        representation,
+        size_of::<Value>()
+    );
+    println!("{:<16}{:>12}{:>12}", "script", "time/run", "fastest");
+    for (name, source) in SCRIPTS.iter() {
+        let mut total = Duration::default();
+        let mut fastest = Duration::MAX;
+        for _ in 0..RUNS {
+            let mut vm = VM::new();
+            let start = Instant::now();
+            assert_eq!(InterpretResult::Ok, vm.interpret(source), "{}", name);
+            let elapsed = start.elapsed();
+            total += elapsed;
+            fastest = fastest.min(elapsed);
+        }
+        println!("{:<16}{:>12.2?}{:>12.2?}", name, total / RUNS, fastest);
+    }
+}
@@ -1,6 +1,10 @@
-use crate::function::Closure;
-use crate::vm::CallFrame;
-use crate::{Function, Value};
+use crate::chunk::LineStart;
+use crate::class::{BoundMethod, Class, Instance};
+use crate::function::{Closure, FunctionUpvalue, NativeFn, Upvalue};
+use crate::list::List;
+use crate::map::Map;
+use crate::module::ScriptModule;
+use crate::{Function, Value, ValueKind};
 use std::any::Any;
 use std::collections::{HashMap, VecDeque};
 use std::fmt;
@@ -29,6 +33,21 @@ impl<T> Clone for Reference<T> {
 
 impl<T> Copy for Reference<T> {}
 
+// a nan-boxed value keeps only the index of the object it refers to.
+#[cfg(feature = "nan_boxing")]
+impl<T> Reference<T> {
+    pub(crate) fn index(&self) -> usize {
+        self.index
+    }
+
+    pub(crate) fn from_index(index: usize) -> Self {
+        Reference {
+            index,
+            _marker: PhantomData,
+        }
+    }
+}
+
 impl<T> fmt::Display for Reference<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "ref({})", self.index)
@@ -45,12 +64,17 @@ struct Empty;
 
 pub trait Trace {
     fn trace(&self, allocator: &mut Allocator);
+    // approximate number of bytes the object holds, used to schedule collections.
+    fn size(&self) -> usize;
     fn as_any(&self) -> &dyn Any;
     fn as_any_mut(&mut self) -> &mut dyn Any;
 }
 
 impl Trace for Empty {
     fn trace(&self, _: &mut Allocator) {}
+    fn size(&self) -> usize {
+        0
+    }
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -61,6 +85,9 @@ impl Trace for Empty {
 
 impl Trace for String {
     fn trace(&self, _: &mut Allocator) {}
+    fn size(&self) -> usize {
+        mem::size_of::<String>() + self.capacity()
+    }
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -72,6 +99,19 @@ impl Trace for String {
 impl Trace for Function {
     fn trace(&self, allocator: &mut Allocator) {
         allocator.mark_object(self.name);
+        for &v in &self.chunk.values {
+            allocator.mark_value(v);
+        }
+        for local in &self.chunk.locals {
+            allocator.mark_object(local.name);
+        }
+    }
+    fn size(&self) -> usize {
+        mem::size_of::<Function>()
+            + self.chunk.code.capacity()
+            + self.chunk.values.capacity() * mem::size_of::<Value>()
+            + self.chunk.lines.capacity() * mem::size_of::<LineStart>()
+            + self.upvalues.capacity() * mem::size_of::<FunctionUpvalue>()
     }
     fn as_any(&self) -> &dyn Any {
         self
@@ -84,6 +124,149 @@ impl Trace for Function {
 impl Trace for Closure {
     fn trace(&self, allocator: &mut Allocator) {
         allocator.mark_object(self.func_id);
+        for &upvalue in &self.upvalues {
+            allocator.mark_object(upvalue);
+        }
+        if let Some(module) = self.module {
+            allocator.mark_object(module);
+        }
+    }
+    fn size(&self) -> usize {
+        mem::size_of::<Closure>() + self.upvalues.capacity() * mem::size_of::<Reference<Upvalue>>()
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Trace for NativeFn {
+    fn trace(&self, allocator: &mut Allocator) {
+        allocator.mark_object(self.name);
+    }
+    fn size(&self) -> usize {
+        mem::size_of::<NativeFn>()
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Trace for Upvalue {
+    fn trace(&self, allocator: &mut Allocator) {
+        if let Some(closed) = self.closed {
+            allocator.mark_value(closed);
+        }
+    }
+    fn size(&self) -> usize {
+        mem::size_of::<Upvalue>()
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Trace for Class {
+    fn trace(&self, allocator: &mut Allocator) {
+        allocator.mark_object(self.name);
+        allocator.mark_table(&self.methods);
+    }
+    fn size(&self) -> usize {
+        mem::size_of::<Class>() + table_size(&self.methods)
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Trace for Instance {
+    fn trace(&self, allocator: &mut Allocator) {
+        allocator.mark_object(self.class);
+        allocator.mark_table(&self.fields);
+    }
+    fn size(&self) -> usize {
+        mem::size_of::<Instance>() + table_size(&self.fields)
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Trace for BoundMethod {
+    fn trace(&self, allocator: &mut Allocator) {
+        allocator.mark_value(self.receiver);
+        allocator.mark_object(self.method);
+    }
+    fn size(&self) -> usize {
+        mem::size_of::<BoundMethod>()
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Trace for List {
+    fn trace(&self, allocator: &mut Allocator) {
+        for &item in &self.items {
+            allocator.mark_value(item);
+        }
+    }
+    fn size(&self) -> usize {
+        mem::size_of::<List>() + self.items.capacity() * mem::size_of::<Value>()
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Trace for Map {
+    fn trace(&self, allocator: &mut Allocator) {
+        for &(k, v) in self.iter() {
+            allocator.mark_value(k);
+            allocator.mark_value(v);
+        }
+    }
+    fn size(&self) -> usize {
+        // roughly an entry plus its slot in the index.
+        mem::size_of::<Map>() + self.len() * (3 * mem::size_of::<Value>() + mem::size_of::<usize>())
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Trace for ScriptModule {
+    fn trace(&self, allocator: &mut Allocator) {
+        self.globals.mark(allocator);
+    }
+    fn size(&self) -> usize {
+        // roughly a name, its slot and its value for each global.
+        mem::size_of::<ScriptModule>()
+            + self.path.capacity()
+            + self.globals.len() * (2 * mem::size_of::<usize>() + mem::size_of::<Option<Value>>())
     }
     fn as_any(&self) -> &dyn Any {
         self
@@ -93,8 +276,19 @@ impl Trace for Closure {
     }
 }
 
+fn table_size(table: &Table) -> usize {
+    table.capacity() * (mem::size_of::<Reference<String>>() + mem::size_of::<Value>())
+}
+
+// Roots is implemented by whoever holds references into the heap from outside of it,
+// so that a collection triggered in one place doesn't free objects held by another.
+pub trait Roots {
+    fn mark_roots(&self, allocator: &mut Allocator);
+}
+
 struct ObjHeader {
     is_marked: bool,
+    size: usize,
     obj: Box<dyn Trace>,
 }
 
@@ -102,16 +296,29 @@ impl ObjHeader {
     fn empty() -> Self {
         Self {
             is_marked: false,
+            size: 0,
             obj: Box::new(Empty {}),
         }
     }
+
+    fn is_free(&self) -> bool {
+        self.obj.as_any().is::<Empty>()
+    }
 }
 
+const GC_HEAP_GROW_FACTOR: usize = 2;
+const GC_INITIAL_THRESHOLD: usize = 1024 * 1024;
+
 pub struct Allocator {
     objects: Vec<ObjHeader>,
     free_slots: Vec<usize>,
     gray_stack: VecDeque<usize>,
     strings: HashMap<String, Reference<String>>,
+    bytes_allocated: usize,
+    next_gc: usize,
+    // the most bytes the heap may hold, see `exceeds_max_heap`.
+    max_heap: Option<usize>,
+    stress_gc: bool,
 }
 
 impl Default for Allocator {
@@ -121,24 +328,60 @@ impl Default for Allocator {
             free_slots: vec![],
             gray_stack: VecDeque::new(),
             strings: HashMap::new(),
+            bytes_allocated: 0,
+            next_gc: GC_INITIAL_THRESHOLD,
+            max_heap: None,
+            stress_gc: false,
         }
     }
 }
 
 impl Allocator {
+    // in stress mode, a collection runs on every allocation.
+    // this is meant for shaking out objects which are not reachable from any root.
+    pub fn set_stress_gc(&mut self, stress_gc: bool) {
+        self.stress_gc = stress_gc;
+    }
 
+    pub fn bytes_allocated(&self) -> usize {
+        self.bytes_allocated
+    }
+
+    // set_max_heap limits how many bytes the heap may hold. the allocator doesn't refuse
+    // allocations beyond it, it is up to whoever allocates to check `exceeds_max_heap`.
+    pub fn set_max_heap(&mut self, max_heap: Option<usize>) {
+        self.max_heap = max_heap;
+    }
+
+    pub fn exceeds_max_heap(&self) -> bool {
+        matches!(self.max_heap, Some(max_heap) if self.bytes_allocated > max_heap)
+    }
+
+    // a collection also runs before the heap outgrows its limit,
+    // so that garbage doesn't count against it.
     pub fn should_gc(&self) -> bool {
-        true
+        let threshold = self
+            .max_heap
+            .map_or(self.next_gc, |max| max.min(self.next_gc));
+        self.stress_gc || self.bytes_allocated > threshold
     }
 
     pub fn alloc<T: Trace + 'static>(&mut self, obj: T) -> Reference<T> {
+        let size = obj.size();
+        self.bytes_allocated += size;
+
+        let header = ObjHeader {
+            is_marked: false,
+            size,
+            obj: Box::new(obj),
+        };
         let index = match self.free_slots.pop() {
-            Some(index) => index,
+            Some(index) => {
+                self.objects[index] = header;
+                index
+            }
             None => {
-                self.objects.push(ObjHeader {
-                    obj: Box::new(obj),
-                    is_marked: false,
-                });
+                self.objects.push(header);
                 self.objects.len() - 1
             }
         };
@@ -160,6 +403,20 @@ impl Allocator {
         reference
     }
 
+    // resize accounts for an object which has grown or shrunk since it was allocated,
+    // e.g. a list which has been pushed onto.
+    pub fn resize<T: Any>(&mut self, reference: &Reference<T>) {
+        let header = &mut self.objects[reference.index];
+        let size = header.obj.size();
+        self.bytes_allocated = self.bytes_allocated - header.size + size;
+        header.size = size;
+    }
+
+    // find_string returns the interned string, if there is one, without allocating it.
+    pub(crate) fn find_string(&self, s: &str) -> Option<Reference<String>> {
+        self.strings.get(s).copied()
+    }
+
     pub fn deref<T: Any>(&self, reference: &Reference<T>) -> &T {
         self.objects[reference.index]
             .obj
@@ -168,21 +425,41 @@ impl Allocator {
             .unwrap()
     }
 
+    pub fn deref_mut<T: Any>(&mut self, reference: &Reference<T>) -> &mut T {
+        self.objects[reference.index]
+            .obj
+            .as_any_mut()
+            .downcast_mut()
+            .unwrap()
+    }
+
     fn free(&mut self, index: usize) {
-        self.objects[index] = ObjHeader::empty();
+        let header = mem::replace(&mut self.objects[index], ObjHeader::empty());
+        self.bytes_allocated -= header.size;
         self.free_slots.push(index);
     }
 
+    // the caller is responsible for marking its roots before collecting.
     pub fn collect_garbage(&mut self) {
         self.trace_references();
+        self.remove_white_strings();
         self.sweep();
+
+        self.next_gc = self.bytes_allocated.max(GC_INITIAL_THRESHOLD) * GC_HEAP_GROW_FACTOR;
     }
 
     pub fn mark_value(&mut self, v: Value) {
-        match v {
-            Value::String(id) => self.mark_object(id),
-            Value::Closure(id) => self.mark_object(id),
-            Value::Function(id) => self.mark_object(id),
+        match v.kind() {
+            ValueKind::String(id) => self.mark_object(id),
+            ValueKind::Closure(id) => self.mark_object(id),
+            ValueKind::Function(id) => self.mark_object(id),
+            ValueKind::NativeFn(id) => self.mark_object(id),
+            ValueKind::Class(id) => self.mark_object(id),
+            ValueKind::Instance(id) => self.mark_object(id),
+            ValueKind::BoundMethod(id) => self.mark_object(id),
+            ValueKind::List(id) => self.mark_object(id),
+            ValueKind::Map(id) => self.mark_object(id),
+            ValueKind::Module(id) => self.mark_object(id),
             _ => (),
         }
     }
@@ -196,7 +473,7 @@ impl Allocator {
         self.gray_stack.push_back(v.index);
     }
 
-    fn mark_table(&mut self, table: &Table) {
+    pub fn mark_table(&mut self, table: &Table) {
         for (&k, &v) in table.iter() {
             self.mark_object(k);
             self.mark_value(v);
@@ -210,18 +487,27 @@ impl Allocator {
     }
 
     fn blacken_object(&mut self, i: usize) {
-        let header = mem::replace(&mut self.objects[i], ObjHeader::empty());
+        // only the object is taken out while it is traced, so that the header
+        // stays marked and an object referring to itself isn't grayed again.
+        let obj: Box<dyn Trace> = mem::replace(&mut self.objects[i].obj, Box::new(Empty {}));
 
-        header.obj.trace(self);
+        obj.trace(self);
+
+        self.objects[i].obj = obj;
+    }
 
-        self.objects[i] = header;
+    // the intern table holds its strings weakly.
+    fn remove_white_strings(&mut self) {
+        let objects = &self.objects;
+        self.strings
+            .retain(|_, reference| objects[reference.index].is_marked);
     }
 
     fn sweep(&mut self) {
         for i in 0..self.objects.len() {
             if self.objects[i].is_marked {
                 self.objects[i].is_marked = false;
-            } else {
+            } else if !self.objects[i].is_free() {
                 self.free(i);
             }
         }
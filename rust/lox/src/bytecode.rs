@@ -0,0 +1,676 @@
+use crate::chunk::{Chunk, Handler, LineStart, LocalName, MAX_BYTE_OPERAND};
+use crate::function::FunctionUpvalue;
+use crate::{Allocator, Function, Globals, OpCode, Reference, Value, ValueKind};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+// compiled bytecode is laid out as follows, with every integer in little-endian:
+//   the magic bytes "LOXB", the format version (u16), and a CRC-32 (u32) of the rest,
+//   the string table: a count (u32), then each string as its length (u32) and utf-8 bytes,
+//   the globals table: a count, then each global slot used by the code (u32) and its name,
+//   the script function.
+// a function is its name (an index into the string table), its arity (u32),
+// its upvalues (a count, then each as is_local (u8) and index (u32)), its code
+// (a length, then the bytes), its line table (a count, then each run as offset and line),
+// its handlers (a count, then each as start, end, target and depth),
+// its local names (a count, then each as name, slot, start and end)
+// and its constants (a count, then each as a tag byte and its payload).
+// functions declared inside it are written out in place of their constant.
+// global slots differ between VMs, so they are moved onto the slots of the same names
+// in the VM which loads the bytecode.
+const MAGIC: &[u8; 4] = b"LOXB";
+const HEADER_LEN: usize = 10;
+
+// FORMAT_VERSION is bumped whenever the layout or the instruction encoding changes,
+// as bytecode from another version can't be run.
+pub const FORMAT_VERSION: u16 = 6;
+
+const TAG_NIL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_NUMBER: u8 = 3;
+const TAG_STRING: u8 = 4;
+const TAG_FUNCTION: u8 = 5;
+
+// LoadError is why compiled bytecode couldn't be loaded.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LoadError {
+    // it doesn't start with the magic bytes, so it isn't compiled lox at all.
+    NotBytecode,
+    // it was written with another version of the format.
+    UnsupportedVersion(u16),
+    // the contents don't match the checksum, e.g. the file was damaged.
+    ChecksumMismatch,
+    // it ends in the middle of something.
+    UnexpectedEnd,
+    // the checksum matches but the contents don't make sense, e.g. an unknown opcode.
+    Corrupt(String),
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::NotBytecode => write!(f, "Not compiled lox bytecode."),
+            LoadError::UnsupportedVersion(version) => write!(
+                f,
+                "Bytecode format version {} is not supported, expected version {}.",
+                version, FORMAT_VERSION
+            ),
+            LoadError::ChecksumMismatch => write!(f, "Bytecode doesn't match its checksum."),
+            LoadError::UnexpectedEnd => write!(f, "Bytecode ends unexpectedly."),
+            LoadError::Corrupt(message) => write!(f, "Corrupt bytecode: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+fn corrupt(message: String) -> LoadError {
+    LoadError::Corrupt(message)
+}
+
+// is_bytecode tells compiled bytecode apart from source code.
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+// serialize writes out a function returned by `Parser::compile`, along with every
+// function declared inside it.
+// the globals are those of the VM which compiled it.
+pub fn serialize(
+    allocator: &Allocator,
+    globals: &Globals,
+    func_id: Reference<Function>,
+) -> Vec<u8> {
+    let mut writer = Writer {
+        allocator,
+        strings: Vec::new(),
+        string_indices: HashMap::new(),
+        global_slots: Vec::new(),
+        out: Vec::new(),
+    };
+    writer.function(func_id);
+
+    let mut global_table = Vec::new();
+    put_len(&mut global_table, writer.global_slots.len());
+    for slot in writer.global_slots.clone() {
+        put_len(&mut global_table, slot);
+        let name = writer.string_index(globals.name(slot));
+        put_len(&mut global_table, name);
+    }
+
+    let mut body = Vec::new();
+    put_len(&mut body, writer.strings.len());
+    for s in &writer.strings {
+        put_len(&mut body, s.len());
+        body.extend_from_slice(s.as_bytes());
+    }
+    body.append(&mut global_table);
+    body.append(&mut writer.out);
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + body.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&crc32(&body).to_le_bytes());
+    bytes.append(&mut body);
+    bytes
+}
+
+// deserialize reads back a script function written by `serialize`, interning its strings
+// and giving its globals slots in `globals`.
+// nothing is collected while loading, and like a freshly compiled function,
+// nothing holds onto the function until it runs.
+pub fn deserialize(
+    allocator: &mut Allocator,
+    globals: &mut Globals,
+    bytes: &[u8],
+) -> Result<Reference<Function>, LoadError> {
+    if !is_bytecode(bytes) {
+        return Err(LoadError::NotBytecode);
+    }
+    if bytes.len() < HEADER_LEN {
+        return Err(LoadError::UnexpectedEnd);
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != FORMAT_VERSION {
+        return Err(LoadError::UnsupportedVersion(version));
+    }
+    let checksum = u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]);
+    let body = &bytes[HEADER_LEN..];
+    if crc32(body) != checksum {
+        return Err(LoadError::ChecksumMismatch);
+    }
+
+    let mut reader = Reader {
+        bytes: body,
+        pos: 0,
+        strings: Vec::new(),
+        global_slots: HashMap::new(),
+    };
+    for i in 0..reader.len()? {
+        let len = reader.len()?;
+        let s = reader.take(len)?;
+        let s = std::str::from_utf8(s)
+            .map_err(|_| corrupt(format!("String {} is not valid UTF-8.", i)))?;
+        reader.strings.push(allocator.new_string(s.to_owned()));
+    }
+    for _ in 0..reader.len()? {
+        let slot = reader.len()?;
+        let name = reader.string()?;
+        reader.global_slots.insert(slot, globals.slot(name));
+    }
+
+    let func_id = reader.function(allocator)?;
+    if reader.pos != body.len() {
+        return Err(corrupt("Unexpected data after the script.".to_owned()));
+    }
+    let script = allocator.deref(&func_id);
+    if script.arity != 0 || !script.upvalues.is_empty() {
+        return Err(corrupt(
+            "The script can't take parameters or capture variables.".to_owned(),
+        ));
+    }
+    Ok(func_id)
+}
+
+fn put_len(out: &mut Vec<u8>, n: usize) {
+    let n = u32::try_from(n).expect("length doesn't fit in the bytecode format");
+    out.extend_from_slice(&n.to_le_bytes());
+}
+
+struct Writer<'a> {
+    allocator: &'a Allocator,
+    // the string table, and where each string is in it.
+    strings: Vec<&'a str>,
+    string_indices: HashMap<Reference<String>, usize>,
+    // the global slots used by the code.
+    global_slots: Vec<usize>,
+    out: Vec<u8>,
+}
+
+impl<'a> Writer<'a> {
+    fn len(&mut self, n: usize) {
+        put_len(&mut self.out, n);
+    }
+
+    fn string_index(&mut self, s: Reference<String>) -> usize {
+        if let Some(&index) = self.string_indices.get(&s) {
+            return index;
+        }
+        let string: &'a String = self.allocator.deref(&s);
+        self.strings.push(string);
+        self.string_indices.insert(s, self.strings.len() - 1);
+        self.strings.len() - 1
+    }
+
+    fn string(&mut self, s: Reference<String>) {
+        let index = self.string_index(s);
+        self.len(index);
+    }
+
+    fn function(&mut self, func_id: Reference<Function>) {
+        let allocator = self.allocator;
+        let function = allocator.deref(&func_id);
+        self.string(function.name);
+        self.len(function.arity);
+
+        self.len(function.upvalues.len());
+        for upvalue in &function.upvalues {
+            self.out.push(upvalue.is_local as u8);
+            self.len(upvalue.index);
+        }
+
+        let chunk = &function.chunk;
+        let mut offset = 0;
+        while offset < chunk.code.len() {
+            let (op, next) = chunk.read(offset);
+            if let OpCode::GetGlobal(slot) | OpCode::SetGlobal(slot) | OpCode::DefineGlobal(slot) =
+                op
+            {
+                if !self.global_slots.contains(&slot) {
+                    self.global_slots.push(slot);
+                }
+            }
+            offset = next;
+        }
+        self.len(chunk.code.len());
+        self.out.extend_from_slice(&chunk.code);
+        self.len(chunk.lines.len());
+        for start in &chunk.lines {
+            self.len(start.offset);
+            self.len(start.line);
+        }
+        self.len(chunk.handlers.len());
+        for handler in &chunk.handlers {
+            self.len(handler.start);
+            self.len(handler.end);
+            self.len(handler.target);
+            self.len(handler.depth);
+        }
+        self.len(chunk.locals.len());
+        for local in &chunk.locals {
+            self.string(local.name);
+            self.len(local.slot);
+            self.len(local.start);
+            self.len(local.end);
+        }
+
+        self.len(chunk.values.len());
+        for value in &chunk.values {
+            match value.kind() {
+                ValueKind::Nil => self.out.push(TAG_NIL),
+                ValueKind::Bool(false) => self.out.push(TAG_FALSE),
+                ValueKind::Bool(true) => self.out.push(TAG_TRUE),
+                ValueKind::Number(n) => {
+                    self.out.push(TAG_NUMBER);
+                    self.out.extend_from_slice(&n.to_le_bytes());
+                }
+                ValueKind::String(s) => {
+                    self.out.push(TAG_STRING);
+                    self.string(s);
+                }
+                ValueKind::Function(f) => {
+                    self.out.push(TAG_FUNCTION);
+                    self.function(f);
+                }
+                // the compiler only makes constants of the values above.
+                other => unreachable!("a compiled function can't hold {:?}", other),
+            }
+        }
+    }
+}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+    pos: usize,
+    strings: Vec<Reference<String>>,
+    // the slot each global in the bytecode has in the VM it is loaded into.
+    global_slots: HashMap<usize, usize>,
+}
+
+impl<'b> Reader<'b> {
+    fn take(&mut self, n: usize) -> Result<&'b [u8], LoadError> {
+        let end = self.pos.saturating_add(n);
+        if end > self.bytes.len() {
+            return Err(LoadError::UnexpectedEnd);
+        }
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn len(&mut self) -> Result<usize, LoadError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+
+    fn number(&mut self) -> Result<f64, LoadError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(f64::from_le_bytes(bytes))
+    }
+
+    fn string(&mut self) -> Result<Reference<String>, LoadError> {
+        let index = self.len()?;
+        self.strings
+            .get(index)
+            .copied()
+            .ok_or_else(|| corrupt(format!("String {} is not in the string table.", index)))
+    }
+
+    fn function(&mut self, allocator: &mut Allocator) -> Result<Reference<Function>, LoadError> {
+        let mut function = Function::new(self.string()?);
+        function.arity = self.len()?;
+
+        for _ in 0..self.len()? {
+            let is_local = match self.byte()? {
+                0 => false,
+                1 => true,
+                b => return Err(corrupt(format!("Invalid upvalue kind {}.", b))),
+            };
+            let index = self.len()?;
+            function.upvalues.push(FunctionUpvalue { index, is_local });
+        }
+
+        let code_len = self.len()?;
+        function.chunk.code = self.take(code_len)?.to_vec();
+        for _ in 0..self.len()? {
+            let offset = self.len()?;
+            let line = self.len()?;
+            function.chunk.lines.push(LineStart { offset, line });
+        }
+        for _ in 0..self.len()? {
+            let handler = Handler {
+                start: self.len()?,
+                end: self.len()?,
+                target: self.len()?,
+                depth: self.len()?,
+            };
+            function.chunk.handlers.push(handler);
+        }
+        for _ in 0..self.len()? {
+            let local = LocalName {
+                name: self.string()?,
+                slot: self.len()?,
+                start: self.len()?,
+                end: self.len()?,
+            };
+            function.chunk.locals.push(local);
+        }
+
+        for _ in 0..self.len()? {
+            let value = match self.byte()? {
+                TAG_NIL => Value::NIL,
+                TAG_FALSE => Value::bool(false),
+                TAG_TRUE => Value::bool(true),
+                TAG_NUMBER => Value::number(self.number()?),
+                TAG_STRING => Value::from(self.string()?),
+                TAG_FUNCTION => Value::from(self.function(allocator)?),
+                tag => return Err(corrupt(format!("Unknown constant tag {}.", tag))),
+            };
+            function.chunk.values.push(value);
+        }
+
+        check(allocator, &function, &self.global_slots)?;
+        self.move_globals(&mut function)?;
+        Ok(allocator.alloc(function))
+    }
+
+    // move_globals puts the function's globals into the slots they have in this VM.
+    fn move_globals(&self, function: &mut Function) -> Result<(), LoadError> {
+        if self.global_slots.iter().all(|(from, to)| from == to) {
+            return Ok(());
+        }
+        // the slots may take a different number of bytes, which moves the jumps around.
+        let (mut instructions, handlers, locals) = function
+            .chunk
+            .decode()
+            .ok_or_else(|| corrupt("Invalid jump.".to_owned()))?;
+        for instruction in &mut instructions {
+            if let OpCode::GetGlobal(slot) | OpCode::SetGlobal(slot) | OpCode::DefineGlobal(slot) =
+                &mut instruction.op
+            {
+                *slot = self.global_slots[slot];
+            }
+        }
+        let values = std::mem::take(&mut function.chunk.values);
+        function.chunk = Chunk::encode(&instructions, &handlers, &locals, values)
+            .ok_or_else(|| corrupt("A jump doesn't fit after moving the globals.".to_owned()))?;
+        Ok(())
+    }
+}
+
+// check makes sure that the VM can run the function without reading past its code,
+// constants or stack: every instruction is whole, refers to constants of the right type and
+// to upvalues it has, every jump, handler and local name lands on an instruction, the code can't
+// run off its end, and the stack is deep enough for what each instruction takes off it,
+// see `check_stack`.
+fn check(
+    allocator: &Allocator,
+    function: &Function,
+    global_slots: &HashMap<usize, usize>,
+) -> Result<(), LoadError> {
+    let chunk = &function.chunk;
+    let string_at = |index: usize| matches!(chunk.values.get(index), Some(v) if matches!(v.kind(), ValueKind::String(_)));
+
+    let mut starts = vec![false; chunk.code.len()];
+    let mut jumps = Vec::new();
+    let mut last = None;
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        if chunk.instruction_len(offset).is_none() {
+            return Err(corrupt(format!(
+                "Invalid instruction at offset {}.",
+                offset
+            )));
+        }
+        starts[offset] = true;
+        let (op, next) = chunk.read(offset);
+        let valid = match op {
+            OpCode::GetGlobal(slot) | OpCode::SetGlobal(slot) | OpCode::DefineGlobal(slot) => {
+                global_slots.contains_key(&slot)
+            }
+            OpCode::Class(index)
+            | OpCode::GetProperty(index)
+            | OpCode::SetProperty(index)
+            | OpCode::Method(index)
+            | OpCode::Invoke(index, _)
+            | OpCode::GetSuper(index)
+            | OpCode::SuperInvoke(index, _)
+            | OpCode::Import(index) => string_at(index),
+            OpCode::Constant(index) => index < chunk.values.len(),
+            OpCode::Closure(index) => match chunk.values.get(index).map(|v| v.kind()) {
+                // the closure captures upvalues of this function by their index.
+                Some(ValueKind::Function(f)) => allocator
+                    .deref(&f)
+                    .upvalues
+                    .iter()
+                    .all(|upvalue| upvalue.is_local || upvalue.index < function.upvalues.len()),
+                _ => false,
+            },
+            OpCode::GetUpvalue(slot) | OpCode::SetUpvalue(slot) => slot < function.upvalues.len(),
+            OpCode::Jump(jump) | OpCode::JumpIfFalse(jump) | OpCode::JumpIfTrue(jump) => {
+                jumps.push((offset, next + jump));
+                true
+            }
+            OpCode::Loop(jump) => {
+                jumps.push((offset, next.wrapping_sub(jump)));
+                true
+            }
+            _ => true,
+        };
+        if !valid {
+            return Err(corrupt(format!(
+                "Invalid operand for {:?} at offset {}.",
+                op, offset
+            )));
+        }
+        last = Some(op);
+        offset = next;
+    }
+
+    // optimized code may end in a loop which never exits, or in a throw.
+    if !matches!(
+        last,
+        Some(OpCode::Return) | Some(OpCode::Loop(_)) | Some(OpCode::Throw)
+    ) {
+        return Err(corrupt("Code doesn't end in a return.".to_owned()));
+    }
+    for (offset, target) in jumps {
+        if !starts.get(target).copied().unwrap_or(false) {
+            return Err(corrupt(format!(
+                "Jump at offset {} doesn't land on an instruction.",
+                offset
+            )));
+        }
+    }
+    // a handler may cover the code up to its very end.
+    let is_start = |offset: usize| offset == chunk.code.len() || starts[offset];
+    for handler in &chunk.handlers {
+        // slot 0 always holds the function, and locals take up to a byte's worth of slots.
+        let valid = handler.start < handler.end
+            && handler.end <= chunk.code.len()
+            && handler.target < chunk.code.len()
+            && is_start(handler.start)
+            && is_start(handler.end)
+            && is_start(handler.target)
+            && (1..=MAX_BYTE_OPERAND + 1).contains(&handler.depth);
+        if !valid {
+            return Err(corrupt(format!("Invalid handler {:?}.", handler)));
+        }
+    }
+    for local in &chunk.locals {
+        let valid = local.start < local.end
+            && local.end <= chunk.code.len()
+            && is_start(local.start)
+            && is_start(local.end)
+            && local.slot <= MAX_BYTE_OPERAND;
+        if !valid {
+            return Err(corrupt(format!("Invalid local name {:?}.", local)));
+        }
+    }
+
+    let lines_in_order = chunk.lines.first().map(|start| start.offset) == Some(0)
+        && chunk.lines.windows(2).all(|w| w[0].offset < w[1].offset)
+        && chunk
+            .lines
+            .iter()
+            .all(|start| start.offset < chunk.code.len());
+    if !lines_in_order {
+        return Err(corrupt("Invalid line table.".to_owned()));
+    }
+    check_stack(allocator, function)
+}
+
+// check_stack follows every path through the code, keeping track of how many values
+// the frame has on the stack, from the function and its arguments on. each instruction
+// takes a fixed number of values off the stack and pushes a fixed number back, so the
+// height has to be the same however an instruction is reached, and locals have to be below it.
+// local names aren't held to it, as `break` and `continue` pop locals still in scope.
+fn check_stack(allocator: &Allocator, function: &Function) -> Result<(), LoadError> {
+    let chunk = &function.chunk;
+    let mut heights: Vec<Option<usize>> = vec![None; chunk.code.len()];
+    // the offsets reached which haven't been followed on from yet.
+    let mut pending = vec![];
+    reach(&mut heights, &mut pending, 0, function.arity + 1)?;
+
+    while let Some(offset) = pending.pop() {
+        let height = heights[offset].expect("pending offsets have a height");
+        let (op, next) = chunk.read(offset);
+        let (pops, pushes) = stack_effect(op);
+        let slot_valid = match op {
+            OpCode::GetLocal(slot) | OpCode::SetLocal(slot) => slot < height,
+            // the closure captures locals of this frame by their slot.
+            OpCode::Closure(index) => match chunk.values[index].kind() {
+                ValueKind::Function(f) => allocator
+                    .deref(&f)
+                    .upvalues
+                    .iter()
+                    .all(|upvalue| !upvalue.is_local || upvalue.index < height),
+                _ => false,
+            },
+            _ => true,
+        };
+        if height < pops || !slot_valid {
+            return Err(corrupt(format!(
+                "Invalid stack use by {:?} at offset {}.",
+                op, offset
+            )));
+        }
+        let after = height - pops + pushes;
+
+        // what the instruction throws is caught with the stack cut down to the handler's depth,
+        // which it may already have taken values off.
+        if let Some(handler) = chunk.handler(offset) {
+            if height - pops < handler.depth {
+                return Err(corrupt(format!("Invalid handler {:?}.", handler)));
+            }
+            reach(
+                &mut heights,
+                &mut pending,
+                handler.target,
+                handler.depth + 1,
+            )?;
+        }
+        match op {
+            OpCode::Return | OpCode::Throw => {}
+            OpCode::Jump(jump) => reach(&mut heights, &mut pending, next + jump, after)?,
+            OpCode::Loop(jump) => reach(&mut heights, &mut pending, next - jump, after)?,
+            OpCode::JumpIfFalse(jump) | OpCode::JumpIfTrue(jump) => {
+                reach(&mut heights, &mut pending, next + jump, after)?;
+                reach(&mut heights, &mut pending, next, after)?;
+            }
+            _ => reach(&mut heights, &mut pending, next, after)?,
+        }
+    }
+    Ok(())
+}
+
+// reach records the height of the stack at an offset, which has to match the height
+// it was reached with before.
+fn reach(
+    heights: &mut [Option<usize>],
+    pending: &mut Vec<usize>,
+    offset: usize,
+    height: usize,
+) -> Result<(), LoadError> {
+    match heights[offset] {
+        None => {
+            heights[offset] = Some(height);
+            pending.push(offset);
+            Ok(())
+        }
+        Some(h) if h == height => Ok(()),
+        Some(_) => Err(corrupt(format!(
+            "Stack height differs between paths to offset {}.",
+            offset
+        ))),
+    }
+}
+
+// stack_effect is how many values an instruction takes off the stack, and how many it
+// pushes back. an instruction which only looks at values takes them off and pushes them back.
+fn stack_effect(op: OpCode) -> (usize, usize) {
+    match op {
+        OpCode::Jump(_) | OpCode::Loop(_) => (0, 0),
+        OpCode::Print
+        | OpCode::Pop
+        | OpCode::DefineGlobal(_)
+        | OpCode::CloseUpvalue
+        | OpCode::Return
+        | OpCode::Throw => (1, 0),
+        OpCode::GetGlobal(_)
+        | OpCode::GetLocal(_)
+        | OpCode::GetUpvalue(_)
+        | OpCode::Constant(_)
+        | OpCode::Closure(_)
+        | OpCode::Class(_)
+        | OpCode::Nil
+        | OpCode::True
+        | OpCode::False
+        | OpCode::Import(_) => (0, 1),
+        OpCode::JumpIfFalse(_)
+        | OpCode::JumpIfTrue(_)
+        | OpCode::SetGlobal(_)
+        | OpCode::SetLocal(_)
+        | OpCode::SetUpvalue(_)
+        | OpCode::GetProperty(_)
+        | OpCode::Negate
+        | OpCode::Not => (1, 1),
+        // a method and a subclass are taken off with the class they go into left below them.
+        OpCode::Method(_) | OpCode::Inherit => (2, 1),
+        OpCode::SetProperty(_)
+        | OpCode::GetSuper(_)
+        | OpCode::GetIndex
+        | OpCode::Equal
+        | OpCode::Greater
+        | OpCode::Less
+        | OpCode::Add
+        | OpCode::Subtract
+        | OpCode::Multiply
+        | OpCode::Divide => (2, 1),
+        OpCode::SetIndex => (3, 1),
+        // calls take the callee or receiver along with the arguments, and super calls the superclass.
+        OpCode::Call(args) | OpCode::Invoke(_, args) => (args + 1, 1),
+        OpCode::SuperInvoke(_, args) => (args + 2, 1),
+        OpCode::BuildList(items) => (items, 1),
+        OpCode::BuildMap(entries) => (entries * 2, 1),
+    }
+}
+
+// crc32 is the usual IEEE CRC-32, as used by zip and png.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
@@ -1,22 +1,43 @@
-use crate::value::Value;
+use crate::value::{Value, ValueKind};
 use crate::Reference;
 
-#[derive(Copy, Clone)]
+// OpCode is a decoded instruction, see `Chunk` for how it is encoded.
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum OpCode {
     Return,
     Print,
     JumpIfFalse(usize),
+    // JumpIfTrue is only emitted by the optimizer, see `optimizer::collapse_not_jumps`.
+    JumpIfTrue(usize),
     Jump(usize),
     Loop(usize),
     Pop,
+    // the operand of the global instructions is the slot of the variable, see `Globals`.
     GetGlobal(usize),
     SetGlobal(usize),
     DefineGlobal(usize),
     GetLocal(usize),
     SetLocal(usize),
+    GetUpvalue(usize),
+    SetUpvalue(usize),
+    CloseUpvalue,
     Constant(usize),
     Call(usize),
     Closure(usize),
+    Class(usize),
+    GetProperty(usize),
+    SetProperty(usize),
+    Method(usize),
+    Invoke(usize, usize),
+    Inherit,
+    GetSuper(usize),
+    SuperInvoke(usize, usize),
+    // BuildList collects that many values from the top of the stack into a new list.
+    BuildList(usize),
+    // BuildMap collects that many pairs of keys and values from the top of the stack into a new map.
+    BuildMap(usize),
+    GetIndex,
+    SetIndex,
     Nil,
     True,
     False,
@@ -29,21 +50,205 @@ pub enum OpCode {
     Divide,
     Negate,
     Not,
+    // Throw raises the value on top of the stack, see `Handler` for where it is caught.
+    Throw,
+    // Import pushes the module at the path in the constant, running it first
+    // the first time it is imported, see `ScriptModule`.
+    Import(usize),
+}
+
+// the opcode byte each instruction starts with.
+const OP_RETURN: u8 = 0;
+const OP_PRINT: u8 = 1;
+const OP_JUMP_IF_FALSE: u8 = 2;
+const OP_JUMP: u8 = 3;
+const OP_LOOP: u8 = 4;
+const OP_POP: u8 = 5;
+const OP_GET_GLOBAL: u8 = 6;
+const OP_SET_GLOBAL: u8 = 7;
+const OP_DEFINE_GLOBAL: u8 = 8;
+const OP_GET_LOCAL: u8 = 9;
+const OP_SET_LOCAL: u8 = 10;
+const OP_GET_UPVALUE: u8 = 11;
+const OP_SET_UPVALUE: u8 = 12;
+const OP_CLOSE_UPVALUE: u8 = 13;
+const OP_CONSTANT: u8 = 14;
+const OP_CALL: u8 = 15;
+const OP_CLOSURE: u8 = 16;
+const OP_CLASS: u8 = 17;
+const OP_GET_PROPERTY: u8 = 18;
+const OP_SET_PROPERTY: u8 = 19;
+const OP_METHOD: u8 = 20;
+const OP_INVOKE: u8 = 21;
+const OP_INHERIT: u8 = 22;
+const OP_GET_SUPER: u8 = 23;
+const OP_SUPER_INVOKE: u8 = 24;
+const OP_BUILD_LIST: u8 = 25;
+const OP_BUILD_MAP: u8 = 26;
+const OP_GET_INDEX: u8 = 27;
+const OP_SET_INDEX: u8 = 28;
+const OP_NIL: u8 = 29;
+const OP_TRUE: u8 = 30;
+const OP_FALSE: u8 = 31;
+const OP_EQUAL: u8 = 32;
+const OP_GREATER: u8 = 33;
+const OP_LESS: u8 = 34;
+const OP_ADD: u8 = 35;
+const OP_SUBTRACT: u8 = 36;
+const OP_MULTIPLY: u8 = 37;
+const OP_DIVIDE: u8 = 38;
+const OP_NEGATE: u8 = 39;
+const OP_NOT: u8 = 40;
+const OP_JUMP_IF_TRUE: u8 = 41;
+const OP_THROW: u8 = 42;
+const OP_IMPORT: u8 = 43;
+
+// set on the opcode byte of an instruction whose constant index doesn't fit in a byte.
+// such a `Long` variant takes a 3-byte index instead.
+const LONG: u8 = 0x80;
+
+// the largest operands the encoding can hold.
+pub const MAX_CONSTANTS: usize = 1 << 24;
+pub const MAX_BYTE_OPERAND: usize = u8::MAX as usize;
+pub const MAX_SHORT_OPERAND: usize = u16::MAX as usize;
+// how many bytes a jump instruction takes.
+pub const JUMP_LEN: usize = 3;
+
+// Operands is what follows the opcode byte of an instruction.
+enum Operands {
+    None,
+    // a local slot, an upvalue or an argument count.
+    Byte(usize),
+    // a jump offset or an item count.
+    Short(usize),
+    // an index into the constants or a global slot, 1 byte wide or 3 bytes for a `Long` variant.
+    Constant(usize),
+    // a constant index for the method name, and an argument count.
+    Invoke(usize, usize),
+}
+
+impl OpCode {
+    // len is how many bytes the instruction takes once encoded.
+    pub(crate) fn len(self) -> usize {
+        let index_len = |index: usize| if index <= MAX_BYTE_OPERAND { 1 } else { 3 };
+        match self.encode().1 {
+            Operands::None => 1,
+            Operands::Byte(_) => 2,
+            Operands::Short(_) => 3,
+            Operands::Constant(index) => 1 + index_len(index),
+            Operands::Invoke(index, _) => 2 + index_len(index),
+        }
+    }
+
+    fn encode(self) -> (u8, Operands) {
+        use Operands::*;
+        match self {
+            OpCode::Return => (OP_RETURN, None),
+            OpCode::Print => (OP_PRINT, None),
+            OpCode::JumpIfFalse(offset) => (OP_JUMP_IF_FALSE, Short(offset)),
+            OpCode::JumpIfTrue(offset) => (OP_JUMP_IF_TRUE, Short(offset)),
+            OpCode::Jump(offset) => (OP_JUMP, Short(offset)),
+            OpCode::Loop(offset) => (OP_LOOP, Short(offset)),
+            OpCode::Pop => (OP_POP, None),
+            OpCode::GetGlobal(slot) => (OP_GET_GLOBAL, Constant(slot)),
+            OpCode::SetGlobal(slot) => (OP_SET_GLOBAL, Constant(slot)),
+            OpCode::DefineGlobal(slot) => (OP_DEFINE_GLOBAL, Constant(slot)),
+            OpCode::GetLocal(slot) => (OP_GET_LOCAL, Byte(slot)),
+            OpCode::SetLocal(slot) => (OP_SET_LOCAL, Byte(slot)),
+            OpCode::GetUpvalue(slot) => (OP_GET_UPVALUE, Byte(slot)),
+            OpCode::SetUpvalue(slot) => (OP_SET_UPVALUE, Byte(slot)),
+            OpCode::CloseUpvalue => (OP_CLOSE_UPVALUE, None),
+            OpCode::Constant(index) => (OP_CONSTANT, Constant(index)),
+            OpCode::Call(arg_num) => (OP_CALL, Byte(arg_num)),
+            OpCode::Closure(index) => (OP_CLOSURE, Constant(index)),
+            OpCode::Class(index) => (OP_CLASS, Constant(index)),
+            OpCode::GetProperty(index) => (OP_GET_PROPERTY, Constant(index)),
+            OpCode::SetProperty(index) => (OP_SET_PROPERTY, Constant(index)),
+            OpCode::Method(index) => (OP_METHOD, Constant(index)),
+            OpCode::Invoke(index, arg_num) => (OP_INVOKE, Invoke(index, arg_num)),
+            OpCode::Inherit => (OP_INHERIT, None),
+            OpCode::GetSuper(index) => (OP_GET_SUPER, Constant(index)),
+            OpCode::SuperInvoke(index, arg_num) => (OP_SUPER_INVOKE, Invoke(index, arg_num)),
+            OpCode::BuildList(item_num) => (OP_BUILD_LIST, Short(item_num)),
+            OpCode::BuildMap(entry_num) => (OP_BUILD_MAP, Short(entry_num)),
+            OpCode::GetIndex => (OP_GET_INDEX, None),
+            OpCode::SetIndex => (OP_SET_INDEX, None),
+            OpCode::Nil => (OP_NIL, None),
+            OpCode::True => (OP_TRUE, None),
+            OpCode::False => (OP_FALSE, None),
+            OpCode::Equal => (OP_EQUAL, None),
+            OpCode::Greater => (OP_GREATER, None),
+            OpCode::Less => (OP_LESS, None),
+            OpCode::Add => (OP_ADD, None),
+            OpCode::Subtract => (OP_SUBTRACT, None),
+            OpCode::Multiply => (OP_MULTIPLY, None),
+            OpCode::Divide => (OP_DIVIDE, None),
+            OpCode::Negate => (OP_NEGATE, None),
+            OpCode::Not => (OP_NOT, None),
+            OpCode::Throw => (OP_THROW, None),
+            OpCode::Import(index) => (OP_IMPORT, Constant(index)),
+        }
+    }
+}
+
+// LineStart marks where a run of bytes compiled from the same source line begins.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LineStart {
+    pub offset: usize,
+    pub line: usize,
+}
+
+// Instruction is a decoded instruction which doesn't depend on where it is in the code:
+// the operand of a jump is the index of the instruction it goes to instead of an offset,
+// and a backward `Loop` is a `Jump` like any other. this way instructions can be removed
+// or change their width without breaking jumps, see `Chunk::decode`.
+#[derive(Copy, Clone)]
+pub(crate) struct Instruction {
+    pub op: OpCode,
+    pub line: usize,
+}
+
+// Handler catches what is thrown while the instructions in `start..end` run,
+// which are those of a `try` block or of the `catch` block before a `finally`.
+// the stack of the frame is cut back to `depth` slots, the thrown value is pushed,
+// and execution goes on at `target`. the offsets are code offsets, or instruction
+// indices in decoded instructions.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Handler {
+    pub start: usize,
+    pub end: usize,
+    pub target: usize,
+    pub depth: usize,
+}
+
+// LocalName is debug info naming the local variable in `slot` of the frame
+// while the code in `start..end` runs, so that debuggers can look locals up by name.
+// like those of handlers, the offsets are instruction indices in decoded instructions.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LocalName {
+    pub name: Reference<String>,
+    pub slot: usize,
+    pub start: usize,
+    pub end: usize,
 }
 
+// Chunk is a function's bytecode.
+// instructions are encoded into `code` as an opcode byte followed by their operands,
+// and `lines` holds one entry per run of instructions from the same line.
+// `handlers` go from the innermost `try` out, so the first one which covers
+// an instruction is the one catching what it throws. `locals` name the local variables.
+#[derive(Default)]
 pub struct Chunk {
-    pub instructions: Vec<OpCode>,
+    pub code: Vec<u8>,
     pub values: Vec<Value>,
-    pub lines: Vec<usize>,
+    pub lines: Vec<LineStart>,
+    pub handlers: Vec<Handler>,
+    pub locals: Vec<LocalName>,
 }
 
 impl Chunk {
     pub fn new() -> Self {
-        Self {
-            instructions: Vec::new(),
-            values: Vec::new(),
-            lines: Vec::new(),
-        }
+        Self::default()
     }
 
     pub fn add_constant(&mut self, value: Value) -> usize {
@@ -51,13 +256,355 @@ impl Chunk {
         self.values.len() - 1
     }
 
+    // add_instruction encodes the instruction at the end of the code.
+    // the compiler keeps the operands within what the encoding can hold.
     pub fn add_instruction(&mut self, op: OpCode, line: usize) {
-        self.instructions.push(op);
-        self.lines.push(line);
+        if self.lines.last().map(|last| last.line) != Some(line) {
+            self.lines.push(LineStart {
+                offset: self.code.len(),
+                line,
+            });
+        }
+
+        let (opcode, operands) = op.encode();
+        match operands {
+            Operands::None => self.code.push(opcode),
+            Operands::Byte(n) => {
+                self.code.push(opcode);
+                self.push_byte(n);
+            }
+            Operands::Short(n) => {
+                self.code.push(opcode);
+                self.push_short(n);
+            }
+            Operands::Constant(index) => self.push_constant(opcode, index),
+            Operands::Invoke(index, arg_num) => {
+                self.push_constant(opcode, index);
+                self.push_byte(arg_num);
+            }
+        }
+    }
+
+    fn push_byte(&mut self, n: usize) {
+        assert!(n <= MAX_BYTE_OPERAND, "operand {} doesn't fit in a byte", n);
+        self.code.push(n as u8);
+    }
+
+    fn push_short(&mut self, n: usize) {
+        assert!(
+            n <= MAX_SHORT_OPERAND,
+            "operand {} doesn't fit in 2 bytes",
+            n
+        );
+        self.code.extend_from_slice(&(n as u16).to_be_bytes());
+    }
+
+    fn push_constant(&mut self, opcode: u8, index: usize) {
+        if index <= MAX_BYTE_OPERAND {
+            self.code.push(opcode);
+            self.code.push(index as u8);
+        } else {
+            assert!(
+                index < MAX_CONSTANTS,
+                "constant index {} is too large",
+                index
+            );
+            self.code.push(opcode | LONG);
+            self.code
+                .extend_from_slice(&(index as u32).to_be_bytes()[1..]);
+        }
+    }
+
+    // patch_jump replaces the offset of the jump instruction starting at `offset`.
+    pub fn patch_jump(&mut self, offset: usize, jump: usize) {
+        assert!(
+            jump <= MAX_SHORT_OPERAND,
+            "jump {} doesn't fit in 2 bytes",
+            jump
+        );
+        self.code[offset + 1..offset + 3].copy_from_slice(&(jump as u16).to_be_bytes());
     }
 
-    pub fn read_string(&self, index: usize) -> &Reference<String> {
-        if let Value::String(ref v) = self.values[index] {
+    // read decodes the instruction starting at `offset`,
+    // and returns it along with where the next instruction starts.
+    pub fn read(&self, offset: usize) -> (OpCode, usize) {
+        let code = &self.code[offset..];
+        let opcode = code[0];
+        let byte = |i: usize| code[i] as usize;
+        let short = |i: usize| (byte(i) << 8) | byte(i + 1);
+        // the constant index, and how many bytes it takes.
+        let constant = || {
+            if opcode & LONG != 0 {
+                ((byte(1) << 16) | (byte(2) << 8) | byte(3), 3)
+            } else {
+                (byte(1), 1)
+            }
+        };
+
+        let (op, len) = match opcode & !LONG {
+            OP_RETURN => (OpCode::Return, 1),
+            OP_PRINT => (OpCode::Print, 1),
+            OP_JUMP_IF_FALSE => (OpCode::JumpIfFalse(short(1)), 3),
+            OP_JUMP_IF_TRUE => (OpCode::JumpIfTrue(short(1)), 3),
+            OP_JUMP => (OpCode::Jump(short(1)), 3),
+            OP_LOOP => (OpCode::Loop(short(1)), 3),
+            OP_POP => (OpCode::Pop, 1),
+            OP_GET_GLOBAL => {
+                let (index, index_len) = constant();
+                (OpCode::GetGlobal(index), 1 + index_len)
+            }
+            OP_SET_GLOBAL => {
+                let (index, index_len) = constant();
+                (OpCode::SetGlobal(index), 1 + index_len)
+            }
+            OP_DEFINE_GLOBAL => {
+                let (index, index_len) = constant();
+                (OpCode::DefineGlobal(index), 1 + index_len)
+            }
+            OP_GET_LOCAL => (OpCode::GetLocal(byte(1)), 2),
+            OP_SET_LOCAL => (OpCode::SetLocal(byte(1)), 2),
+            OP_GET_UPVALUE => (OpCode::GetUpvalue(byte(1)), 2),
+            OP_SET_UPVALUE => (OpCode::SetUpvalue(byte(1)), 2),
+            OP_CLOSE_UPVALUE => (OpCode::CloseUpvalue, 1),
+            OP_CONSTANT => {
+                let (index, index_len) = constant();
+                (OpCode::Constant(index), 1 + index_len)
+            }
+            OP_CALL => (OpCode::Call(byte(1)), 2),
+            OP_CLOSURE => {
+                let (index, index_len) = constant();
+                (OpCode::Closure(index), 1 + index_len)
+            }
+            OP_CLASS => {
+                let (index, index_len) = constant();
+                (OpCode::Class(index), 1 + index_len)
+            }
+            OP_GET_PROPERTY => {
+                let (index, index_len) = constant();
+                (OpCode::GetProperty(index), 1 + index_len)
+            }
+            OP_SET_PROPERTY => {
+                let (index, index_len) = constant();
+                (OpCode::SetProperty(index), 1 + index_len)
+            }
+            OP_METHOD => {
+                let (index, index_len) = constant();
+                (OpCode::Method(index), 1 + index_len)
+            }
+            OP_INVOKE => {
+                let (index, index_len) = constant();
+                (OpCode::Invoke(index, byte(1 + index_len)), 2 + index_len)
+            }
+            OP_INHERIT => (OpCode::Inherit, 1),
+            OP_GET_SUPER => {
+                let (index, index_len) = constant();
+                (OpCode::GetSuper(index), 1 + index_len)
+            }
+            OP_SUPER_INVOKE => {
+                let (index, index_len) = constant();
+                (
+                    OpCode::SuperInvoke(index, byte(1 + index_len)),
+                    2 + index_len,
+                )
+            }
+            OP_BUILD_LIST => (OpCode::BuildList(short(1)), 3),
+            OP_BUILD_MAP => (OpCode::BuildMap(short(1)), 3),
+            OP_GET_INDEX => (OpCode::GetIndex, 1),
+            OP_SET_INDEX => (OpCode::SetIndex, 1),
+            OP_NIL => (OpCode::Nil, 1),
+            OP_TRUE => (OpCode::True, 1),
+            OP_FALSE => (OpCode::False, 1),
+            OP_EQUAL => (OpCode::Equal, 1),
+            OP_GREATER => (OpCode::Greater, 1),
+            OP_LESS => (OpCode::Less, 1),
+            OP_ADD => (OpCode::Add, 1),
+            OP_SUBTRACT => (OpCode::Subtract, 1),
+            OP_MULTIPLY => (OpCode::Multiply, 1),
+            OP_DIVIDE => (OpCode::Divide, 1),
+            OP_NEGATE => (OpCode::Negate, 1),
+            OP_NOT => (OpCode::Not, 1),
+            OP_THROW => (OpCode::Throw, 1),
+            OP_IMPORT => {
+                let (index, index_len) = constant();
+                (OpCode::Import(index), 1 + index_len)
+            }
+            _ => unreachable!("unknown opcode {}", opcode),
+        };
+        (op, offset + len)
+    }
+
+    // handler returns the innermost handler covering the instruction at `offset`.
+    pub fn handler(&self, offset: usize) -> Option<Handler> {
+        self.handlers
+            .iter()
+            .find(|handler| handler.start <= offset && offset < handler.end)
+            .copied()
+    }
+
+    // decode turns the code into instructions which can be rewritten and then encoded again,
+    // along with the handlers and local names pointing at them.
+    pub(crate) fn decode(&self) -> Option<(Vec<Instruction>, Vec<Handler>, Vec<LocalName>)> {
+        let mut decoded = Vec::new();
+        let mut offset = 0;
+        while offset < self.code.len() {
+            let (op, next) = self.read(offset);
+            decoded.push((offset, op, next));
+            offset = next;
+        }
+
+        // the end of the code is where a handler covering the last instruction ends.
+        let index_of = |offset: usize| match decoded.binary_search_by_key(&offset, |d| d.0) {
+            Ok(index) => Some(index),
+            Err(index) if index == decoded.len() && offset == self.code.len() => Some(index),
+            Err(_) => None,
+        };
+        let instructions = decoded
+            .iter()
+            .map(|&(offset, op, next)| {
+                let op = match op {
+                    OpCode::Jump(jump) => OpCode::Jump(index_of(next + jump)?),
+                    OpCode::Loop(jump) => OpCode::Jump(index_of(next - jump)?),
+                    OpCode::JumpIfFalse(jump) => OpCode::JumpIfFalse(index_of(next + jump)?),
+                    OpCode::JumpIfTrue(jump) => OpCode::JumpIfTrue(index_of(next + jump)?),
+                    op => op,
+                };
+                Some(Instruction {
+                    op,
+                    line: self.line(offset),
+                })
+            })
+            .collect::<Option<_>>()?;
+        let handlers = self
+            .handlers
+            .iter()
+            .map(|handler| {
+                Some(Handler {
+                    start: index_of(handler.start)?,
+                    end: index_of(handler.end)?,
+                    target: index_of(handler.target)?,
+                    depth: handler.depth,
+                })
+            })
+            .collect::<Option<_>>()?;
+        let locals = self
+            .locals
+            .iter()
+            .map(|local| {
+                Some(LocalName {
+                    start: index_of(local.start)?,
+                    end: index_of(local.end)?,
+                    ..*local
+                })
+            })
+            .collect::<Option<_>>()?;
+        Some((instructions, handlers, locals))
+    }
+
+    // encode builds a chunk out of decoded instructions, or returns None if a jump
+    // doesn't fit in its operand anymore.
+    pub(crate) fn encode(
+        instructions: &[Instruction],
+        handlers: &[Handler],
+        locals: &[LocalName],
+        values: Vec<Value>,
+    ) -> Option<Chunk> {
+        // jumps always take the same number of bytes, so every offset is known up front.
+        let mut offsets = Vec::with_capacity(instructions.len() + 1);
+        let mut offset = 0;
+        for instruction in instructions {
+            offsets.push(offset);
+            offset += instruction.op.len();
+        }
+        offsets.push(offset);
+
+        let mut chunk = Chunk::new();
+        chunk.values = values;
+        for (i, instruction) in instructions.iter().enumerate() {
+            let next = offsets[i + 1];
+            let op = match instruction.op {
+                OpCode::Jump(target) if offsets[target] < next => {
+                    OpCode::Loop(next - offsets[target])
+                }
+                OpCode::Jump(target) => OpCode::Jump(offsets[target] - next),
+                OpCode::JumpIfFalse(target) => {
+                    OpCode::JumpIfFalse(offsets[target].checked_sub(next)?)
+                }
+                OpCode::JumpIfTrue(target) => {
+                    OpCode::JumpIfTrue(offsets[target].checked_sub(next)?)
+                }
+                op => op,
+            };
+            match op {
+                OpCode::Jump(jump)
+                | OpCode::Loop(jump)
+                | OpCode::JumpIfFalse(jump)
+                | OpCode::JumpIfTrue(jump)
+                    if jump > MAX_SHORT_OPERAND =>
+                {
+                    return None
+                }
+                _ => chunk.add_instruction(op, instruction.line),
+            }
+        }
+        chunk.handlers = handlers
+            .iter()
+            .map(|handler| Handler {
+                start: offsets[handler.start],
+                end: offsets[handler.end],
+                target: offsets[handler.target],
+                depth: handler.depth,
+            })
+            .collect();
+        chunk.locals = locals
+            .iter()
+            .map(|local| LocalName {
+                start: offsets[local.start],
+                end: offsets[local.end],
+                ..*local
+            })
+            .collect();
+        Some(chunk)
+    }
+
+    // instruction_len returns how many bytes the instruction starting at `offset` takes,
+    // or None if there isn't a whole instruction there, which only happens to code
+    // that didn't come from the compiler.
+    pub(crate) fn instruction_len(&self, offset: usize) -> Option<usize> {
+        let opcode = *self.code.get(offset)?;
+        let index_len = if opcode & LONG != 0 { 3 } else { 1 };
+        let len = match opcode & !LONG {
+            OP_JUMP_IF_FALSE | OP_JUMP_IF_TRUE | OP_JUMP | OP_LOOP | OP_BUILD_LIST
+            | OP_BUILD_MAP => 3,
+            OP_GET_LOCAL | OP_SET_LOCAL | OP_GET_UPVALUE | OP_SET_UPVALUE | OP_CALL => 2,
+            OP_GET_GLOBAL | OP_SET_GLOBAL | OP_DEFINE_GLOBAL | OP_CONSTANT | OP_CLOSURE
+            | OP_CLASS | OP_GET_PROPERTY | OP_SET_PROPERTY | OP_METHOD | OP_GET_SUPER
+            | OP_IMPORT => 1 + index_len,
+            OP_INVOKE | OP_SUPER_INVOKE => 2 + index_len,
+            op if op <= OP_NOT || op == OP_THROW => 1,
+            _ => return None,
+        };
+        if offset + len > self.code.len() {
+            return None;
+        }
+        Some(len)
+    }
+
+    // line returns the source line of the instruction which the byte at `offset` belongs to.
+    pub fn line(&self, offset: usize) -> usize {
+        let run = self.lines.partition_point(|start| start.offset <= offset);
+        self.lines[run - 1].line
+    }
+
+    // locals_at returns the names of the local variables in scope at `offset`,
+    // innermost last.
+    pub fn locals_at(&self, offset: usize) -> impl Iterator<Item = &LocalName> {
+        self.locals
+            .iter()
+            .filter(move |local| local.start <= offset && offset < local.end)
+    }
+
+    pub fn read_string(&self, index: usize) -> Reference<String> {
+        if let ValueKind::String(v) = self.values[index].kind() {
             return v;
         }
         unreachable!()
@@ -73,65 +620,133 @@ impl Debug for Chunk {
         println!("== {} ==", name);
 
         println!("==== instructions ====");
-        for i in 0..self.instructions.len() {
-            disassemble_instruction(self, i)
+        let mut offset = 0;
+        while offset < self.code.len() {
+            offset = disassemble_instruction(self, offset);
         }
         println!("==== values ====");
         for i in 0..self.values.len() {
             println!("{}: {:?}", i, &self.values[i]);
         }
+        if !self.handlers.is_empty() {
+            println!("==== handlers ====");
+            for handler in &self.handlers {
+                println!(
+                    "{:04}..{:04} -> {:04} (depth {})",
+                    handler.start, handler.end, handler.target, handler.depth
+                );
+            }
+        }
+        if !self.locals.is_empty() {
+            println!("==== locals ====");
+            for local in &self.locals {
+                println!(
+                    "{:04}..{:04} slot {} {:?}",
+                    local.start, local.end, local.slot, local.name
+                );
+            }
+        }
     }
 }
 
-pub fn disassemble_instruction(chunk: &Chunk, offset: usize) {
+// disassemble_instruction prints the instruction starting at `offset`,
+// and returns where the next one starts.
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
     print!("{:04} ", offset);
-    if offset > 0 && chunk.lines[offset] == chunk.lines[offset - 1] {
+    if offset > 0 && chunk.line(offset) == chunk.line(offset - 1) {
         print!("   | ");
     } else {
-        print!("{:04} ", chunk.lines[offset]);
-    }
-
-    if let Some(op) = chunk.instructions.get(offset) {
-        match op {
-            OpCode::Return => simple_instruction("OP_RETURN"),
-            OpCode::Print => simple_instruction("OP_PRINT"),
-            OpCode::JumpIfFalse(offset) => byte_instruction("OP_JUMP_IF_FALSE", *offset),
-            OpCode::Jump(offset) => byte_instruction("OP_JUMP", *offset),
-            OpCode::Loop(offset) => byte_instruction("OP_LOOP", *offset),
-            OpCode::Pop => simple_instruction("OP_POP"),
-            OpCode::GetGlobal(index) => constant_instruction("OP_GET_GLOBAL", chunk, *index),
-            OpCode::SetGlobal(index) => constant_instruction("OP_SET_GLOBAL", chunk, *index),
-            OpCode::DefineGlobal(index) => constant_instruction("OP_DEFINE_GLOBAL", chunk, *index),
-            OpCode::GetLocal(index) => byte_instruction("OP_GET_LOCAL", *index),
-            OpCode::SetLocal(index) => byte_instruction("OP_SET_LOCAL", *index),
-            OpCode::Constant(index) => constant_instruction("OP_CONSTANT", chunk, *index),
-            OpCode::Call(arg_num) => byte_instruction("OP_CALL", *arg_num),
-            OpCode::Closure(_) => simple_instruction("OP_CLOSURE"),
-            OpCode::Negate => simple_instruction("OP_NEGATE"),
-            OpCode::Add => simple_instruction("OP_ADD"),
-            OpCode::Subtract => simple_instruction("OP_SUBTRACT"),
-            OpCode::Multiply => simple_instruction("OP_MULTIPLY"),
-            OpCode::Divide => simple_instruction("OP_DIVIDE"),
-            OpCode::Nil => simple_instruction("OP_NIL"),
-            OpCode::True => simple_instruction("OP_TRUE"),
-            OpCode::False => simple_instruction("OP_FALSE"),
-            OpCode::Equal => simple_instruction("OP_EQUAL"),
-            OpCode::Greater => simple_instruction("OP_GREATER"),
-            OpCode::Less => simple_instruction("OP_LESS"),
-            OpCode::Not => simple_instruction("OP_NOT"),
+        print!("{:04} ", chunk.line(offset));
+    }
+
+    let (op, next) = chunk.read(offset);
+    let long = chunk.code[offset] & LONG != 0;
+    match op {
+        OpCode::Return => simple_instruction("OP_RETURN"),
+        OpCode::Print => simple_instruction("OP_PRINT"),
+        OpCode::JumpIfFalse(jump) => jump_instruction("OP_JUMP_IF_FALSE", next, jump as isize),
+        OpCode::JumpIfTrue(jump) => jump_instruction("OP_JUMP_IF_TRUE", next, jump as isize),
+        OpCode::Jump(jump) => jump_instruction("OP_JUMP", next, jump as isize),
+        OpCode::Loop(jump) => jump_instruction("OP_LOOP", next, -(jump as isize)),
+        OpCode::Pop => simple_instruction("OP_POP"),
+        OpCode::GetGlobal(slot) => byte_instruction(&long_name("OP_GET_GLOBAL", long), slot),
+        OpCode::SetGlobal(slot) => byte_instruction(&long_name("OP_SET_GLOBAL", long), slot),
+        OpCode::DefineGlobal(slot) => byte_instruction(&long_name("OP_DEFINE_GLOBAL", long), slot),
+        OpCode::GetLocal(index) => byte_instruction("OP_GET_LOCAL", index),
+        OpCode::SetLocal(index) => byte_instruction("OP_SET_LOCAL", index),
+        OpCode::GetUpvalue(index) => byte_instruction("OP_GET_UPVALUE", index),
+        OpCode::SetUpvalue(index) => byte_instruction("OP_SET_UPVALUE", index),
+        OpCode::CloseUpvalue => simple_instruction("OP_CLOSE_UPVALUE"),
+        OpCode::Constant(index) => constant_instruction("OP_CONSTANT", chunk, index, long),
+        OpCode::Call(arg_num) => byte_instruction("OP_CALL", arg_num),
+        OpCode::Closure(index) => constant_instruction("OP_CLOSURE", chunk, index, long),
+        OpCode::Class(index) => constant_instruction("OP_CLASS", chunk, index, long),
+        OpCode::GetProperty(index) => constant_instruction("OP_GET_PROPERTY", chunk, index, long),
+        OpCode::SetProperty(index) => constant_instruction("OP_SET_PROPERTY", chunk, index, long),
+        OpCode::Method(index) => constant_instruction("OP_METHOD", chunk, index, long),
+        OpCode::Invoke(index, arg_num) => {
+            invoke_instruction("OP_INVOKE", chunk, index, arg_num, long)
         }
+        OpCode::Inherit => simple_instruction("OP_INHERIT"),
+        OpCode::GetSuper(index) => constant_instruction("OP_GET_SUPER", chunk, index, long),
+        OpCode::SuperInvoke(index, arg_num) => {
+            invoke_instruction("OP_SUPER_INVOKE", chunk, index, arg_num, long)
+        }
+        OpCode::BuildList(item_num) => byte_instruction("OP_BUILD_LIST", item_num),
+        OpCode::BuildMap(entry_num) => byte_instruction("OP_BUILD_MAP", entry_num),
+        OpCode::GetIndex => simple_instruction("OP_GET_INDEX"),
+        OpCode::SetIndex => simple_instruction("OP_SET_INDEX"),
+        OpCode::Negate => simple_instruction("OP_NEGATE"),
+        OpCode::Add => simple_instruction("OP_ADD"),
+        OpCode::Subtract => simple_instruction("OP_SUBTRACT"),
+        OpCode::Multiply => simple_instruction("OP_MULTIPLY"),
+        OpCode::Divide => simple_instruction("OP_DIVIDE"),
+        OpCode::Nil => simple_instruction("OP_NIL"),
+        OpCode::True => simple_instruction("OP_TRUE"),
+        OpCode::False => simple_instruction("OP_FALSE"),
+        OpCode::Equal => simple_instruction("OP_EQUAL"),
+        OpCode::Greater => simple_instruction("OP_GREATER"),
+        OpCode::Less => simple_instruction("OP_LESS"),
+        OpCode::Not => simple_instruction("OP_NOT"),
+        OpCode::Throw => simple_instruction("OP_THROW"),
+        OpCode::Import(index) => constant_instruction("OP_IMPORT", chunk, index, long),
     }
+    next
 }
 
 fn simple_instruction(name: &str) {
     println!("{}", name);
 }
 
-fn constant_instruction(name: &str, chunk: &Chunk, index: usize) {
+fn long_name(name: &str, long: bool) -> String {
+    if long {
+        format!("{}_LONG", name)
+    } else {
+        name.to_owned()
+    }
+}
+
+fn constant_instruction(name: &str, chunk: &Chunk, index: usize, long: bool) {
+    let value = &chunk.values[index];
+    println!("{} {:04} {:.2}", long_name(name, long), index, value);
+}
+
+fn invoke_instruction(name: &str, chunk: &Chunk, index: usize, arg_num: usize, long: bool) {
     let value = &chunk.values[index];
-    println!("{} {:04} {:.2}", name, index, value);
+    println!(
+        "{} ({} args) {:04} {:.2}",
+        long_name(name, long),
+        arg_num,
+        index,
+        value
+    );
 }
 
 fn byte_instruction(name: &str, index: usize) {
     println!("{} {:04}", name, index);
 }
+
+// jump_instruction shows where the jump lands, counting from the next instruction.
+fn jump_instruction(name: &str, next: usize, jump: isize) {
+    println!("{} {:04} -> {}", name, jump.abs(), next as isize + jump);
+}
@@ -0,0 +1,62 @@
+use crate::allocator::Table;
+use crate::function::Closure;
+use crate::{Reference, Value};
+
+pub struct Class {
+    pub name: Reference<String>,
+    pub methods: Table,
+}
+
+impl std::fmt::Debug for Class {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "<class {}>", self.name)
+    }
+}
+
+impl Class {
+    pub fn new(name: Reference<String>) -> Self {
+        Self {
+            name,
+            methods: Table::new(),
+        }
+    }
+}
+
+pub struct Instance {
+    pub class: Reference<Class>,
+    pub fields: Table,
+}
+
+impl std::fmt::Debug for Instance {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "<instance {}>", self.class)
+    }
+}
+
+impl Instance {
+    pub fn new(class: Reference<Class>) -> Self {
+        Self {
+            class,
+            fields: Table::new(),
+        }
+    }
+}
+
+// BoundMethod is a method closure paired with the instance it was accessed from,
+// so that `this` still refers to the receiver when the method is called later.
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: Reference<Closure>,
+}
+
+impl std::fmt::Debug for BoundMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "<bound method {}>", self.method)
+    }
+}
+
+impl BoundMethod {
+    pub fn new(receiver: Value, method: Reference<Closure>) -> Self {
+        Self { receiver, method }
+    }
+}
@@ -1,9 +1,14 @@
-use crate::chunk::{Debug, OpCode};
-use crate::function::{Function, FunctionType};
+use crate::chunk::{
+    Handler, LocalName, OpCode, JUMP_LEN, MAX_BYTE_OPERAND, MAX_CONSTANTS, MAX_SHORT_OPERAND,
+};
+use crate::function::{Function, FunctionType, FunctionUpvalue};
+use crate::globals::Globals;
+use crate::optimizer::{self, OptimizationLevel};
 use crate::scanner::Scanner;
 use crate::token::{Token, TokenType};
 use crate::value::Value;
 
+use crate::allocator::{Roots, Trace};
 use crate::{Allocator, Reference};
 use std::collections::HashMap;
 use std::mem;
@@ -51,7 +56,48 @@ impl Add<i32> for &Precedence {
     }
 }
 
-type ParseFn<'r> = fn(&mut Parser<'r>, can_assign: bool) -> Result<(), String>;
+// CompileError is a diagnostic reported by the scanner or the parser.
+// both `line` and `column` are zero-based, like `Token::line`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CompileError {
+    pub line: usize,
+    pub column: usize,
+    pub lexeme: String,
+    pub message: String,
+}
+
+impl CompileError {
+    pub fn new(token: &Token, message: &str) -> Self {
+        Self {
+            line: token.line,
+            column: token.column,
+            lexeme: token.source.to_owned(),
+            message: message.to_owned(),
+        }
+    }
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.lexeme.is_empty() {
+            write!(
+                f,
+                "[line {}:{}] Error at end: {}",
+                self.line, self.column, self.message
+            )
+        } else {
+            write!(
+                f,
+                "[line {}:{}] Error at '{}': {}",
+                self.line, self.column, self.lexeme, self.message
+            )
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+type ParseFn<'r> = fn(&mut Parser<'r>, can_assign: bool) -> Result<(), CompileError>;
 
 struct ParseRule<'r> {
     prefix: Option<ParseFn<'r>>,
@@ -78,9 +124,50 @@ pub struct Compiler<'a> {
     scope_depth: usize,
     function: Function,
     func_type: FunctionType,
+    // the `try` statements with a `finally` block being compiled, innermost last.
+    finally_scopes: Vec<FinallyScope>,
+    // the loops being compiled, innermost last.
+    loops: Vec<Loop>,
     enclosing: Option<Box<Compiler<'a>>>,
 }
 
+// FinallyScope is a `try` statement whose `finally` block has to run before a `return`,
+// `break` or `continue` inside it leaves the statement. the returned value is kept
+// in a hidden local, next to another one telling the `finally` block how it was entered:
+// nil when falling through, false when returning, true when an exception was thrown,
+// and the index of the loop exit in `loop_exits` when breaking or continuing.
+struct FinallyScope {
+    value_slot: usize,
+    kind_slot: usize,
+    // the number of locals when the `try` block starts.
+    depth: usize,
+    // the jumps into the `finally` block, to patch once it is compiled.
+    exit_jumps: Vec<usize>,
+    loop_exits: Vec<Exit>,
+}
+
+// Loop is a loop whose body is being compiled, which `break` and `continue` jump out of.
+struct Loop {
+    // where `continue` goes: the condition of a `while` loop, or the increment of a `for` loop.
+    start: usize,
+    // the number of locals outside of the body.
+    depth: usize,
+    // the number of finally scopes outside of the loop.
+    finally_depth: usize,
+    // the jumps out of the loop, to patch once it is compiled.
+    break_jumps: Vec<usize>,
+}
+
+// Exit is a statement jumping out of the statements it is in.
+#[derive(Copy, Clone, PartialEq)]
+enum Exit {
+    // returns the value on top of the stack.
+    Return,
+    // breaks out of or continues the loop at the index in `Compiler::loops`.
+    Break(usize),
+    Continue(usize),
+}
+
 impl<'a> Compiler<'a> {
     pub fn new(name: Reference<String>, kind: FunctionType) -> Box<Self> {
         let mut compiler = Self {
@@ -88,19 +175,88 @@ impl<'a> Compiler<'a> {
             scope_depth: 0,
             function: Function::new(name),
             func_type: kind,
+            finally_scopes: Vec::new(),
+            loops: Vec::new(),
             enclosing: None,
         };
 
-        compiler.locals.push(Local { name: "", depth: 0 });
+        // the first slot holds the callee itself, or the receiver inside methods.
+        let slot_name = match compiler.func_type {
+            FunctionType::Method | FunctionType::Initializer => "this",
+            _ => "",
+        };
+        compiler.locals.push(Local {
+            name: slot_name,
+            depth: Some(0),
+            is_captured: false,
+            name_index: None,
+        });
 
         Box::new(compiler)
     }
+
+    fn resolve_local(&self, name: &'a str) -> Result<Option<usize>, String> {
+        for (i, local) in self.locals.iter().enumerate().rev() {
+            if local.name == name {
+                if local.depth.is_none() {
+                    return Err("Can't read local variable in its own initializer.".to_string());
+                }
+                return Ok(Some(i));
+            }
+        }
+        Ok(None)
+    }
+
+    // looks for a variable declared in any of the surrounding functions.
+    // each function along the way records the upvalue so that the closure
+    // created at runtime can capture it from its direct parent.
+    fn resolve_upvalue(&mut self, name: &'a str) -> Result<Option<usize>, String> {
+        let enclosing = match self.enclosing.as_mut() {
+            Some(enclosing) => enclosing,
+            None => return Ok(None),
+        };
+
+        if let Some(index) = enclosing.resolve_local(name)? {
+            enclosing.locals[index].is_captured = true;
+            return self.add_upvalue(index, true).map(Some);
+        }
+
+        if let Some(index) = enclosing.resolve_upvalue(name)? {
+            return self.add_upvalue(index, false).map(Some);
+        }
+
+        Ok(None)
+    }
+
+    fn add_upvalue(&mut self, index: usize, is_local: bool) -> Result<usize, String> {
+        let upvalue = FunctionUpvalue { index, is_local };
+        let upvalues = &mut self.function.upvalues;
+        if let Some(i) = upvalues.iter().position(|u| *u == upvalue) {
+            return Ok(i);
+        }
+        if upvalues.len() > MAX_BYTE_OPERAND {
+            return Err("Too many closure variables in function.".to_string());
+        }
+        upvalues.push(upvalue);
+        Ok(upvalues.len() - 1)
+    }
+}
+
+// ClassCompiler tracks the class whose body is currently being compiled.
+struct ClassCompiler<'a> {
+    name: &'a str,
+    has_superclass: bool,
 }
 
 pub struct Parser<'a> {
     compiler: Box<Compiler<'a>>,
+    class_compilers: Vec<ClassCompiler<'a>>,
     tokens: Vec<Token<'a>>,
+    errors: Vec<CompileError>,
     allocator: &'a mut Allocator,
+    globals: &'a mut Globals,
+    roots: Option<&'a dyn Roots>,
+    optimization: OptimizationLevel,
     token_pos: usize,
     parse_rules: HashMap<TokenType, ParseRule<'a>>,
 }
@@ -108,7 +264,11 @@ pub struct Parser<'a> {
 #[derive(Default)]
 struct Local<'a> {
     name: &'a str,
-    depth: usize,
+    // None until the variable's initializer has been compiled.
+    depth: Option<usize>,
+    is_captured: bool,
+    // where the local is in the chunk's local names, see `LocalName`.
+    name_index: Option<usize>,
 }
 
 macro_rules! parse_rules {
@@ -127,24 +287,37 @@ macro_rules! parse_rules {
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(allocator: &'a mut Allocator) -> Self {
+    pub fn new(allocator: &'a mut Allocator, globals: &'a mut Globals) -> Self {
         let func_name = allocator.new_string("script".to_string());
 
         Self {
             compiler: Compiler::new(func_name, FunctionType::Script),
+            class_compilers: Vec::new(),
             allocator,
+            globals,
+            roots: None,
+            optimization: OptimizationLevel::None,
             tokens: Vec::new(),
+            errors: Vec::new(),
             token_pos: 0,
             parse_rules: parse_rules![
                 LeftParen => Some(Parser::grouping), Some(Parser::call), Call;
                 RightParen => None, None, None;
+                LeftBrace => Some(Parser::map), None, None;
+                RightBrace => None, None, None;
+                LeftBracket => Some(Parser::list), Some(Parser::index), Call;
+                RightBracket => None, None, None;
+                Dot => None, Some(Parser::dot), Call;
                 Plus => None, Some(Parser::binary), Term;
                 Minus => Some(Parser::unary), Some(Parser::binary), Term;
                 Star => None, Some(Parser::binary), Term;
                 Slash => None, Some(Parser::binary), Term;
                 SemiColon => None, None, None;
+                Colon => None, None, None;
                 Comma => None, None, None;
                 Identifier => Some(Parser::variable), None, None;
+                This => Some(Parser::this), None, None;
+                Super => Some(Parser::super_), None, None;
                 String => Some(Parser::string), None, None;
                 Number => Some(Parser::number), None, None;
                 And => None, Some(Parser::and), And;
@@ -153,6 +326,22 @@ impl<'a> Parser<'a> {
                 False => Some(Parser::literal), None, None;
                 Nil => Some(Parser::literal), None, None;
                 Print => None, None, None;
+                Class => None, None, None;
+                Else => None, None, None;
+                For => None, None, None;
+                Fun => Some(Parser::lambda), None, None;
+                If => None, None, None;
+                Return => None, None, None;
+                Break => None, None, None;
+                Continue => None, None, None;
+                Import => None, None, None;
+                As => None, None, None;
+                Throw => None, None, None;
+                Try => None, None, None;
+                Catch => None, None, None;
+                Finally => None, None, None;
+                Var => None, None, None;
+                While => None, None, None;
                 Bang => Some(Parser::unary), None, None;
                 BangEqual => None, Some(Parser::binary), Equality;
                 Equal => None, None, None;
@@ -161,30 +350,87 @@ impl<'a> Parser<'a> {
                 GreaterEqual => None, Some(Parser::binary), Comparison;
                 Less => None, Some(Parser::binary), Comparison;
                 LessEqual => None, Some(Parser::binary), Comparison;
+                Error => None, None, None;
                 Eof => None, None, None;
             ],
         }
     }
 
-    pub fn compile(&mut self, source: &'a str) -> Result<Reference<Function>, String> {
-        match Scanner::new(source).scan_tokens() {
-            Ok(tokens) => {
-                self.tokens = tokens;
-            }
-            Err(e) => return Err(e),
-        }
+    // compiles the whole source even after an error,
+    // so that every diagnostic is reported at once.
+    pub fn compile(&mut self, source: &'a str) -> Result<Reference<Function>, Vec<CompileError>> {
+        let (tokens, mut errors) = Scanner::new(source).scan_tokens();
+        self.tokens = tokens;
 
         while !self.advance_if_matched(TokenType::Eof) {
-            self.declaration()?;
+            self.declaration();
         }
         self.end_compiler();
 
-        let func_name = self.allocator.new_string(self.previous().source.to_owned());
+        errors.append(&mut self.errors);
+        if !errors.is_empty() {
+            errors.sort_by_key(|e| (e.line, e.column));
+            return Err(errors);
+        }
+
+        let func_name = self.intern(self.previous().source.to_owned());
         let function = std::mem::replace(&mut self.compiler.function, Function::new(func_name));
-        let func_id = self.allocator.alloc(function);
+        let func_id = self.alloc(function);
         Ok(func_id)
     }
 
+    // compile_module compiles the script of an imported module,
+    // which ends by returning the module it runs with in its first slot.
+    pub(crate) fn compile_module(
+        &mut self,
+        source: &'a str,
+    ) -> Result<Reference<Function>, Vec<CompileError>> {
+        self.compiler.func_type = FunctionType::Module;
+        self.compile(source)
+    }
+
+    pub fn set_optimization_level(&mut self, level: OptimizationLevel) {
+        self.optimization = level;
+    }
+
+    // registers objects held by the caller (e.g. the VM), which must survive
+    // collections triggered while compiling.
+    pub(crate) fn set_roots(&mut self, roots: &'a dyn Roots) {
+        self.roots = Some(roots);
+    }
+
+    fn alloc<T: Trace + 'static>(&mut self, obj: T) -> Reference<T> {
+        if self.allocator.should_gc() {
+            self.mark_roots();
+            obj.trace(self.allocator);
+            self.allocator.collect_garbage();
+        }
+        self.allocator.alloc(obj)
+    }
+
+    fn intern(&mut self, name: String) -> Reference<String> {
+        if self.allocator.should_gc() {
+            self.mark_roots();
+            self.allocator.collect_garbage();
+        }
+        self.allocator.new_string(name)
+    }
+
+    // functions still being compiled are not on the heap yet,
+    // so their names and constants are marked through the compiler chain.
+    fn mark_roots(&mut self) {
+        if let Some(roots) = self.roots {
+            roots.mark_roots(self.allocator);
+        }
+        self.globals.mark(self.allocator);
+
+        let mut compiler = Some(&self.compiler);
+        while let Some(c) = compiler {
+            c.function.trace(self.allocator);
+            compiler = c.enclosing.as_ref();
+        }
+    }
+
     fn advance_if_matched(&mut self, typ: TokenType) -> bool {
         if self.current().typ == typ {
             self.advance();
@@ -198,56 +444,205 @@ impl<'a> Parser<'a> {
         self.token_pos += 1;
     }
 
+    // reading past the end keeps returning the trailing EOF token.
     fn current(&self) -> &Token<'a> {
-        &self.tokens[self.token_pos]
+        &self.tokens[self.token_pos.min(self.tokens.len() - 1)]
     }
 
     fn previous(&self) -> &Token<'a> {
-        &self.tokens[self.token_pos - 1]
+        &self.tokens[(self.token_pos - 1).min(self.tokens.len() - 1)]
+    }
+
+    fn error_at_current(&self, msg: &str) -> CompileError {
+        CompileError::new(self.current(), msg)
+    }
+
+    fn error_at_previous(&self, msg: &str) -> CompileError {
+        CompileError::new(self.previous(), msg)
+    }
+
+    // skips tokens until a statement boundary after an error,
+    // so that a single mistake doesn't cascade into more diagnostics.
+    fn synchronize(&mut self) {
+        while self.current().typ != TokenType::Eof {
+            if self.token_pos > 0 && self.previous().typ == TokenType::SemiColon {
+                return;
+            }
+            match self.current().typ {
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return
+                | TokenType::Throw
+                | TokenType::Try
+                | TokenType::Import
+                | TokenType::Break
+                | TokenType::Continue => return,
+                _ => self.advance(),
+            }
+        }
     }
 
     /*
     program -> declaration* EOF ;
-    declaration -> classDecl | funDecl | varDecl | statement ;
-    statement -> exprStmt | forStmt | ifStmt | printStmt | returnStmt | whileStmt | block ;
+    declaration -> classDecl | funDecl | varDecl | importDecl | statement ;
+    statement -> exprStmt | forStmt | ifStmt | printStmt | returnStmt | breakStmt
+               | continueStmt | throwStmt | tryStmt | whileStmt | block ;
      */
 
-    fn declaration(&mut self) -> Result<(), String> {
-        if self.advance_if_matched(TokenType::Fun) {
+    fn declaration(&mut self) {
+        let result = if self.advance_if_matched(TokenType::Class) {
+            self.class_declaration()
+        } else if self.current().typ == TokenType::Fun && !self.is_lambda() {
+            self.advance();
             self.fun_declaration()
         } else if self.advance_if_matched(TokenType::Var) {
             self.var_declaration()
+        } else if self.advance_if_matched(TokenType::Import) {
+            self.import_declaration()
         } else {
             self.statement()
+        };
+
+        if let Err(e) = result {
+            self.errors.push(e);
+            self.synchronize();
         }
     }
 
     fn parse_identifier(&mut self) -> &'a str {
         let name = self.previous().source;
         if self.compiler.scope_depth > 0 {
-            self.compiler.locals.push(Local { name, depth: 0 });
+            self.add_local(name);
         }
         name
     }
 
+    fn add_local(&mut self, name: &'a str) {
+        if self.compiler.locals.len() > MAX_BYTE_OPERAND {
+            // reported without stopping, as the declaration itself parses fine.
+            let err = self.error_at_previous("Too many local variables in function.");
+            self.errors.push(err);
+            return;
+        }
+        self.compiler.locals.push(Local {
+            name,
+            depth: None,
+            is_captured: false,
+            name_index: None,
+        });
+    }
+
     fn define_variable(&mut self, name: &'a str) {
         if self.compiler.scope_depth > 0 {
             self.compiler
                 .locals
                 .last_mut()
                 .expect("Expect locals exist one more")
-                .depth = self.compiler.scope_depth;
+                .depth = Some(self.compiler.scope_depth);
+            self.name_local(self.compiler.locals.len() - 1);
             return;
         }
 
-        let global = self.identifier_constant(name);
-        self.emit(OpCode::DefineGlobal(global));
+        let slot = self.global_slot(name);
+        self.emit(OpCode::DefineGlobal(slot));
+    }
+
+    // ```
+    // "class" IDENTIFIER ( "<" IDENTIFIER )? "{" function* "}"
+    // ```
+    fn class_declaration(&mut self) -> Result<(), CompileError> {
+        self.consume(TokenType::Identifier, "Expect class name.")?;
+        let name = self.parse_identifier();
+        let name_constant = self.identifier_constant(name);
+
+        self.emit(OpCode::Class(name_constant));
+        self.define_variable(name);
+
+        self.class_compilers.push(ClassCompiler {
+            name,
+            has_superclass: false,
+        });
+        let result = self.class_body();
+        let class_compiler = self.class_compilers.pop().expect("Expect a class");
+
+        if class_compiler.has_superclass {
+            self.end_scope();
+        }
+
+        result
+    }
+
+    fn class_body(&mut self) -> Result<(), CompileError> {
+        let name = self.class_compilers.last().expect("Expect a class").name;
+
+        if self.advance_if_matched(TokenType::Less) {
+            self.superclass(name)?;
+        }
+
+        // load the class back onto the stack so that methods can be bound to it.
+        self.named_variable(name, false)?;
+
+        self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
+        while self.current().typ != TokenType::RightBrace && self.current().typ != TokenType::Eof {
+            self.method()?;
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after class body.")?;
+        self.emit(OpCode::Pop);
+
+        Ok(())
+    }
+
+    fn superclass(&mut self, name: &'a str) -> Result<(), CompileError> {
+        self.consume(TokenType::Identifier, "Expect superclass name.")?;
+        let superclass = self.previous().source;
+        if superclass == name {
+            return Err(self.error_at_previous("A class can't inherit from itself."));
+        }
+        self.named_variable(superclass, false)?;
+
+        // the superclass is kept in a local named 'super' for the class body,
+        // so that methods can capture it like any other variable.
+        self.begin_scope();
+        self.add_local("super");
+        self.define_variable("super");
+
+        self.named_variable(name, false)?;
+        self.emit(OpCode::Inherit);
+
+        self.class_compilers
+            .last_mut()
+            .expect("Expect a class")
+            .has_superclass = true;
+
+        Ok(())
+    }
+
+    fn method(&mut self) -> Result<(), CompileError> {
+        self.consume(TokenType::Identifier, "Expect method name.")?;
+        let name = self.previous().source;
+        let name_constant = self.identifier_constant(name);
+
+        let kind = if name == "init" {
+            FunctionType::Initializer
+        } else {
+            FunctionType::Method
+        };
+        self.function(name, kind)?;
+
+        self.emit(OpCode::Method(name_constant));
+
+        Ok(())
     }
 
     // ```
     // "fun" IDENTIFIER "(" ")" "{" blockStmt
     // ```
-    fn fun_declaration(&mut self) -> Result<(), String> {
+    fn fun_declaration(&mut self) -> Result<(), CompileError> {
         self.consume(TokenType::Identifier, "Expect function name")?;
         let name = self.parse_identifier();
 
@@ -258,38 +653,86 @@ impl<'a> Parser<'a> {
         Ok(())
     }
 
+    // a statement starting with `fun (` is an anonymous function used as an expression,
+    // e.g. one which is called right away.
+    fn is_lambda(&self) -> bool {
+        let next = self.tokens.get(self.token_pos + 1).map(|token| token.typ);
+        next == Some(TokenType::LeftParen)
+    }
+
+    // name_local starts the code where the local in `slot` can be looked up by its name.
+    // the hidden locals have no name to look them up by.
+    fn name_local(&mut self, slot: usize) {
+        let name = self.compiler.locals[slot].name;
+        if name.is_empty() {
+            return;
+        }
+        let name = self.intern(name.to_owned());
+        let chunk = &mut self.compiler.function.chunk;
+        let start = chunk.code.len();
+        chunk.locals.push(LocalName {
+            name,
+            slot,
+            start,
+            end: start,
+        });
+        self.compiler.locals[slot].name_index = Some(chunk.locals.len() - 1);
+    }
+
+    // unname_local ends the code where the local can be looked up, as it goes out of scope.
+    fn unname_local(&mut self, slot: usize) {
+        if let Some(index) = self.compiler.locals[slot].name_index {
+            let chunk = &mut self.compiler.function.chunk;
+            chunk.locals[index].end = chunk.code.len();
+        }
+    }
+
     fn push_compiler(&mut self, name: &str, kind: FunctionType) {
-        let func_name = self.allocator.new_string(name.to_owned());
+        let func_name = self.intern(name.to_owned());
         let new_compiler = Compiler::new(func_name, kind);
         let old_compiler = mem::replace(&mut self.compiler, new_compiler);
         self.compiler.enclosing = Some(old_compiler);
-        let func_name = self.allocator.new_string(name.to_string());
-        self.compiler.function.name = func_name;
+        self.name_local(0);
     }
 
     fn pop_compiler(&mut self) -> Function {
         self.end_compiler();
 
-        let function = match self.compiler.enclosing.take() {
+        match self.compiler.enclosing.take() {
             Some(enclosing) => {
                 let compiler = mem::replace(&mut self.compiler, enclosing);
                 compiler.function
             }
             None => panic!("Cannot find an enclosing compiler."),
-        };
-
-        function
+        }
     }
 
-    fn function(&mut self, name: &str, kind: FunctionType) -> Result<(), String> {
+    fn function(&mut self, name: &str, kind: FunctionType) -> Result<(), CompileError> {
         self.push_compiler(name, kind);
+        // the compiler is popped even on error, so that parsing resumes in the enclosing function.
+        let result = self.function_body();
+        let function = self.pop_compiler();
+        result?;
+
+        let func_id = self.alloc(function);
+        let index = self.make_constant(Value::from(func_id));
+        self.emit(OpCode::Closure(index));
+
+        Ok(())
+    }
+
+    fn function_body(&mut self) -> Result<(), CompileError> {
         self.begin_scope();
 
         self.consume(TokenType::LeftParen, "Expect '(' after function name.")?;
 
         if !self.advance_if_matched(TokenType::RightParen) {
             loop {
-                self.advance();
+                if self.compiler.function.arity == MAX_BYTE_OPERAND {
+                    return Err(self.error_at_current("Can't have more than 255 parameters."));
+                }
+                self.compiler.function.arity += 1;
+                self.consume(TokenType::Identifier, "Expect parameter name.")?;
                 let param = self.parse_identifier();
                 self.define_variable(param);
 
@@ -300,20 +743,13 @@ impl<'a> Parser<'a> {
             self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
         }
         self.consume(TokenType::LeftBrace, "Expect '{' before function body.")?;
-        self.block()?;
-
-        let function = self.pop_compiler();
-        let func_id = self.allocator.alloc(function);
-        let index = self.make_constant(Value::Function(func_id));
-        self.emit(OpCode::Closure(index));
-
-        Ok(())
+        self.block()
     }
 
     // ```
     // "var" IDENTIFIER ("=" expression)? ";" ;
     // ```
-    fn var_declaration(&mut self) -> Result<(), String> {
+    fn var_declaration(&mut self) -> Result<(), CompileError> {
         self.consume(TokenType::Identifier, "Expect variable name")?;
         let name = self.parse_identifier();
 
@@ -330,38 +766,79 @@ impl<'a> Parser<'a> {
         Ok(())
     }
 
+    /*
+    importDecl -> "import" STRING "as" IDENTIFIER ";" ;
+     */
+    fn import_declaration(&mut self) -> Result<(), CompileError> {
+        self.consume(TokenType::String, "Expect module path after 'import'.")?;
+        // trim quotes
+        let path = &self.previous().source[1..=self.previous().source.len() - 2];
+        let path = self.intern(path.to_string());
+        let index = self.make_constant(Value::from(path));
+        self.consume(TokenType::As, "Expect 'as' after module path.")?;
+        self.consume(TokenType::Identifier, "Expect module name.")?;
+        let name = self.parse_identifier();
+        self.consume(TokenType::SemiColon, "Expect ';' after import.")?;
+
+        self.emit(OpCode::Import(index));
+        self.define_variable(name);
+
+        Ok(())
+    }
+
     fn identifier_constant(&mut self, name: &'a str) -> usize {
         let name = name.to_string();
-        let s = self.allocator.new_string(name);
-        let idx = self.make_constant(Value::String(s));
-        return idx;
+        let s = self.intern(name);
+        self.make_constant(Value::from(s))
+    }
+
+    // global_slot resolves a global variable to its slot, which it keeps
+    // for every script compiled in the same VM.
+    fn global_slot(&mut self, name: &'a str) -> usize {
+        let name = self.intern(name.to_string());
+        let slot = self.globals.slot(name);
+        if slot >= MAX_CONSTANTS {
+            let err = self.error_at_previous("Too many global variables.");
+            self.errors.push(err);
+            return 0;
+        }
+        slot
     }
 
     /*
-    statement -> exprStmt | forStmt | ifStmt | printStmt | returnStmt | whileStmt | block ;
+    statement -> exprStmt | forStmt | ifStmt | printStmt | returnStmt | breakStmt
+               | continueStmt | throwStmt | tryStmt | whileStmt | block ;
      */
-    fn statement(&mut self) -> Result<(), String> {
+    fn statement(&mut self) -> Result<(), CompileError> {
         if self.advance_if_matched(TokenType::Print) {
             self.print_statement()
         } else if self.advance_if_matched(TokenType::If) {
             self.if_statement()
         } else if self.advance_if_matched(TokenType::Return) {
             self.return_statement()
+        } else if self.advance_if_matched(TokenType::Break) {
+            self.break_statement()
+        } else if self.advance_if_matched(TokenType::Continue) {
+            self.continue_statement()
+        } else if self.advance_if_matched(TokenType::Throw) {
+            self.throw_statement()
+        } else if self.advance_if_matched(TokenType::Try) {
+            self.try_statement()
         } else if self.advance_if_matched(TokenType::While) {
             self.while_statement()
         } else if self.advance_if_matched(TokenType::For) {
             self.for_statement()
         } else if self.advance_if_matched(TokenType::LeftBrace) {
             self.begin_scope();
-            self.block()?;
+            let result = self.block();
             self.end_scope();
-            Ok(())
+            result
         } else {
             self.expression_statement()
         }
     }
 
-    fn print_statement(&mut self) -> Result<(), String> {
+    fn print_statement(&mut self) -> Result<(), CompileError> {
         self.expression()?;
         self.consume(TokenType::SemiColon, "Expect ';' after print statement.")?;
         self.emit(OpCode::Print);
@@ -369,7 +846,7 @@ impl<'a> Parser<'a> {
         Ok(())
     }
 
-    fn if_statement(&mut self) -> Result<(), String> {
+    fn if_statement(&mut self) -> Result<(), CompileError> {
         self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
         self.expression()?;
         self.consume(TokenType::RightParen, "Expect ')' after condition of 'if'.")?;
@@ -394,27 +871,300 @@ impl<'a> Parser<'a> {
         Ok(())
     }
 
-    fn return_statement(&mut self) -> Result<(), String> {
-        if self.compiler.func_type == FunctionType::Script {
-            return Err(format!(
-                "[Line {}] Error: Cannot return from top-level code.",
-                self.current().line
-            ));
+    fn return_statement(&mut self) -> Result<(), CompileError> {
+        if matches!(
+            self.compiler.func_type,
+            FunctionType::Script | FunctionType::Module
+        ) {
+            return Err(self.error_at_previous("Can't return from top-level code."));
         }
 
         if self.advance_if_matched(TokenType::SemiColon) {
-            self.emit_return();
+            self.emit_return_value();
         } else {
+            if self.compiler.func_type == FunctionType::Initializer {
+                return Err(self.error_at_previous("Can't return a value from an initializer."));
+            }
             self.expression()?;
             self.consume(TokenType::SemiColon, "Expect ';' after return value.")?;
-            self.emit(OpCode::Return);
         }
 
+        self.emit_exit(Exit::Return);
+
+        Ok(())
+    }
+
+    fn break_statement(&mut self) -> Result<(), CompileError> {
+        let index = match self.compiler.loops.len() {
+            0 => return Err(self.error_at_previous("Can't use 'break' outside of a loop.")),
+            n => n - 1,
+        };
+        self.consume(TokenType::SemiColon, "Expect ';' after 'break'.")?;
+        self.emit_exit(Exit::Break(index));
+
+        Ok(())
+    }
+
+    fn continue_statement(&mut self) -> Result<(), CompileError> {
+        let index = match self.compiler.loops.len() {
+            0 => return Err(self.error_at_previous("Can't use 'continue' outside of a loop.")),
+            n => n - 1,
+        };
+        self.consume(TokenType::SemiColon, "Expect ';' after 'continue'.")?;
+        self.emit_exit(Exit::Continue(index));
+
+        Ok(())
+    }
+
+    // emit_exit jumps out of the statements being compiled, discarding the locals declared
+    // in them. when there is a `finally` block on the way, the jump goes there instead,
+    // recording the exit for the block to carry on with once it has run.
+    fn emit_exit(&mut self, exit: Exit) {
+        let finally_depth = match exit {
+            Exit::Return => 0,
+            Exit::Break(index) | Exit::Continue(index) => self.compiler.loops[index].finally_depth,
+        };
+        if self.compiler.finally_scopes.len() > finally_depth {
+            let scope = self.compiler.finally_scopes.len() - 1;
+            let FinallyScope {
+                value_slot,
+                kind_slot,
+                depth,
+                ..
+            } = self.compiler.finally_scopes[scope];
+            if exit == Exit::Return {
+                self.emit(OpCode::SetLocal(value_slot));
+                self.emit(OpCode::Pop);
+                self.emit(OpCode::False);
+            } else {
+                let exits = &mut self.compiler.finally_scopes[scope].loop_exits;
+                let code = match exits.iter().position(|&e| e == exit) {
+                    Some(code) => code,
+                    None => {
+                        exits.push(exit);
+                        exits.len() - 1
+                    }
+                };
+                self.emit_constant(Value::number(code as f64));
+            }
+            self.emit(OpCode::SetLocal(kind_slot));
+            self.emit(OpCode::Pop);
+            self.discard_locals(depth);
+            let jump = self.emit_jump(OpCode::Jump(0));
+            self.compiler.finally_scopes[scope].exit_jumps.push(jump);
+            return;
+        }
+
+        match exit {
+            Exit::Return => self.emit(OpCode::Return),
+            Exit::Break(index) => {
+                self.discard_locals(self.compiler.loops[index].depth);
+                let jump = self.emit_jump(OpCode::Jump(0));
+                self.compiler.loops[index].break_jumps.push(jump);
+            }
+            Exit::Continue(index) => {
+                self.discard_locals(self.compiler.loops[index].depth);
+                self.emit_loop(self.compiler.loops[index].start);
+            }
+        }
+    }
+
+    /*
+    throwStmt -> "throw" expression ";" ;
+     */
+    fn throw_statement(&mut self) -> Result<(), CompileError> {
+        self.expression()?;
+        self.consume(TokenType::SemiColon, "Expect ';' after thrown value.")?;
+        self.emit(OpCode::Throw);
+
         Ok(())
     }
 
-    fn while_statement(&mut self) -> Result<(), String> {
-        let start_pos = self.compiler.function.chunk.instructions.len() - 1;
+    /*
+    tryStmt -> "try" block ( "catch" "(" IDENTIFIER ")" block )? ( "finally" block )? ;
+     */
+    fn try_statement(&mut self) -> Result<(), CompileError> {
+        let finally_scopes = self.compiler.finally_scopes.len();
+        self.begin_scope();
+        let result = self.try_clauses();
+        self.end_scope();
+        self.compiler.finally_scopes.truncate(finally_scopes);
+        result
+    }
+
+    // the `try` block is covered by a handler going to the `catch` block, which is covered
+    // by another one itself when there is a `finally` block. without a `catch` block,
+    // the `try` block's handler goes straight to storing the exception for the `finally` block.
+    fn try_clauses(&mut self) -> Result<(), CompileError> {
+        let has_finally = self.has_finally();
+        if has_finally {
+            // the hidden locals of the finally scope, see FinallyScope.
+            for _ in 0..2 {
+                self.emit(OpCode::Nil);
+                self.add_local("");
+                self.define_variable("");
+            }
+            let kind_slot = self.compiler.locals.len() - 1;
+            self.compiler.finally_scopes.push(FinallyScope {
+                value_slot: kind_slot - 1,
+                kind_slot,
+                depth: self.compiler.locals.len(),
+                exit_jumps: Vec::new(),
+                loop_exits: Vec::new(),
+            });
+        }
+        let depth = self.compiler.locals.len();
+
+        self.consume(TokenType::LeftBrace, "Expect '{' after 'try'.")?;
+        let start = self.compiler.function.chunk.code.len();
+        self.begin_scope();
+        let result = self.block();
+        self.end_scope();
+        result?;
+        let end = self.compiler.function.chunk.code.len();
+        let mut exits = vec![self.emit_jump(OpCode::Jump(0))];
+
+        let mut handler = Handler {
+            start,
+            end,
+            target: self.compiler.function.chunk.code.len(),
+            depth,
+        };
+        if self.advance_if_matched(TokenType::Catch) {
+            self.add_handler(handler);
+            self.consume(TokenType::LeftParen, "Expect '(' after 'catch'.")?;
+            self.consume(TokenType::Identifier, "Expect exception variable name.")?;
+            // the handler leaves the exception where the variable's slot is.
+            self.begin_scope();
+            let name = self.parse_identifier();
+            self.define_variable(name);
+            let result = self.consume(TokenType::RightParen, "Expect ')' after catch variable.");
+            let result = result.and_then(|_| {
+                self.consume(TokenType::LeftBrace, "Expect '{' after catch variable.")
+            });
+            let start = self.compiler.function.chunk.code.len();
+            let result = result.and_then(|_| self.block());
+            let end = self.compiler.function.chunk.code.len();
+            self.end_scope();
+            result?;
+            exits.push(self.emit_jump(OpCode::Jump(0)));
+            handler = Handler {
+                start,
+                end,
+                target: self.compiler.function.chunk.code.len(),
+                depth,
+            };
+        } else if !has_finally {
+            return Err(self.error_at_current("Expect 'catch' or 'finally' after try block."));
+        }
+
+        if !has_finally {
+            for exit in exits {
+                self.patch_jump(exit);
+            }
+            return Ok(());
+        }
+        self.add_handler(handler);
+        let scope = self
+            .compiler
+            .finally_scopes
+            .pop()
+            .expect("Expect the finally scope of the try statement");
+
+        // the exception is on top of the stack when the handler gets here.
+        self.emit(OpCode::SetLocal(scope.value_slot));
+        self.emit(OpCode::Pop);
+        self.emit(OpCode::True);
+        self.emit(OpCode::SetLocal(scope.kind_slot));
+        self.emit(OpCode::Pop);
+        for exit in exits.into_iter().chain(scope.exit_jumps) {
+            self.patch_jump(exit);
+        }
+
+        self.consume(TokenType::Finally, "Expect 'finally'.")?;
+        self.consume(TokenType::LeftBrace, "Expect '{' after 'finally'.")?;
+        self.begin_scope();
+        let result = self.block();
+        self.end_scope();
+        result?;
+
+        // carry on the way the finally block was entered.
+        for (code, exit) in scope.loop_exits.into_iter().enumerate() {
+            self.emit(OpCode::GetLocal(scope.kind_slot));
+            self.emit_constant(Value::number(code as f64));
+            self.emit(OpCode::Equal);
+            let other = self.emit_jump(OpCode::JumpIfFalse(0));
+            self.emit(OpCode::Pop);
+            self.emit_exit(exit);
+            self.patch_jump(other);
+            self.emit(OpCode::Pop);
+        }
+        self.emit(OpCode::GetLocal(scope.kind_slot));
+        let not_thrown = self.emit_jump(OpCode::JumpIfFalse(0));
+        self.emit(OpCode::Pop);
+        self.emit(OpCode::GetLocal(scope.value_slot));
+        self.emit(OpCode::Throw);
+        self.patch_jump(not_thrown);
+        self.emit(OpCode::Nil);
+        self.emit(OpCode::Equal);
+        let returning = self.emit_jump(OpCode::JumpIfFalse(0));
+        self.emit(OpCode::Pop);
+        let done = self.emit_jump(OpCode::Jump(0));
+        self.patch_jump(returning);
+        self.emit(OpCode::Pop);
+        self.emit(OpCode::GetLocal(scope.value_slot));
+        self.emit_exit(Exit::Return);
+        self.patch_jump(done);
+
+        Ok(())
+    }
+
+    // a handler covering no code could never catch anything, so it is left out.
+    fn add_handler(&mut self, handler: Handler) {
+        if handler.start < handler.end {
+            self.compiler.function.chunk.handlers.push(handler);
+        }
+    }
+
+    // has_finally looks ahead past the `try` block and the `catch` block, if any,
+    // for a `finally` block.
+    fn has_finally(&self) -> bool {
+        let mut pos = self.skip_block(self.token_pos);
+        let typ = |pos: usize| self.tokens.get(pos).map(|token| token.typ);
+        if typ(pos) == Some(TokenType::Catch) {
+            pos += 1;
+            while !matches!(
+                typ(pos),
+                Some(TokenType::LeftBrace) | Some(TokenType::Eof) | None
+            ) {
+                pos += 1;
+            }
+            pos = self.skip_block(pos);
+        }
+        typ(pos) == Some(TokenType::Finally)
+    }
+
+    // the position after the block starting at `pos`, if there is one.
+    fn skip_block(&self, mut pos: usize) -> usize {
+        let mut depth = 0;
+        while let Some(token) = self.tokens.get(pos) {
+            match token.typ {
+                TokenType::LeftBrace => depth += 1,
+                TokenType::RightBrace => depth -= 1,
+                TokenType::Eof => return pos,
+                _ if depth == 0 => return pos,
+                _ => {}
+            }
+            pos += 1;
+            if depth == 0 {
+                break;
+            }
+        }
+        pos
+    }
+
+    fn while_statement(&mut self) -> Result<(), CompileError> {
+        let start_pos = self.compiler.function.chunk.code.len();
         self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
         self.expression()?;
         self.consume(
@@ -428,22 +1178,49 @@ impl<'a> Parser<'a> {
         );
 
         self.emit(OpCode::Pop);
-        self.statement()?;
+        let break_jumps = self.loop_body(start_pos)?;
 
         // back immediately to a start position
         self.emit_loop(start_pos);
 
         self.patch_jump(exit_pos);
         self.emit(OpCode::Pop);
+        for jump in break_jumps {
+            self.patch_jump(jump);
+        }
 
         Ok(())
     }
 
+    // loop_body compiles the body of a loop which `continue` goes back to `start` from,
+    // and returns the jumps `break` left to patch to where the loop ends.
+    fn loop_body(&mut self, start: usize) -> Result<Vec<usize>, CompileError> {
+        self.compiler.loops.push(Loop {
+            start,
+            depth: self.compiler.locals.len(),
+            finally_depth: self.compiler.finally_scopes.len(),
+            break_jumps: Vec::new(),
+        });
+        let result = self.statement();
+        let body = self
+            .compiler
+            .loops
+            .pop()
+            .expect("Expect the loop being compiled");
+        result.map(|_| body.break_jumps)
+    }
+
     /*
     forStmt -> "for" "(" ( varDecl | exprStmt | ";" ) expression? ";" expression? ")" statement ;
      */
-    fn for_statement(&mut self) -> Result<(), String> {
+    fn for_statement(&mut self) -> Result<(), CompileError> {
         self.begin_scope();
+        let result = self.for_clauses();
+        self.end_scope();
+        result
+    }
+
+    fn for_clauses(&mut self) -> Result<(), CompileError> {
         self.consume(TokenType::LeftParen, "Expect '(' after 'for' .")?;
 
         // initializer clause
@@ -455,7 +1232,7 @@ impl<'a> Parser<'a> {
             self.expression_statement()?;
         }
 
-        let cond_pos = self.compiler.function.chunk.instructions.len() - 1;
+        let cond_pos = self.compiler.function.chunk.code.len();
 
         // condition expression
         let maybe_exit_pos = match self.advance_if_matched(TokenType::SemiColon) {
@@ -484,7 +1261,7 @@ impl<'a> Parser<'a> {
                     /* set a placeholder for now, patch it later. */
                     OpCode::Jump(0),
                 );
-                let increment_pos = self.compiler.function.chunk.instructions.len() - 1;
+                let increment_pos = self.compiler.function.chunk.code.len();
 
                 self.expression()?;
                 self.emit(OpCode::Pop);
@@ -498,26 +1275,33 @@ impl<'a> Parser<'a> {
         };
 
         // body statement
-        self.statement()?;
+        let break_jumps = self.loop_body(back_pos)?;
         self.emit_loop(back_pos);
 
-        maybe_exit_pos.map(|exit_pos| {
+        if let Some(exit_pos) = maybe_exit_pos {
             self.patch_jump(exit_pos);
             self.emit(OpCode::Pop);
-        });
-
-        self.end_scope();
+        }
+        for jump in break_jumps {
+            self.patch_jump(jump);
+        }
 
         Ok(())
     }
 
     // back to a start position
     fn emit_loop(&mut self, start_pos: usize) {
-        let offset = self.compiler.function.chunk.instructions.len() - start_pos;
+        // the offset counts from the end of the loop instruction itself.
+        let offset = self.compiler.function.chunk.code.len() + JUMP_LEN - start_pos;
+        if offset > MAX_SHORT_OPERAND {
+            let err = self.error_at_previous("Loop body too large.");
+            self.errors.push(err);
+            return;
+        }
         self.emit(OpCode::Loop(offset));
     }
 
-    fn expression_statement(&mut self) -> Result<(), String> {
+    fn expression_statement(&mut self) -> Result<(), CompileError> {
         self.expression()?;
         self.consume(
             TokenType::SemiColon,
@@ -528,9 +1312,9 @@ impl<'a> Parser<'a> {
         Ok(())
     }
 
-    fn block(&mut self) -> Result<(), String> {
+    fn block(&mut self) -> Result<(), CompileError> {
         while self.current().typ != TokenType::RightBrace && self.current().typ != TokenType::Eof {
-            self.declaration()?;
+            self.declaration();
         }
 
         self.consume(TokenType::RightBrace, "Expect '}' after block.")
@@ -540,39 +1324,76 @@ impl<'a> Parser<'a> {
         self.compiler.scope_depth += 1;
     }
 
+    // discard_locals emits the instructions discarding the locals above `depth`,
+    // for jumping out of their scopes while they are still being compiled.
+    fn discard_locals(&mut self, depth: usize) {
+        for i in (depth..self.compiler.locals.len()).rev() {
+            if self.compiler.locals[i].is_captured {
+                self.emit(OpCode::CloseUpvalue);
+            } else {
+                self.emit(OpCode::Pop);
+            }
+        }
+    }
+
     fn end_scope(&mut self) {
         self.compiler.scope_depth -= 1;
-        while self.compiler.locals.len() > 0
-            && self.compiler.locals.last().unwrap().depth > self.compiler.scope_depth
-        {
+        while let Some(local) = self.compiler.locals.last() {
+            if !matches!(local.depth, Some(depth) if depth > self.compiler.scope_depth) {
+                break;
+            }
             // discard local variables.
+            // captured ones are moved onto the heap so that closures can outlive them.
+            let is_captured = local.is_captured;
+            self.unname_local(self.compiler.locals.len() - 1);
+            if is_captured {
+                self.emit(OpCode::CloseUpvalue);
+            } else {
+                self.emit(OpCode::Pop);
+            }
             self.compiler.locals.pop();
-            self.emit(OpCode::Pop);
         }
     }
 
     // consume is similar to advance() in that it reads the next token.
     // but it also validates that the token has an expected type.
     // if not, it reports an error.
-    fn consume(&mut self, typ: TokenType, msg: &str) -> Result<(), String> {
+    fn consume(&mut self, typ: TokenType, msg: &str) -> Result<(), CompileError> {
         if self.current().typ == typ {
             self.advance();
             return Ok(());
         }
 
-        Err(format!("[Line {}] Error: {}", self.current().line, msg))
+        Err(self.error_at_current(msg))
     }
 
     fn end_compiler(&mut self) {
         self.emit_return();
-        let name = match self.compiler.func_type {
-            FunctionType::Script => "code",
-            FunctionType::Function => self.allocator.deref(&self.compiler.function.name),
-        };
-        self.compiler.function.chunk.disassemble(name);
+        // the locals of the function's own scope are in scope until its very end.
+        for slot in 0..self.compiler.locals.len() {
+            self.unname_local(slot);
+        }
+        // the code is thrown away anyway when there are errors.
+        if self.optimization == OptimizationLevel::Full && self.errors.is_empty() {
+            optimizer::optimize(&mut self.compiler.function.chunk);
+        }
+        #[cfg(feature = "debug_print_code")]
+        {
+            use crate::chunk::Debug;
+            let name = match self.compiler.func_type {
+                FunctionType::Script | FunctionType::Module => "code",
+                _ => self.allocator.deref(&self.compiler.function.name),
+            };
+            self.compiler.function.chunk.disassemble(name);
+        }
     }
 
     fn make_constant(&mut self, v: Value) -> usize {
+        if self.compiler.function.chunk.values.len() >= MAX_CONSTANTS {
+            let err = self.error_at_previous("Too many constants in one chunk.");
+            self.errors.push(err);
+            return 0;
+        }
         self.compiler.function.chunk.add_constant(v)
     }
 
@@ -582,10 +1403,24 @@ impl<'a> Parser<'a> {
     }
 
     fn emit_return(&mut self) {
-        self.emit(OpCode::Nil);
+        self.emit_return_value();
         self.emit(OpCode::Return);
     }
 
+    // the value returned without one being given.
+    fn emit_return_value(&mut self) {
+        // an initializer always returns the instance being initialized,
+        // and a module's script the module, which the VM puts in its first slot.
+        if matches!(
+            self.compiler.func_type,
+            FunctionType::Initializer | FunctionType::Module
+        ) {
+            self.emit(OpCode::GetLocal(0));
+        } else {
+            self.emit(OpCode::Nil);
+        }
+    }
+
     fn emit(&mut self, op: OpCode) {
         self.compiler
             .function
@@ -594,42 +1429,39 @@ impl<'a> Parser<'a> {
     }
 
     fn emit_jump(&mut self, op: OpCode) -> usize {
+        let pos = self.compiler.function.chunk.code.len();
         self.emit(op);
-        self.compiler.function.chunk.instructions.len() - 1
+        pos
     }
 
-    // patches the instruction at 'pos' to replace the offset value for the jump
+    // patches the jump instruction at 'pos' to land on the end of the code.
     fn patch_jump(&mut self, pos: usize) {
-        let offset = self.compiler.function.chunk.instructions.len() - 1 - pos;
-        match self.compiler.function.chunk.instructions.get(pos).unwrap() {
-            OpCode::JumpIfFalse(_) => {
-                self.compiler.function.chunk.instructions[pos] = OpCode::JumpIfFalse(offset);
-            }
-            OpCode::Jump(_) => {
-                self.compiler.function.chunk.instructions[pos] = OpCode::Jump(offset);
-            }
-            _ => unreachable!(),
+        let offset = self.compiler.function.chunk.code.len() - pos - JUMP_LEN;
+        if offset > MAX_SHORT_OPERAND {
+            let err = self.error_at_previous("Too much code to jump over.");
+            self.errors.push(err);
+            return;
         }
-        return;
+        self.compiler.function.chunk.patch_jump(pos, offset);
     }
 
     // number literals
     // e.g. 123
-    fn number(&mut self, _: bool) -> Result<(), String> {
+    fn number(&mut self, _: bool) -> Result<(), CompileError> {
         let v: f64 = self
             .previous()
             .source
             .parse()
             .expect("Compiler tried to parse to number");
 
-        self.emit_constant(Value::Number(v));
+        self.emit_constant(Value::number(v));
 
         Ok(())
     }
 
     // parentheses for grouping
     // e.g. (123)
-    fn grouping(&mut self, _: bool) -> Result<(), String> {
+    fn grouping(&mut self, _: bool) -> Result<(), CompileError> {
         // we assume the initial '(' has already been consumed.
         // so we recursively call back into expression() between the parentheses.
         self.expression()?;
@@ -637,9 +1469,16 @@ impl<'a> Parser<'a> {
         self.consume(TokenType::RightParen, "Expect ')' after expression.")
     }
 
+    // anonymous functions, which are named after the line they start on
+    // e.g. fun (a, b) { return a + b; }
+    fn lambda(&mut self, _: bool) -> Result<(), CompileError> {
+        let name = format!("anonymous@{}", self.previous().line);
+        self.function(&name, FunctionType::Function)
+    }
+
     // unary negation
     // e.g. -123
-    fn unary(&mut self, _: bool) -> Result<(), String> {
+    fn unary(&mut self, _: bool) -> Result<(), CompileError> {
         // remember the operator.
         let typ = self.previous().typ;
 
@@ -657,7 +1496,7 @@ impl<'a> Parser<'a> {
     }
 
     // e.g. 123 + 456
-    fn binary(&mut self, _: bool) -> Result<(), String> {
+    fn binary(&mut self, _: bool) -> Result<(), CompileError> {
         // remember the operator.
         let typ = self.previous().typ;
 
@@ -693,7 +1532,7 @@ impl<'a> Parser<'a> {
     }
 
     // e.g. true
-    fn literal(&mut self, _: bool) -> Result<(), String> {
+    fn literal(&mut self, _: bool) -> Result<(), CompileError> {
         let typ = self.previous().typ;
         match typ {
             TokenType::True => self.emit(OpCode::True),
@@ -705,16 +1544,16 @@ impl<'a> Parser<'a> {
         Ok(())
     }
 
-    fn string(&mut self, _: bool) -> Result<(), String> {
+    fn string(&mut self, _: bool) -> Result<(), CompileError> {
         // trim quotes
         let s = &self.previous().source[1..=self.previous().source.len() - 2];
-        let s = self.allocator.new_string(s.to_string());
-        self.emit_constant(Value::String(s));
+        let s = self.intern(s.to_string());
+        self.emit_constant(Value::from(s));
 
         Ok(())
     }
 
-    fn and(&mut self, _: bool) -> Result<(), String> {
+    fn and(&mut self, _: bool) -> Result<(), CompileError> {
         let pos = self.emit_jump(
             /* set a placeholder for now, and patch it later. */
             OpCode::JumpIfFalse(0),
@@ -730,7 +1569,7 @@ impl<'a> Parser<'a> {
         Ok(())
     }
 
-    fn or(&mut self, _: bool) -> Result<(), String> {
+    fn or(&mut self, _: bool) -> Result<(), CompileError> {
         let else_pos = self.emit_jump(
             /* set a placeholder for now, and patch it later. */
             OpCode::JumpIfFalse(0),
@@ -750,11 +1589,21 @@ impl<'a> Parser<'a> {
         Ok(())
     }
 
-    fn call(&mut self, _: bool) -> Result<(), String> {
+    fn call(&mut self, _: bool) -> Result<(), CompileError> {
+        let arg_count = self.argument_list()?;
+        self.emit(OpCode::Call(arg_count));
+
+        Ok(())
+    }
+
+    fn argument_list(&mut self) -> Result<usize, CompileError> {
         let mut arg_count = 0;
         if !self.advance_if_matched(TokenType::RightParen) {
             loop {
                 self.expression()?;
+                if arg_count == MAX_BYTE_OPERAND {
+                    return Err(self.error_at_previous("Can't have more than 255 arguments."));
+                }
                 arg_count += 1;
                 if !self.advance_if_matched(TokenType::Comma) {
                     break;
@@ -763,23 +1612,154 @@ impl<'a> Parser<'a> {
             self.consume(TokenType::RightParen, "Expect ')' after arguments.")?;
         }
 
-        self.emit(OpCode::Call(arg_count));
+        Ok(arg_count)
+    }
+
+    // e.g. foo.bar, foo.bar = 1, foo.bar(1)
+    fn dot(&mut self, can_assign: bool) -> Result<(), CompileError> {
+        self.consume(TokenType::Identifier, "Expect property name after '.'.")?;
+        let name = self.identifier_constant(self.previous().source);
+
+        if can_assign && self.advance_if_matched(TokenType::Equal) {
+            self.expression()?;
+            self.emit(OpCode::SetProperty(name));
+        } else if self.advance_if_matched(TokenType::LeftParen) {
+            // a method call is compiled into a single instruction
+            // without allocating a bound method in between.
+            let arg_count = self.argument_list()?;
+            self.emit(OpCode::Invoke(name, arg_count));
+        } else {
+            self.emit(OpCode::GetProperty(name));
+        }
 
         Ok(())
     }
 
-    fn variable(&mut self, can_assign: bool) -> Result<(), String> {
+    // e.g. [1, 2, 3]
+    fn list(&mut self, _: bool) -> Result<(), CompileError> {
+        let mut item_count = 0;
+        if !self.advance_if_matched(TokenType::RightBracket) {
+            loop {
+                self.expression()?;
+                if item_count == MAX_SHORT_OPERAND {
+                    return Err(self.error_at_previous("Too many items in list literal."));
+                }
+                item_count += 1;
+                if !self.advance_if_matched(TokenType::Comma) {
+                    break;
+                }
+            }
+            self.consume(TokenType::RightBracket, "Expect ']' after list items.")?;
+        }
+        self.emit(OpCode::BuildList(item_count));
+
+        Ok(())
+    }
+
+    // e.g. {"a": 1, "b": 2}
+    // a statement starting with '{' is still a block, so this only applies within expressions.
+    fn map(&mut self, _: bool) -> Result<(), CompileError> {
+        let mut entry_count = 0;
+        if !self.advance_if_matched(TokenType::RightBrace) {
+            loop {
+                self.expression()?;
+                self.consume(TokenType::Colon, "Expect ':' after map key.")?;
+                self.expression()?;
+                if entry_count == MAX_SHORT_OPERAND {
+                    return Err(self.error_at_previous("Too many entries in map literal."));
+                }
+                entry_count += 1;
+                if !self.advance_if_matched(TokenType::Comma) {
+                    break;
+                }
+            }
+            self.consume(TokenType::RightBrace, "Expect '}' after map entries.")?;
+        }
+        self.emit(OpCode::BuildMap(entry_count));
+
+        Ok(())
+    }
+
+    // e.g. xs[0], xs[0] = 1
+    fn index(&mut self, can_assign: bool) -> Result<(), CompileError> {
+        self.expression()?;
+        self.consume(TokenType::RightBracket, "Expect ']' after index.")?;
+
+        if can_assign && self.advance_if_matched(TokenType::Equal) {
+            self.expression()?;
+            self.emit(OpCode::SetIndex);
+        } else {
+            self.emit(OpCode::GetIndex);
+        }
+
+        Ok(())
+    }
+
+    fn this(&mut self, _: bool) -> Result<(), CompileError> {
+        if self.class_compilers.is_empty() {
+            return Err(self.error_at_previous("Can't use 'this' outside of a class."));
+        }
+
+        self.named_variable("this", false)
+    }
+
+    // e.g. super.foo, super.foo(1)
+    fn super_(&mut self, _: bool) -> Result<(), CompileError> {
+        match self.class_compilers.last() {
+            None => return Err(self.error_at_previous("Can't use 'super' outside of a class.")),
+            Some(class_compiler) if !class_compiler.has_superclass => {
+                return Err(
+                    self.error_at_previous("Can't use 'super' in a class with no superclass.")
+                );
+            }
+            _ => {}
+        }
+
+        self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
+        self.consume(TokenType::Identifier, "Expect superclass method name.")?;
+        let name = self.identifier_constant(self.previous().source);
+
+        self.named_variable("this", false)?;
+        if self.advance_if_matched(TokenType::LeftParen) {
+            let arg_count = self.argument_list()?;
+            self.named_variable("super", false)?;
+            self.emit(OpCode::SuperInvoke(name, arg_count));
+        } else {
+            self.named_variable("super", false)?;
+            self.emit(OpCode::GetSuper(name));
+        }
+
+        Ok(())
+    }
+
+    fn variable(&mut self, can_assign: bool) -> Result<(), CompileError> {
         let name = self.previous().source;
+        self.named_variable(name, can_assign)
+    }
+
+    fn named_variable(&mut self, name: &'a str, can_assign: bool) -> Result<(), CompileError> {
+        let local = self
+            .compiler
+            .resolve_local(name)
+            .map_err(|msg| self.error_at_previous(&msg))?;
+        let upvalue = match local {
+            Some(_) => None,
+            None => self
+                .compiler
+                .resolve_upvalue(name)
+                .map_err(|msg| self.error_at_previous(&msg))?,
+        };
 
-        let (set_op, get_op) = if let Some(idx) = self.resolve_local(&self.compiler, name)? {
+        let (set_op, get_op) = if let Some(idx) = local {
             // in current scope
             (OpCode::SetLocal(idx), OpCode::GetLocal(idx))
-        // } else if let Some(idx) = self.resolve_upvalue(name) {
-        // (OpCode::SetLocal(idx), OpCode::GetLocal(idx))
+        } else if let Some(idx) = upvalue {
+            // captured from an enclosing function
+            (OpCode::SetUpvalue(idx), OpCode::GetUpvalue(idx))
         } else {
             // global
-            let idx = self.identifier_constant(name);
-            (OpCode::SetGlobal(idx), OpCode::GetGlobal(idx))
+            let slot = self.global_slot(name);
+            (OpCode::SetGlobal(slot), OpCode::GetGlobal(slot))
         };
 
         if can_assign && self.advance_if_matched(TokenType::Equal) {
@@ -792,41 +1772,17 @@ impl<'a> Parser<'a> {
         Ok(())
     }
 
-    fn resolve_local(
-        &self,
-        compiler: &Box<Compiler<'a>>,
-        name: &'a str,
-    ) -> Result<Option<usize>, String> {
-        for (i, local) in compiler.locals.iter().enumerate().rev() {
-            if local.name == name {
-                if local.depth == 0 {
-                    return Err("Can't read local variable in its own initializer.".to_string());
-                }
-                return Ok(Some(i));
-            }
-        }
-        Ok(None)
-    }
-
-    fn resolve_upvalue(&mut self, name: &'a str) -> Result<Option<usize>, String> {
-        match &self.compiler.enclosing {
-            Some(enclosing) => self.resolve_local(enclosing, name),
-            None => Ok(None),
-        }
-    }
-
-    fn expression(&mut self) -> Result<(), String> {
+    fn expression(&mut self) -> Result<(), CompileError> {
         self.parse_precedence(Precedence::Assignment)
     }
 
     fn get_rule(&self, typ: &TokenType) -> &ParseRule<'a> {
-        &self
-            .parse_rules
+        self.parse_rules
             .get(typ)
-            .expect(format!("no entry found for key: {}", typ).as_str())
+            .unwrap_or_else(|| panic!("no entry found for key: {}", typ))
     }
 
-    fn parse_precedence(&mut self, precedence: Precedence) -> Result<(), String> {
+    fn parse_precedence(&mut self, precedence: Precedence) -> Result<(), CompileError> {
         self.advance();
         let prefix_rule = self.get_rule(&self.previous().typ).prefix;
 
@@ -846,18 +1802,12 @@ impl<'a> Parser<'a> {
                 }
 
                 if can_assign && self.advance_if_matched(TokenType::Equal) {
-                    return Err(format!(
-                        "[line {}] Error: Invalid assignment target.",
-                        self.previous().line
-                    ));
+                    return Err(self.error_at_previous("Invalid assignment target."));
                 }
 
                 Ok(())
             }
-            None => Err(format!(
-                "[line {}] Error: Expect expression",
-                self.previous().line
-            )),
+            None => Err(self.error_at_previous("Expect expression.")),
         }
     }
 }
@@ -0,0 +1,64 @@
+use crate::vm::VM;
+use std::collections::BTreeSet;
+
+// Debugger is told whenever a script stops at a breakpoint or after a step,
+// and decides how it carries on. see `VM::set_debugger`.
+pub trait Debugger {
+    // stopped is called before the first instruction of the line the script stopped at.
+    fn stopped(&mut self, stop: &mut Stop) -> Resume;
+}
+
+// any closure taking a stop can be used as a debugger, e.g. to script one.
+impl<F: FnMut(&mut Stop) -> Resume> Debugger for F {
+    fn stopped(&mut self, stop: &mut Stop) -> Resume {
+        self(stop)
+    }
+}
+
+// Breakpoint is a line of the main script or of a module, by its path.
+// the main script's path is the one given to `VM::set_script_path`, and a module's
+// is resolved against the script importing it, as in stack traces.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Breakpoint {
+    pub path: String,
+    pub line: usize,
+}
+
+impl Breakpoint {
+    pub fn new(path: &str, line: usize) -> Self {
+        Self {
+            path: path.to_owned(),
+            line,
+        }
+    }
+}
+
+// Stop is a script stopped at a line of the file at `path`. the VM can be looked into
+// but not run, while the breakpoints can be changed before it carries on.
+pub struct Stop<'v> {
+    pub reason: StopReason,
+    pub path: &'v str,
+    pub line: usize,
+    pub vm: &'v VM,
+    pub breakpoints: &'v mut BTreeSet<Breakpoint>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum StopReason {
+    Breakpoint,
+    // a step finished, or the VM was paused.
+    Step,
+}
+
+// Resume is how a stopped script carries on.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Resume {
+    // runs until the next breakpoint.
+    Continue,
+    // stops at the next line, inside a function which the line calls if there is one.
+    StepIn,
+    // stops at the next line of the same function, or of its caller once it returns.
+    StepOver,
+    // stops at the next line of the caller once the function returns.
+    StepOut,
+}
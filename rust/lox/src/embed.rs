@@ -0,0 +1,250 @@
+use crate::allocator::Allocator;
+use crate::value::{Value, ValueKind};
+use crate::vm::{Limit, RuntimeError, VM};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Error is what calling into a VM from Rust fails with.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    // no global variable of the name has been defined.
+    UndefinedVariable(String),
+    // the value isn't of the Rust type it was converted into.
+    WrongType {
+        expected: &'static str,
+        found: &'static str,
+    },
+    // the script raised an error, or threw a value which it didn't catch.
+    Runtime(RuntimeError),
+    // the call was abandoned as a limit was exceeded.
+    LimitExceeded(Limit),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UndefinedVariable(name) => write!(f, "Undefined variable '{}'.", name),
+            Error::WrongType { expected, found } => {
+                write!(f, "Expected {} but got {}.", expected, found)
+            }
+            Error::Runtime(e) => write!(f, "{}", e),
+            Error::LimitExceeded(limit) => write!(f, "{}", limit),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<RuntimeError> for Error {
+    fn from(e: RuntimeError) -> Self {
+        Error::Runtime(e)
+    }
+}
+
+// describes the type of a value for `Error::WrongType`.
+fn type_name(value: Value) -> &'static str {
+    match value.kind() {
+        ValueKind::Nil => "nil",
+        ValueKind::Bool(_) => "a boolean",
+        ValueKind::Number(_) => "a number",
+        ValueKind::String(_) => "a string",
+        ValueKind::Function(_)
+        | ValueKind::Closure(_)
+        | ValueKind::NativeFn(_)
+        | ValueKind::BoundMethod(_) => "a function",
+        ValueKind::Class(_) => "a class",
+        ValueKind::Instance(_) => "an instance",
+        ValueKind::List(_) => "a list",
+        ValueKind::Map(_) => "a map",
+        ValueKind::Module(_) => "a module",
+    }
+}
+
+// FromValue converts a value of a script into a Rust type.
+pub trait FromValue: Sized {
+    fn from_value(value: Value, vm: &VM) -> Result<Self, Error>;
+}
+
+// IntoValue converts a Rust type into a value which scripts can use,
+// allocating it on the heap of the VM if it needs to be.
+pub trait IntoValue {
+    fn into_value(self, vm: &mut VM) -> Value;
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value, _: &VM) -> Result<Self, Error> {
+        Ok(value)
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: Value, _: &VM) -> Result<Self, Error> {
+        match value.kind() {
+            ValueKind::Number(n) => Ok(n),
+            _ => Err(Error::WrongType {
+                expected: "a number",
+                found: type_name(value),
+            }),
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value, _: &VM) -> Result<Self, Error> {
+        match value.kind() {
+            ValueKind::Bool(b) => Ok(b),
+            _ => Err(Error::WrongType {
+                expected: "a boolean",
+                found: type_name(value),
+            }),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value, vm: &VM) -> Result<Self, Error> {
+        match value.kind() {
+            ValueKind::String(s) => Ok(vm.allocator.deref(&s).clone()),
+            _ => Err(Error::WrongType {
+                expected: "a string",
+                found: type_name(value),
+            }),
+        }
+    }
+}
+
+// nil is None, and anything else has to convert into the inner type.
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value, vm: &VM) -> Result<Self, Error> {
+        match value.kind() {
+            ValueKind::Nil => Ok(None),
+            _ => T::from_value(value, vm).map(Some),
+        }
+    }
+}
+
+impl FromValue for Handle {
+    fn from_value(value: Value, vm: &VM) -> Result<Self, Error> {
+        Ok(vm.root(value))
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self, _: &mut VM) -> Value {
+        self
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self, _: &mut VM) -> Value {
+        Value::number(self)
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self, _: &mut VM) -> Value {
+        Value::bool(self)
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self, vm: &mut VM) -> Value {
+        Value::from(vm.intern(self))
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self, vm: &mut VM) -> Value {
+        Value::from(vm.intern(self.to_owned()))
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self, vm: &mut VM) -> Value {
+        match self {
+            Some(value) => value.into_value(vm),
+            None => Value::NIL,
+        }
+    }
+}
+
+impl IntoValue for &Handle {
+    fn into_value(self, _: &mut VM) -> Value {
+        self.value
+    }
+}
+
+// Handles are the values held by Rust through handles, which the VM marks as roots.
+#[derive(Default)]
+pub(crate) struct Handles {
+    values: Vec<Option<Value>>,
+    free_slots: Vec<usize>,
+}
+
+impl Handles {
+    fn add(&mut self, value: Value) -> usize {
+        match self.free_slots.pop() {
+            Some(slot) => {
+                self.values[slot] = Some(value);
+                slot
+            }
+            None => {
+                self.values.push(Some(value));
+                self.values.len() - 1
+            }
+        }
+    }
+
+    fn remove(&mut self, slot: usize) {
+        self.values[slot] = None;
+        self.free_slots.push(slot);
+    }
+
+    pub(crate) fn mark(&self, allocator: &mut Allocator) {
+        for &value in self.values.iter().flatten() {
+            allocator.mark_value(value);
+        }
+    }
+}
+
+// Handle keeps a value alive for as long as Rust holds onto it.
+// a bare `Value` which refers to the heap may be freed by the next collection,
+// as the VM doesn't know about it, e.g. a string passed to `VM::call` along with another.
+pub struct Handle {
+    value: Value,
+    slot: usize,
+    handles: Rc<RefCell<Handles>>,
+}
+
+impl Handle {
+    pub(crate) fn new(value: Value, handles: &Rc<RefCell<Handles>>) -> Self {
+        let slot = handles.borrow_mut().add(value);
+        Self {
+            value,
+            slot,
+            handles: Rc::clone(handles),
+        }
+    }
+
+    pub fn value(&self) -> Value {
+        self.value
+    }
+}
+
+impl Clone for Handle {
+    fn clone(&self) -> Self {
+        Handle::new(self.value, &self.handles)
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.handles.borrow_mut().remove(self.slot);
+    }
+}
+
+impl std::fmt::Debug for Handle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Handle({:?})", self.value)
+    }
+}
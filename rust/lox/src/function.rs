@@ -1,29 +1,73 @@
 use crate::chunk::Chunk;
+use crate::module::ScriptModule;
 use crate::{Allocator, Reference, Value};
+use std::rc::Rc;
 
 #[derive(Eq, PartialEq)]
 pub enum FunctionType {
     Function,
+    Initializer,
+    Method,
     Script,
+    // the script of an imported module, which returns the module itself.
+    Module,
 }
 
-#[derive(Copy, Clone)]
-pub struct NativeFn(pub fn(&Allocator, &[Value]) -> Value);
+// NativeFnBody is the host code behind a native function.
+// it gets the arguments it was called with, and may allocate its result on the heap.
+// an error is raised as a runtime error in the calling script.
+pub type NativeFnBody = dyn Fn(&mut Allocator, &[Value]) -> Result<Value, String>;
+
+// NativeFn is a function implemented in Rust which scripts can call like any other.
+// the body is shared so that the VM can keep calling it while handing out the allocator;
+// state captured by the closure has to be mutated through a `Cell` or `RefCell`.
+pub struct NativeFn {
+    pub name: Reference<String>,
+    pub arity: usize,
+    pub body: Rc<NativeFnBody>,
+}
 
 impl std::fmt::Debug for NativeFn {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "<fn>")
+        write!(f, "<native fn {}>", self.name)
     }
 }
 
-impl PartialEq for NativeFn {
-    fn eq(&self, _other: &Self) -> bool {
-        return false;
+impl NativeFn {
+    pub fn new<F>(name: Reference<String>, arity: usize, body: F) -> Self
+    where
+        F: Fn(&mut Allocator, &[Value]) -> Result<Value, String> + 'static,
+    {
+        Self {
+            name,
+            arity,
+            body: Rc::new(body),
+        }
+    }
+}
+
+// Upvalue is a variable captured by a closure.
+// while the variable still lives on the stack, `location` points to its slot.
+// once the variable goes out of scope, the value is moved into `closed`.
+pub struct Upvalue {
+    pub location: usize,
+    pub closed: Option<Value>,
+}
+
+impl Upvalue {
+    pub fn new(location: usize) -> Self {
+        Self {
+            location,
+            closed: None,
+        }
     }
 }
 
 pub struct Closure {
     pub func_id: Reference<Function>,
+    pub upvalues: Vec<Reference<Upvalue>>,
+    // the module whose globals the function uses, or None for the main script's.
+    pub module: Option<Reference<ScriptModule>>,
 }
 
 impl std::fmt::Debug for Closure {
@@ -34,13 +78,28 @@ impl std::fmt::Debug for Closure {
 
 impl Closure {
     pub fn new(func_id: Reference<Function>) -> Self {
-        Self { func_id }
+        Self {
+            func_id,
+            upvalues: Vec::new(),
+            module: None,
+        }
     }
 }
 
+// FunctionUpvalue tells the VM where a closure captures each upvalue from:
+// a local slot of the enclosing function, or an upvalue of the enclosing closure.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FunctionUpvalue {
+    pub index: usize,
+    pub is_local: bool,
+}
+
 pub struct Function {
     pub chunk: Chunk,
     pub name: Reference<String>,
+    // the number of parameters it takes.
+    pub arity: usize,
+    pub upvalues: Vec<FunctionUpvalue>,
 }
 
 impl std::fmt::Debug for Function {
@@ -54,6 +113,8 @@ impl Function {
         Self {
             chunk: Chunk::new(),
             name,
+            arity: 0,
+            upvalues: Vec::new(),
         }
     }
 }
@@ -0,0 +1,68 @@
+use crate::{Allocator, Reference, Value};
+use std::collections::HashMap;
+
+// Globals are the global variables of a VM.
+// the compiler resolves each name to a slot once, so that the VM reads and writes
+// globals by index instead of hashing their names. every script compiled in the same VM
+// shares the slots, and a slot is undefined until a declaration of its name runs.
+#[derive(Default)]
+pub struct Globals {
+    slots: HashMap<Reference<String>, usize>,
+    names: Vec<Reference<String>>,
+    values: Vec<Option<Value>>,
+}
+
+impl Globals {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // slot returns the slot of the name, giving it a new one the first time.
+    pub(crate) fn slot(&mut self, name: Reference<String>) -> usize {
+        if let Some(&slot) = self.slots.get(&name) {
+            return slot;
+        }
+        self.names.push(name);
+        self.values.push(None);
+        self.slots.insert(name, self.names.len() - 1);
+        self.names.len() - 1
+    }
+
+    // the number of slots given out so far.
+    pub(crate) fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub(crate) fn name(&self, slot: usize) -> Reference<String> {
+        self.names[slot]
+    }
+
+    pub(crate) fn get_slot(&self, slot: usize) -> Option<Value> {
+        self.values[slot]
+    }
+
+    pub(crate) fn set_slot(&mut self, slot: usize, value: Value) {
+        self.values[slot] = Some(value);
+    }
+
+    // get returns the value of a global variable which has been defined.
+    pub fn get(&self, name: &Reference<String>) -> Option<&Value> {
+        let &slot = self.slots.get(name)?;
+        self.values[slot].as_ref()
+    }
+
+    // insert defines a global variable, or replaces its value.
+    pub fn insert(&mut self, name: Reference<String>, value: Value) {
+        let slot = self.slot(name);
+        self.set_slot(slot, value);
+    }
+
+    pub(crate) fn mark(&self, allocator: &mut Allocator) {
+        for &name in &self.names {
+            allocator.mark_object(name);
+        }
+        for &value in self.values.iter().flatten() {
+            allocator.mark_value(value);
+        }
+    }
+}
@@ -1,17 +1,38 @@
 mod allocator;
+mod bytecode;
 mod chunk;
+mod class;
 mod compiler;
+mod debugger;
+mod embed;
 mod function;
+mod globals;
+mod list;
+mod map;
+mod module;
+mod optimizer;
+mod output;
 mod scanner;
+mod stdlib;
 mod token;
 mod value;
 mod vm;
 
 pub use allocator::{Allocator, Reference};
-pub use chunk::{Chunk, OpCode};
-pub use compiler::Parser;
-pub use function::Function;
+pub use bytecode::{deserialize, is_bytecode, serialize, LoadError, FORMAT_VERSION};
+pub use chunk::{Chunk, Debug, Handler, LineStart, LocalName, OpCode};
+pub use compiler::{CompileError, Parser};
+pub use debugger::{Breakpoint, Debugger, Resume, Stop, StopReason};
+pub use embed::{Error, FromValue, Handle, IntoValue};
+pub use function::{Function, NativeFn, NativeFnBody};
+pub use globals::Globals;
+pub use list::List;
+pub use map::Map;
+pub use module::{FileResolver, ModuleResolver, ScriptModule};
+pub use optimizer::OptimizationLevel;
+pub use output::OutputBuffer;
 pub use scanner::Scanner;
+pub use stdlib::Module;
 pub use token::TokenType;
-pub use value::Value;
-pub use vm::{InterpretResult, VM};
+pub use value::{Value, ValueKind};
+pub use vm::{InterpretResult, Limit, Limits, RuntimeError, TraceFrame, VM};
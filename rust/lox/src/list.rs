@@ -0,0 +1,37 @@
+use crate::{Value, ValueKind};
+
+// List is a growable sequence of values, created by a `[a, b, c]` literal.
+pub struct List {
+    pub items: Vec<Value>,
+}
+
+impl std::fmt::Debug for List {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "<list len={}>", self.items.len())
+    }
+}
+
+impl List {
+    pub fn new(items: Vec<Value>) -> Self {
+        Self { items }
+    }
+
+    // index checks that the value is a valid position in the list.
+    pub fn index(&self, index: Value) -> Result<usize, String> {
+        let n = match index.kind() {
+            ValueKind::Number(n) if n.fract() == 0.0 => n,
+            _ => return Err("List index must be an integer.".to_owned()),
+        };
+        if n < 0.0 {
+            return Err(format!("List index {} is negative.", n));
+        }
+        if n >= self.items.len() as f64 {
+            return Err(format!(
+                "List index {} is out of range for a list of length {}.",
+                n,
+                self.items.len()
+            ));
+        }
+        Ok(n as usize)
+    }
+}
@@ -0,0 +1,310 @@
+use lox::{
+    is_bytecode, serialize, Breakpoint, CompileError, Debug, Debugger, Function, InterpretResult,
+    OptimizationLevel, Reference, Resume, Stop, StopReason, ValueKind, VM,
+};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::{env, fs, io, mem, process};
+
+// exit codes from sysexits.h, as used by clox.
+const EX_USAGE: i32 = 64;
+const EX_DATAERR: i32 = 65;
+const EX_SOFTWARE: i32 = 70;
+const EX_IOERR: i32 = 74;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    match args.len() {
+        1 => repl(),
+        2 => run_file(&args[1], false),
+        3 if args[1] == "--debug" => run_file(&args[2], true),
+        4 if args[1] == "--compile" => compile_file(&args[2], &args[3]),
+        _ => {
+            eprintln!(
+                "Usage: lox [path]\n       lox --debug <path>\n       lox --compile <path> <output>"
+            );
+            process::exit(EX_USAGE);
+        }
+    }
+}
+
+fn read_file(path: &str) -> Vec<u8> {
+    match fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            eprintln!("Could not read file \"{}\": {}.", path, e);
+            process::exit(EX_IOERR);
+        }
+    }
+}
+
+fn read_source(path: &str, contents: Vec<u8>) -> String {
+    match String::from_utf8(contents) {
+        Ok(source) => source,
+        Err(_) => {
+            eprintln!("Could not read file \"{}\": not valid UTF-8.", path);
+            process::exit(EX_DATAERR);
+        }
+    }
+}
+
+// run_file runs either a script or the bytecode written by `--compile`.
+// when debugging, it stops at the first line for the commands of `Prompt`.
+fn run_file(path: &str, debug: bool) {
+    let contents = read_file(path);
+    let mut vm = VM::new();
+    vm.set_script_path(path);
+    if debug {
+        vm.set_debugger(Prompt::default());
+        vm.pause();
+    }
+    let result = if is_bytecode(&contents) {
+        match vm.load(&contents) {
+            Ok(func_id) => vm.execute(func_id),
+            Err(e) => {
+                eprintln!("Could not load \"{}\": {}", path, e);
+                process::exit(EX_DATAERR);
+            }
+        }
+    } else {
+        vm.interpret(&read_source(path, contents))
+    };
+
+    match result {
+        InterpretResult::Ok => {}
+        InterpretResult::CompileError(errors) => {
+            report_compile_errors(&errors);
+            process::exit(EX_DATAERR);
+        }
+        InterpretResult::RuntimeError(e) => {
+            eprintln!("{}", e);
+            process::exit(EX_SOFTWARE);
+        }
+        InterpretResult::LimitExceeded(limit) => {
+            eprintln!("{}", limit);
+            process::exit(EX_SOFTWARE);
+        }
+    }
+}
+
+// compile_file writes the bytecode of a script, so that it can be run without compiling it again.
+// as it is compiled once ahead of time, it is worth optimizing.
+fn compile_file(path: &str, output: &str) {
+    let source = read_source(path, read_file(path));
+    let mut vm = VM::new();
+    vm.set_optimization_level(OptimizationLevel::Full);
+    let func_id = match vm.compile(&source) {
+        Ok(func_id) => func_id,
+        Err(errors) => {
+            report_compile_errors(&errors);
+            process::exit(EX_DATAERR);
+        }
+    };
+    if let Err(e) = fs::write(output, serialize(&vm.allocator, &vm.globals, func_id)) {
+        eprintln!("Could not write file \"{}\": {}.", output, e);
+        process::exit(EX_IOERR);
+    }
+}
+
+// repl interprets one line at a time on the same VM, so globals carry over between lines.
+// input which ends in the middle of a declaration (e.g. an unclosed block) is continued
+// on the next line, and an empty line gives up on it.
+// `:dis` shows the bytecode of the last input, and `:dis <source>` that of the given source.
+fn repl() {
+    let mut vm = VM::new();
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut source = String::new();
+    let mut last = String::new();
+
+    loop {
+        print!("{}", if source.is_empty() { "> " } else { "... " });
+        io::stdout().flush().expect("failed to flush stdout");
+
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => {
+                println!();
+                return;
+            }
+        };
+
+        if source.is_empty() {
+            let command = line.trim();
+            if command == ":dis" {
+                disassemble(&mut vm, &last);
+                continue;
+            }
+            if let Some(arg) = command.strip_prefix(":dis ") {
+                disassemble(&mut vm, arg);
+                continue;
+            }
+        }
+
+        let giving_up = !source.is_empty() && line.trim().is_empty();
+        source.push_str(&line);
+        source.push('\n');
+
+        match vm.interpret(&source) {
+            InterpretResult::Ok => {}
+            InterpretResult::CompileError(errors) => {
+                if !giving_up && is_incomplete(&errors) {
+                    continue;
+                }
+                report_compile_errors(&errors);
+            }
+            InterpretResult::RuntimeError(e) => eprintln!("{}", e),
+            InterpretResult::LimitExceeded(limit) => eprintln!("{}", limit),
+        }
+        last = mem::take(&mut source);
+    }
+}
+
+// the source is unfinished rather than wrong when the parser ran out of tokens.
+fn is_incomplete(errors: &[CompileError]) -> bool {
+    errors.iter().any(|e| e.lexeme.is_empty())
+}
+
+fn report_compile_errors(errors: &[CompileError]) {
+    for e in errors {
+        eprintln!("{}", e);
+    }
+}
+
+fn disassemble(vm: &mut VM, source: &str) {
+    match vm.compile(source) {
+        Ok(func_id) => disassemble_function(vm, func_id),
+        Err(errors) => report_compile_errors(&errors),
+    }
+}
+
+// disassembles the function and every function declared inside it.
+fn disassemble_function(vm: &VM, func_id: Reference<Function>) {
+    let function = vm.allocator.deref(&func_id);
+    let name: &String = vm.allocator.deref(&function.name);
+    function.chunk.disassemble(name);
+    for value in &function.chunk.values {
+        if let ValueKind::Function(id) = value.kind() {
+            disassemble_function(vm, id);
+        }
+    }
+}
+
+const DEBUG_HELP: &str = "\
+break <line>    stop at the line of the current file (b)
+break <path>:<line>
+                stop at the line of another file, by its path as shown at stops
+clear [<path>:]<line>
+                stop no longer at the line
+step            run to the next line, stepping into calls (s)
+next            run to the next line, stepping over calls (n)
+finish          run until the function returns (f)
+continue        run until the next breakpoint (c)
+locals          show the local variables of the function
+print <name>    show a local variable, or a global of the current file (p)
+backtrace       show the calls which led to the line (bt)
+quit            stop the script (q)
+lines are counted from 0, as in stack traces.";
+
+// Prompt is the debugger of `--debug`, which asks stdin what to do whenever the script stops.
+#[derive(Default)]
+struct Prompt {
+    // the lines of each file stopped in so far, by its path.
+    // bytecode has no source to show, though it still has lines and local names.
+    sources: HashMap<String, Vec<String>>,
+}
+
+impl Prompt {
+    fn source_line(&mut self, path: &str, line: usize) -> Option<&String> {
+        self.sources
+            .entry(path.to_owned())
+            .or_insert_with(|| match fs::read(path) {
+                Ok(contents) if !is_bytecode(&contents) => String::from_utf8_lossy(&contents)
+                    .lines()
+                    .map(str::to_owned)
+                    .collect(),
+                _ => vec![],
+            })
+            .get(line)
+    }
+}
+
+// parses the `[<path>:]<line>` of a breakpoint, which is in the current file without a path.
+fn parse_breakpoint(argument: &str, current: &str) -> Option<Breakpoint> {
+    let (path, line) = argument.rsplit_once(':').unwrap_or((current, argument));
+    line.parse().ok().map(|line| Breakpoint::new(path, line))
+}
+
+impl Debugger for Prompt {
+    fn stopped(&mut self, stop: &mut Stop) -> Resume {
+        let function = stop
+            .vm
+            .backtrace()
+            .into_iter()
+            .next()
+            .map_or_else(String::new, |frame| frame.function);
+        let reason = match stop.reason {
+            StopReason::Breakpoint => "Breakpoint",
+            StopReason::Step => "Stopped",
+        };
+        println!("{} at {}:{} in {}", reason, stop.path, stop.line, function);
+        if let Some(source) = self.source_line(stop.path, stop.line) {
+            println!("{:>4} | {}", stop.line, source);
+        }
+
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        loop {
+            print!("(debug) ");
+            io::stdout().flush().expect("failed to flush stdout");
+            let line = match lines.next() {
+                Some(Ok(line)) => line,
+                // without anything more to ask, the script runs to its end.
+                _ => {
+                    println!();
+                    stop.breakpoints.clear();
+                    return Resume::Continue;
+                }
+            };
+            let mut words = line.split_whitespace();
+            let (command, argument) = (words.next().unwrap_or(""), words.next());
+            match (command, argument) {
+                ("", _) => {}
+                ("step" | "s", None) => return Resume::StepIn,
+                ("next" | "n", None) => return Resume::StepOver,
+                ("finish" | "f", None) => return Resume::StepOut,
+                ("continue" | "c", None) => return Resume::Continue,
+                ("break" | "b" | "clear", Some(argument)) => {
+                    match parse_breakpoint(argument, stop.path) {
+                        Some(breakpoint) if command == "clear" => {
+                            stop.breakpoints.remove(&breakpoint);
+                        }
+                        Some(breakpoint) => {
+                            stop.breakpoints.insert(breakpoint);
+                        }
+                        None => println!("Expect a line number."),
+                    }
+                }
+                ("locals", None) => {
+                    for (name, value) in stop.vm.locals(0) {
+                        println!("{} = {}", name, value.display(&stop.vm.allocator));
+                    }
+                }
+                ("print" | "p", Some(name)) => {
+                    let value = stop.vm.local(0, name).or_else(|| stop.vm.global(0, name));
+                    match value {
+                        Some(value) => println!("{}", value.display(&stop.vm.allocator)),
+                        None => println!("Undefined variable '{}'.", name),
+                    }
+                }
+                ("backtrace" | "bt", None) => {
+                    for frame in stop.vm.backtrace() {
+                        println!("[line {}] in {}", frame.line, frame.function);
+                    }
+                }
+                ("quit" | "q", None) => process::exit(0),
+                _ => println!("{}", DEBUG_HELP),
+            }
+        }
+    }
+}
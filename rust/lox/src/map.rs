@@ -0,0 +1,126 @@
+use crate::{Reference, Value, ValueKind};
+use std::collections::HashMap;
+
+// MapKey is the hashable form of the values a map can be keyed by.
+// strings are interned, so equal strings share a reference.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+enum MapKey {
+    String(Reference<String>),
+    Number(u64),
+    Bool(bool),
+    Nil,
+}
+
+impl MapKey {
+    fn new(key: Value) -> Result<Self, String> {
+        match key.kind() {
+            ValueKind::String(s) => Ok(Self::String(s)),
+            ValueKind::Number(n) if n.is_nan() => Err("Map key can't be NaN.".to_owned()),
+            ValueKind::Number(n) => {
+                // 0 and -0 are equal, so they have to be the same key.
+                let n = if n == 0.0 { 0.0 } else { n };
+                Ok(Self::Number(n.to_bits()))
+            }
+            ValueKind::Bool(b) => Ok(Self::Bool(b)),
+            ValueKind::Nil => Ok(Self::Nil),
+            _ => Err("Map keys must be strings, numbers, booleans or nil.".to_owned()),
+        }
+    }
+}
+
+// Map associates values with keys, created by a `{"k": v}` literal.
+// entries are kept in the order their keys were first inserted. a removed entry
+// leaves a gap behind rather than moving the ones after it, and the gaps are closed
+// once they outnumber the entries.
+#[derive(Default)]
+pub struct Map {
+    entries: Vec<Option<(Value, Value)>>,
+    // where each key's entry is.
+    indices: HashMap<MapKey, usize>,
+}
+
+impl std::fmt::Debug for Map {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "<map len={}>", self.len())
+    }
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    pub fn get(&self, key: Value) -> Result<Option<Value>, String> {
+        let key = MapKey::new(key)?;
+        Ok(self
+            .indices
+            .get(&key)
+            .and_then(|&i| self.entries[i])
+            .map(|(_, v)| v))
+    }
+
+    pub fn contains_key(&self, key: Value) -> Result<bool, String> {
+        Ok(self.indices.contains_key(&MapKey::new(key)?))
+    }
+
+    // insert replaces the value of an existing key, keeping the key where it was.
+    pub fn insert(&mut self, key: Value, v: Value) -> Result<(), String> {
+        let hashed = MapKey::new(key)?;
+        match self.indices.get(&hashed) {
+            Some(&i) => {
+                if let Some(entry) = &mut self.entries[i] {
+                    entry.1 = v;
+                }
+            }
+            None => {
+                self.indices.insert(hashed, self.entries.len());
+                self.entries.push(Some((key, v)));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: Value) -> Result<Option<Value>, String> {
+        let removed = match self.indices.remove(&MapKey::new(key)?) {
+            Some(removed) => removed,
+            None => return Ok(None),
+        };
+        let removed = self.entries[removed].take().map(|(_, v)| v);
+        if self.entries.len() - self.len() > self.len() {
+            self.compact();
+        }
+        Ok(removed)
+    }
+
+    // compact closes the gaps left by removed entries, which moves the entries after them.
+    fn compact(&mut self) {
+        self.entries.retain(Option::is_some);
+        for (i, entry) in self.entries.iter().enumerate() {
+            if let Some((key, _)) = entry {
+                let key = MapKey::new(*key).expect("only valid keys are inserted");
+                self.indices.insert(key, i);
+            }
+        }
+    }
+
+    // iter goes through the entries in insertion order.
+    pub fn iter(&self) -> impl Iterator<Item = &(Value, Value)> {
+        self.entries.iter().flatten()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = Value> + '_ {
+        self.iter().map(|&(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = Value> + '_ {
+        self.iter().map(|&(_, v)| v)
+    }
+}
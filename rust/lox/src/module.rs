@@ -0,0 +1,78 @@
+use crate::globals::Globals;
+use std::collections::HashMap;
+use std::fs;
+
+// ModuleResolver finds the source of the modules which scripts import.
+// the path has already been resolved against the importing file,
+// e.g. `import "util.lox" as u;` in `lib/main.lox` asks for `lib/util.lox`.
+pub trait ModuleResolver {
+    // load returns the source of a module. an error is raised as a runtime error
+    // at the `import` which asked for it.
+    fn load(&mut self, path: &str) -> Result<String, String>;
+}
+
+// FileResolver reads modules from the filesystem, which is what a VM does by default.
+#[derive(Default)]
+pub struct FileResolver;
+
+impl ModuleResolver for FileResolver {
+    fn load(&mut self, path: &str) -> Result<String, String> {
+        fs::read_to_string(path).map_err(|e| e.to_string())
+    }
+}
+
+// a map from paths to sources serves modules from memory.
+impl ModuleResolver for HashMap<String, String> {
+    fn load(&mut self, path: &str) -> Result<String, String> {
+        self.get(path)
+            .cloned()
+            .ok_or_else(|| "no such module".to_owned())
+    }
+}
+
+// ScriptModule is the value of an imported module.
+// its top-level variables live in globals of its own, which are read as its properties.
+pub struct ScriptModule {
+    pub path: String,
+    pub globals: Globals,
+}
+
+impl std::fmt::Debug for ScriptModule {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "<module {}>", self.path)
+    }
+}
+
+impl ScriptModule {
+    pub fn new(path: String, globals: Globals) -> Self {
+        Self { path, globals }
+    }
+}
+
+// resolve_path joins the imported path onto the directory of the importing file,
+// dropping `.` and `..` components, so that a module is cached once however it is reached.
+// the main script has an empty path when it wasn't run from a file,
+// and then imports are relative to the working directory.
+pub(crate) fn resolve_path(importer: &str, path: &str) -> String {
+    let joined = match (path.starts_with('/'), importer.rfind('/')) {
+        (false, Some(end)) => format!("{}/{}", &importer[..end], path),
+        _ => path.to_owned(),
+    };
+
+    let mut components: Vec<&str> = Vec::new();
+    for component in joined.split('/') {
+        match component {
+            "" | "." => {}
+            ".." if matches!(components.last(), Some(&last) if last != "..") => {
+                components.pop();
+            }
+            _ => components.push(component),
+        }
+    }
+    let resolved = components.join("/");
+    if joined.starts_with('/') {
+        format!("/{}", resolved)
+    } else {
+        resolved
+    }
+}
@@ -0,0 +1,364 @@
+use crate::chunk::{Chunk, Handler, Instruction, LocalName, MAX_CONSTANTS};
+use crate::{OpCode, Value, ValueKind};
+use std::collections::HashMap;
+
+// OptimizationLevel is how much work the compiler puts into the bytecode it emits.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum OptimizationLevel {
+    // the bytecode follows the source one to one, which is the easiest to read disassembled.
+    #[default]
+    None,
+    // constant folding, peephole rewrites, jump threading and dead code removal.
+    Full,
+}
+
+// optimize rewrites a finished chunk into one which does the same with fewer instructions.
+// the chunk is left alone if a jump wouldn't fit in the encoding afterwards.
+pub(crate) fn optimize(chunk: &mut Chunk) {
+    let (mut instructions, mut handlers, mut locals) = match chunk.decode() {
+        Some(decoded) => decoded,
+        None => return,
+    };
+    let mut values = chunk.values.clone();
+
+    // each pass may open up work for the others, e.g. folding `1 < 2` into `true`.
+    loop {
+        let mut ranges = Ranges {
+            handlers: &mut handlers,
+            locals: &mut locals,
+        };
+        let mut changed = fold_constants(&mut instructions, &mut ranges, &mut values);
+        changed |= collapse_not_jumps(&mut instructions, &mut ranges);
+        changed |= thread_jumps(&mut instructions);
+        changed |= remove_dead_code(&mut instructions, &mut ranges);
+        if !changed {
+            break;
+        }
+    }
+    remove_unused_constants(&mut instructions, &mut values);
+
+    if let Some(optimized) = Chunk::encode(&instructions, &handlers, &locals, values) {
+        *chunk = optimized;
+    }
+}
+
+// Ranges are what points at instructions other than jumps,
+// which have to move along when instructions are removed.
+struct Ranges<'r> {
+    handlers: &'r mut Vec<Handler>,
+    locals: &'r mut Vec<LocalName>,
+}
+
+fn jump_target(op: OpCode) -> Option<usize> {
+    match op {
+        OpCode::Jump(target) | OpCode::JumpIfFalse(target) | OpCode::JumpIfTrue(target) => {
+            Some(target)
+        }
+        _ => None,
+    }
+}
+
+fn set_jump_target(op: &mut OpCode, to: usize) {
+    match op {
+        OpCode::Jump(target) | OpCode::JumpIfFalse(target) | OpCode::JumpIfTrue(target) => {
+            *target = to
+        }
+        _ => unreachable!("{:?} is not a jump", op),
+    }
+}
+
+// is_target marks the instructions which some jump goes to, and those where a handler
+// starts catching, stops catching or goes to.
+fn is_target(instructions: &[Instruction], handlers: &[Handler]) -> Vec<bool> {
+    let mut targets = vec![false; instructions.len() + 1];
+    for instruction in instructions {
+        if let Some(target) = jump_target(instruction.op) {
+            targets[target] = true;
+        }
+    }
+    for handler in handlers {
+        targets[handler.start] = true;
+        targets[handler.end] = true;
+        targets[handler.target] = true;
+    }
+    targets
+}
+
+// remove drops the marked instructions, moving jumps and ranges pointing at them
+// onto the next instruction. a range left without any instruction in it is dropped.
+fn remove(instructions: &mut Vec<Instruction>, ranges: &mut Ranges, removed: &[bool]) {
+    let mut new_index = Vec::with_capacity(instructions.len() + 1);
+    let mut kept = 0;
+    for &is_removed in removed {
+        new_index.push(kept);
+        if !is_removed {
+            kept += 1;
+        }
+    }
+    new_index.push(kept);
+
+    let mut i = 0;
+    instructions.retain(|_| {
+        i += 1;
+        !removed[i - 1]
+    });
+    for instruction in instructions.iter_mut() {
+        if let Some(target) = jump_target(instruction.op) {
+            set_jump_target(&mut instruction.op, new_index[target]);
+        }
+    }
+    for handler in ranges.handlers.iter_mut() {
+        handler.start = new_index[handler.start];
+        handler.end = new_index[handler.end];
+        handler.target = new_index[handler.target];
+    }
+    ranges
+        .handlers
+        .retain(|handler| handler.start < handler.end);
+    for local in ranges.locals.iter_mut() {
+        local.start = new_index[local.start];
+        local.end = new_index[local.end];
+    }
+    ranges.locals.retain(|local| local.start < local.end);
+}
+
+// the value an instruction pushes, if it always pushes the same one.
+fn literal(op: OpCode, values: &[Value]) -> Option<Value> {
+    match op {
+        OpCode::Nil => Some(Value::NIL),
+        OpCode::True => Some(Value::bool(true)),
+        OpCode::False => Some(Value::bool(false)),
+        OpCode::Constant(index) => Some(values[index]),
+        _ => None,
+    }
+}
+
+// the instruction which pushes the value, adding it to the constants if needed.
+fn push_literal(value: Value, values: &mut Vec<Value>) -> Option<OpCode> {
+    let n = match value.kind() {
+        ValueKind::Bool(true) => return Some(OpCode::True),
+        ValueKind::Bool(false) => return Some(OpCode::False),
+        ValueKind::Number(n) => n,
+        _ => return None,
+    };
+    let existing = values
+        .iter()
+        .position(|v| matches!(v.kind(), ValueKind::Number(m) if m.to_bits() == n.to_bits()));
+    match existing {
+        Some(index) => Some(OpCode::Constant(index)),
+        None if values.len() < MAX_CONSTANTS => {
+            values.push(value);
+            Some(OpCode::Constant(values.len() - 1))
+        }
+        None => None,
+    }
+}
+
+// evaluates an operator on literals the way the VM would.
+// None means it would raise a runtime error, which is left for the VM to raise.
+fn evaluate(op: OpCode, a: Value, b: Option<Value>) -> Option<Value> {
+    if let (OpCode::Equal, Some(b)) = (op, b) {
+        return Some(Value::bool(a == b));
+    }
+    let value = match (op, a.kind(), b.map(Value::kind)) {
+        (OpCode::Negate, ValueKind::Number(a), None) => Value::number(-a),
+        (OpCode::Not, ValueKind::Bool(_), None) | (OpCode::Not, ValueKind::Nil, None) => {
+            Value::bool(a.is_falsy())
+        }
+        (op, ValueKind::Number(a), Some(ValueKind::Number(b))) => match op {
+            OpCode::Add => Value::number(a + b),
+            OpCode::Subtract => Value::number(a - b),
+            OpCode::Multiply => Value::number(a * b),
+            OpCode::Divide => Value::number(a / b),
+            OpCode::Greater => Value::bool(a > b),
+            OpCode::Less => Value::bool(a < b),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(value)
+}
+
+// fold_constants evaluates operators whose operands are literals, e.g. `1 + 2` into `3`.
+// nothing may jump into the middle of the instructions being folded.
+fn fold_constants(
+    instructions: &mut Vec<Instruction>,
+    ranges: &mut Ranges,
+    values: &mut Vec<Value>,
+) -> bool {
+    let targets = is_target(instructions, ranges.handlers);
+    let mut removed = vec![false; instructions.len()];
+    let mut changed = false;
+    let mut i = 0;
+    while i < instructions.len() {
+        let a = match literal(instructions[i].op, values) {
+            Some(a) => a,
+            None => {
+                i += 1;
+                continue;
+            }
+        };
+        let b = instructions
+            .get(i + 1)
+            .filter(|_| !targets[i + 1])
+            .and_then(|next| literal(next.op, values));
+        let operator = if b.is_some() { i + 2 } else { i + 1 };
+
+        let folded = instructions
+            .get(operator)
+            .filter(|_| !targets[operator])
+            .and_then(|instruction| evaluate(instruction.op, a, b))
+            .and_then(|value| push_literal(value, values));
+        match folded {
+            Some(op) => {
+                instructions[i] = Instruction {
+                    op,
+                    line: instructions[operator].line,
+                };
+                for r in &mut removed[i + 1..=operator] {
+                    *r = true;
+                }
+                changed = true;
+                i = operator + 1;
+            }
+            None => i += 1,
+        }
+    }
+    remove(instructions, ranges, &removed);
+    changed
+}
+
+// collapse_not_jumps turns a negated comparison followed by a conditional jump,
+// e.g. `a >= b` compiled into `Less, Not`, into a jump on the opposite condition.
+// the condition left on the stack is different, so both ways out of the jump have to pop it.
+// the operand of `Not` has to be a comparison, as `Not` raises an error on anything
+// other than booleans and nil.
+fn collapse_not_jumps(instructions: &mut Vec<Instruction>, ranges: &mut Ranges) -> bool {
+    let targets = is_target(instructions, ranges.handlers);
+    let mut removed = vec![false; instructions.len()];
+    let mut changed = false;
+    for i in 1..instructions.len().saturating_sub(2) {
+        let is_comparison = matches!(
+            instructions[i - 1].op,
+            OpCode::Equal | OpCode::Greater | OpCode::Less | OpCode::Not
+        );
+        let target = match instructions[i + 1].op {
+            OpCode::JumpIfFalse(target) => target,
+            _ => continue,
+        };
+        let pops_after =
+            instructions[i + 2].op == OpCode::Pop && instructions[target].op == OpCode::Pop;
+        if removed[i - 1]
+            || !is_comparison
+            || instructions[i].op != OpCode::Not
+            || targets[i]
+            || targets[i + 1]
+            || !pops_after
+        {
+            continue;
+        }
+        removed[i] = true;
+        instructions[i + 1].op = OpCode::JumpIfTrue(target);
+        changed = true;
+    }
+    remove(instructions, ranges, &removed);
+    changed
+}
+
+// thread_jumps makes a jump to an unconditional jump go straight to where that one goes.
+// conditional jumps only go forward, so they are only threaded forward.
+fn thread_jumps(instructions: &mut [Instruction]) -> bool {
+    let mut changed = false;
+    for i in 0..instructions.len() {
+        let target = match jump_target(instructions[i].op) {
+            Some(target) => target,
+            None => continue,
+        };
+
+        // a cycle of jumps is an infinite loop, and is left as it is.
+        let mut end = target;
+        let mut seen = vec![end];
+        while let OpCode::Jump(next) = instructions[end].op {
+            if seen.contains(&next) {
+                end = target;
+                break;
+            }
+            seen.push(next);
+            end = next;
+        }
+
+        let is_conditional = !matches!(instructions[i].op, OpCode::Jump(_));
+        if end != target && (!is_conditional || end > i) {
+            set_jump_target(&mut instructions[i].op, end);
+            changed = true;
+        }
+    }
+    changed
+}
+
+// remove_dead_code removes the instructions which can't be reached, e.g. those after a
+// `return`, and jumps which go to the next instruction anyway.
+// handlers are reached by throwing rather than jumping, so they are kept.
+fn remove_dead_code(instructions: &mut Vec<Instruction>, ranges: &mut Ranges) -> bool {
+    let mut reachable = vec![false; instructions.len()];
+    let mut pending = vec![0];
+    pending.extend(ranges.handlers.iter().map(|handler| handler.target));
+    while let Some(i) = pending.pop() {
+        if i >= instructions.len() || reachable[i] {
+            continue;
+        }
+        reachable[i] = true;
+        match instructions[i].op {
+            OpCode::Return | OpCode::Throw => {}
+            OpCode::Jump(target) => pending.push(target),
+            OpCode::JumpIfFalse(target) | OpCode::JumpIfTrue(target) => {
+                pending.push(target);
+                pending.push(i + 1);
+            }
+            _ => pending.push(i + 1),
+        }
+    }
+
+    let removed: Vec<bool> = instructions
+        .iter()
+        .enumerate()
+        .map(|(i, instruction)| !reachable[i] || jump_target(instruction.op) == Some(i + 1))
+        .collect();
+    if !removed.contains(&true) {
+        return false;
+    }
+    remove(instructions, ranges, &removed);
+    true
+}
+
+fn constant_index(op: &mut OpCode) -> Option<&mut usize> {
+    match op {
+        OpCode::Constant(index)
+        | OpCode::Closure(index)
+        | OpCode::Class(index)
+        | OpCode::GetProperty(index)
+        | OpCode::SetProperty(index)
+        | OpCode::Method(index)
+        | OpCode::Invoke(index, _)
+        | OpCode::GetSuper(index)
+        | OpCode::SuperInvoke(index, _)
+        | OpCode::Import(index) => Some(index),
+        _ => None,
+    }
+}
+
+// remove_unused_constants drops the constants which folding or dead code removal
+// left without any instruction using them.
+fn remove_unused_constants(instructions: &mut [Instruction], values: &mut Vec<Value>) {
+    let mut new_index = HashMap::new();
+    let mut used = Vec::new();
+    for instruction in instructions.iter_mut() {
+        if let Some(index) = constant_index(&mut instruction.op) {
+            *index = *new_index.entry(*index).or_insert_with(|| {
+                used.push(values[*index]);
+                used.len() - 1
+            });
+        }
+    }
+    *values = used;
+}
@@ -1,3 +1,4 @@
+use crate::compiler::CompileError;
 use crate::token::{Token, TokenType};
 
 pub struct Scanner<'a> {
@@ -5,6 +6,8 @@ pub struct Scanner<'a> {
     start: usize,
     current: usize,
     line: usize,
+    // where the current line begins, to compute columns.
+    line_start: usize,
 }
 
 impl<'a> Scanner<'a> {
@@ -14,39 +17,37 @@ impl<'a> Scanner<'a> {
             start: 0,
             current: 0,
             line: 0,
+            line_start: 0,
         }
     }
 
     fn is_digit(c: char) -> bool {
-        match c {
-            '0'..='9' => true,
-            _ => false,
-        }
+        c.is_ascii_digit()
     }
 
     fn is_alpha(c: char) -> bool {
-        match c {
-            'a'..='z' | 'A'..='Z' | '_' => true,
-            _ => false,
-        }
+        matches!(c, 'a'..='z' | 'A'..='Z' | '_')
     }
 
-    pub fn scan_tokens(&mut self) -> Result<Vec<Token<'a>>, String> {
+    // scans the whole source. invalid characters are skipped and reported,
+    // so that the parser still gets to see the rest of the tokens.
+    pub fn scan_tokens(&mut self) -> (Vec<Token<'a>>, Vec<CompileError>) {
         let mut tokens = Vec::new();
+        let mut errors = Vec::new();
         loop {
             match self.scan_token() {
                 Ok(tok) => {
                     tokens.push(tok);
                     if tok.typ == TokenType::Eof {
-                        return Ok(tokens);
+                        return (tokens, errors);
                     }
                 }
-                Err(e) => return Err(e),
+                Err(e) => errors.push(e),
             }
         }
     }
 
-    pub fn scan_token(&mut self) -> Result<Token<'a>, String> {
+    pub fn scan_token(&mut self) -> Result<Token<'a>, CompileError> {
         self.skip_whitespace();
 
         self.start = self.current;
@@ -70,7 +71,10 @@ impl<'a> Scanner<'a> {
             ')' => Ok(self.make_token(TokenType::RightParen)),
             '{' => Ok(self.make_token(TokenType::LeftBrace)),
             '}' => Ok(self.make_token(TokenType::RightBrace)),
+            '[' => Ok(self.make_token(TokenType::LeftBracket)),
+            ']' => Ok(self.make_token(TokenType::RightBracket)),
             ';' => Ok(self.make_token(TokenType::SemiColon)),
+            ':' => Ok(self.make_token(TokenType::Colon)),
             ',' => Ok(self.make_token(TokenType::Comma)),
             '.' => Ok(self.make_token(TokenType::Dot)),
             '-' => Ok(self.make_token(TokenType::Minus)),
@@ -106,36 +110,27 @@ impl<'a> Scanner<'a> {
                 Ok(self.make_token(TokenType::Less))
             }
             '"' => self.string(),
-            _ => Err(format!("Unexpected character at {}", self.line)),
+            _ => Err(self.error("Unexpected character.")),
         }
     }
 
+    // the scanner works on byte offsets, so that tokens can slice the source,
+    // and steps over a whole character at a time.
     fn advance(&mut self) -> char {
-        let c = self.source.chars().nth(self.current);
-        self.current = self.current + 1;
-        c.expect("Scanner tried to advance to out of bounds character")
+        let c = self.source[self.current..]
+            .chars()
+            .next()
+            .expect("Scanner tried to advance to out of bounds character");
+        self.current += c.len_utf8();
+        c
     }
 
     fn peek(&self) -> char {
-        if self.is_at_end() {
-            return '\0';
-        }
-
-        self.source
-            .chars()
-            .nth(self.current)
-            .expect("Scanner tried to advance to out of bounds character")
+        self.source[self.current..].chars().next().unwrap_or('\0')
     }
 
     fn peek_next(&self) -> char {
-        if self.is_at_end() {
-            return '\0';
-        }
-
-        self.source
-            .chars()
-            .nth(self.current + 1)
-            .expect("Scanner tried to advance to out of bounds character")
+        self.source[self.current..].chars().nth(1).unwrap_or('\0')
     }
 
     fn skip_whitespace(&mut self) {
@@ -146,17 +141,16 @@ impl<'a> Scanner<'a> {
                     self.advance();
                 }
                 '\n' => {
-                    self.line = self.line + 1;
                     self.advance();
+                    self.new_line();
                 }
-                '/' => {
-                    if self.peek_next() == '/' {
-                        // A comment goes until the end of the line.
-                        while !self.is_at_end() && self.peek() != '\n' {
-                            self.advance();
-                        }
+                '/' if self.peek_next() == '/' => {
+                    // A comment goes until the end of the line.
+                    while !self.is_at_end() && self.peek() != '\n' {
+                        self.advance();
                     }
                 }
+                // anything else, including a slash on its own (division), ends the whitespace.
                 _ => return,
             }
         }
@@ -166,22 +160,30 @@ impl<'a> Scanner<'a> {
         self.current >= self.source.len()
     }
 
-    fn string(&mut self) -> Result<Token<'a>, String> {
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
+    }
+
+    fn string(&mut self) -> Result<Token<'a>, CompileError> {
+        // a string spanning lines is reported where it starts.
+        let (line, column) = (self.line, self.start - self.line_start);
         while !self.is_at_end() && self.peek() != '"' {
-            if self.peek() == '\n' {
-                self.line = self.line + 1;
+            let c = self.advance();
+            if c == '\n' {
+                self.new_line();
             }
-            self.advance();
         }
 
         if self.is_at_end() {
-            return Err(format!("Unterminated string at {}", self.line));
+            let tok = self.make_token_at(TokenType::Error, line, column);
+            return Err(CompileError::new(&tok, "Unterminated string."));
         }
 
         // closing quote.
         self.advance();
 
-        Ok(self.make_token(TokenType::String))
+        Ok(self.make_token_at(TokenType::String, line, column))
     }
 
     fn number(&mut self) -> Token<'a> {
@@ -211,23 +213,52 @@ impl<'a> Scanner<'a> {
     }
 
     fn identifier_type(&self) -> TokenType {
-        let c = self
-            .source
-            .chars()
-            .nth(self.start)
-            .expect("Scanner tried to peek identifier out of bounds character");
+        // identifiers only consist of ascii characters.
+        let c = self.source.as_bytes()[self.start] as char;
 
         match c {
             'a' => {
                 if self.check_rest_keyword(1, "nd") {
                     TokenType::And
+                } else if self.check_rest_keyword(1, "s") {
+                    TokenType::As
+                } else {
+                    TokenType::Identifier
+                }
+            }
+            'b' => {
+                if self.check_rest_keyword(1, "reak") {
+                    TokenType::Break
                 } else {
                     TokenType::Identifier
                 }
             }
             'c' => {
-                if self.check_rest_keyword(1, "lass") {
-                    TokenType::Class
+                if self.current - self.start >= 2 {
+                    match self.source.as_bytes()[self.start + 1] as char {
+                        'a' => {
+                            if self.check_rest_keyword(2, "tch") {
+                                TokenType::Catch
+                            } else {
+                                TokenType::Identifier
+                            }
+                        }
+                        'l' => {
+                            if self.check_rest_keyword(2, "ass") {
+                                TokenType::Class
+                            } else {
+                                TokenType::Identifier
+                            }
+                        }
+                        'o' => {
+                            if self.check_rest_keyword(2, "ntinue") {
+                                TokenType::Continue
+                            } else {
+                                TokenType::Identifier
+                            }
+                        }
+                        _ => TokenType::Identifier,
+                    }
                 } else {
                     TokenType::Identifier
                 }
@@ -241,12 +272,7 @@ impl<'a> Scanner<'a> {
             }
             'f' => {
                 if self.current - self.start >= 2 {
-                    match self
-                        .source
-                        .chars()
-                        .nth(self.start + 1)
-                        .expect("Scanner tried to peek identifier out of bounds character")
-                    {
+                    match self.source.as_bytes()[self.start + 1] as char {
                         'a' => {
                             if self.check_rest_keyword(2, "lse") {
                                 TokenType::False
@@ -254,6 +280,13 @@ impl<'a> Scanner<'a> {
                                 TokenType::Identifier
                             }
                         }
+                        'i' => {
+                            if self.check_rest_keyword(2, "nally") {
+                                TokenType::Finally
+                            } else {
+                                TokenType::Identifier
+                            }
+                        }
                         'o' => {
                             if self.check_rest_keyword(2, "r") {
                                 TokenType::For
@@ -277,6 +310,8 @@ impl<'a> Scanner<'a> {
             'i' => {
                 if self.check_rest_keyword(1, "f") {
                     TokenType::If
+                } else if self.check_rest_keyword(1, "mport") {
+                    TokenType::Import
                 } else {
                     TokenType::Identifier
                 }
@@ -309,17 +344,21 @@ impl<'a> Scanner<'a> {
                     TokenType::Identifier
                 }
             }
+            's' => {
+                if self.check_rest_keyword(1, "uper") {
+                    TokenType::Super
+                } else {
+                    TokenType::Identifier
+                }
+            }
             't' => {
                 if self.current - self.start >= 2 {
-                    match self
-                        .source
-                        .chars()
-                        .nth(self.start + 1)
-                        .expect("Scanner tried to peek identifier out of bounds character")
-                    {
+                    match self.source.as_bytes()[self.start + 1] as char {
                         'h' => {
                             if self.check_rest_keyword(2, "is") {
                                 TokenType::This
+                            } else if self.check_rest_keyword(2, "row") {
+                                TokenType::Throw
                             } else {
                                 TokenType::Identifier
                             }
@@ -327,6 +366,8 @@ impl<'a> Scanner<'a> {
                         'r' => {
                             if self.check_rest_keyword(2, "ue") {
                                 TokenType::True
+                            } else if self.check_rest_keyword(2, "y") {
+                                TokenType::Try
                             } else {
                                 TokenType::Identifier
                             }
@@ -361,14 +402,24 @@ impl<'a> Scanner<'a> {
             return false;
         }
 
-        return &self.source[self.start + offset..self.current] == rest;
+        &self.source[self.start + offset..self.current] == rest
     }
 
     fn make_token(&self, typ: TokenType) -> Token<'a> {
+        self.make_token_at(typ, self.line, self.start - self.line_start)
+    }
+
+    // make_token_at is for tokens which span lines, and so don't start on the current one.
+    fn make_token_at(&self, typ: TokenType, line: usize, column: usize) -> Token<'a> {
         Token {
             typ,
-            line: self.line,
+            line,
+            column,
             source: &self.source[self.start..self.current],
         }
     }
+
+    fn error(&self, msg: &str) -> CompileError {
+        CompileError::new(&self.make_token(TokenType::Error), msg)
+    }
 }
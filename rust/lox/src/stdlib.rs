@@ -0,0 +1,325 @@
+use crate::{Allocator, List, Map, Reference, Value, ValueKind, VM};
+use std::cell::Cell;
+use std::io::BufRead;
+use std::rc::Rc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+// Module is a group of natives which can be loaded into a VM on its own,
+// so that an embedder can leave out e.g. file access.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Module {
+    // clock(), time()
+    Time,
+    // sqrt(x), floor(x), ceil(x), abs(x), pow(x, y), min(a, b), max(a, b), random(), seed(n)
+    Math,
+    // substring(s, start, end), index_of(s, needle), upper(s), lower(s),
+    // to_string(v), to_number(s)
+    Strings,
+    // read_line(), read_file(path), write_file(path, contents)
+    Io,
+}
+
+impl Module {
+    pub const ALL: [Module; 4] = [Module::Time, Module::Math, Module::Strings, Module::Io];
+}
+
+// load_core defines the natives every VM has, which work on the built-in types
+// and don't reach outside of the VM: panic(message), len(v),
+// push(list, v), pop(list), insert(list, index, v), remove(list, index),
+// has(map, key), remove(map, key), keys(map), values(map).
+pub(crate) fn load_core(vm: &mut VM) {
+    vm.define_native("panic", 1, |allocator, args| {
+        Err(format!("panic: {}", args[0].display(allocator)))
+    });
+    // len counts the characters of a string, the items of a list, or the entries of a map.
+    vm.define_native("len", 1, |allocator, args| {
+        let len = match args[0].kind() {
+            ValueKind::String(s) => allocator.deref(&s).chars().count(),
+            ValueKind::List(list) => allocator.deref(&list).items.len(),
+            ValueKind::Map(map) => allocator.deref(&map).len(),
+            _ => return Err("Argument 1 must be a string, a list or a map.".to_owned()),
+        };
+        Ok(Value::number(len as f64))
+    });
+    vm.define_native("push", 2, |allocator, args| {
+        let list = list_arg(args, 0)?;
+        allocator.deref_mut(&list).items.push(args[1]);
+        allocator.resize(&list);
+        Ok(Value::NIL)
+    });
+    vm.define_native("pop", 1, |allocator, args| {
+        let list = list_arg(args, 0)?;
+        allocator
+            .deref_mut(&list)
+            .items
+            .pop()
+            .ok_or_else(|| "Can't pop from an empty list.".to_owned())
+    });
+    // insert puts the value before the given index, which may also be the length of the list.
+    vm.define_native("insert", 3, |allocator, args| {
+        let list_id = list_arg(args, 0)?;
+        let list = allocator.deref_mut(&list_id);
+        let i = match args[1].kind() {
+            ValueKind::Number(n) if n == list.items.len() as f64 => list.items.len(),
+            _ => list.index(args[1])?,
+        };
+        list.items.insert(i, args[2]);
+        allocator.resize(&list_id);
+        Ok(Value::NIL)
+    });
+    // remove takes the item at the given index out of a list, or the given key out of a map,
+    // and returns its value. a missing key is nil.
+    vm.define_native("remove", 2, |allocator, args| match args[0].kind() {
+        ValueKind::List(list) => {
+            let list = allocator.deref_mut(&list);
+            let i = list.index(args[1])?;
+            Ok(list.items.remove(i))
+        }
+        ValueKind::Map(map) => Ok(allocator
+            .deref_mut(&map)
+            .remove(args[1])?
+            .unwrap_or(Value::NIL)),
+        _ => Err("Argument 1 must be a list or a map.".to_owned()),
+    });
+    vm.define_native("has", 2, |allocator, args| {
+        let map = allocator.deref(&map_arg(args, 0)?);
+        Ok(Value::bool(map.contains_key(args[1])?))
+    });
+    // keys and values return new lists, in the order the keys were inserted.
+    vm.define_native("keys", 1, |allocator, args| {
+        let keys = allocator.deref(&map_arg(args, 0)?).keys().collect();
+        Ok(Value::from(allocator.alloc(List::new(keys))))
+    });
+    vm.define_native("values", 1, |allocator, args| {
+        let values = allocator.deref(&map_arg(args, 0)?).values().collect();
+        Ok(Value::from(allocator.alloc(List::new(values))))
+    });
+}
+
+pub(crate) fn load(vm: &mut VM, module: Module) {
+    match module {
+        Module::Time => load_time(vm),
+        Module::Math => load_math(vm),
+        Module::Strings => load_strings(vm),
+        Module::Io => load_io(vm),
+    }
+}
+
+fn number_arg(args: &[Value], i: usize) -> Result<f64, String> {
+    match args[i].kind() {
+        ValueKind::Number(n) => Ok(n),
+        _ => Err(format!("Argument {} must be a number.", i + 1)),
+    }
+}
+
+fn string_arg<'a>(allocator: &'a Allocator, args: &[Value], i: usize) -> Result<&'a str, String> {
+    match args[i].kind() {
+        ValueKind::String(s) => Ok(allocator.deref(&s).as_str()),
+        _ => Err(format!("Argument {} must be a string.", i + 1)),
+    }
+}
+
+fn list_arg(args: &[Value], i: usize) -> Result<Reference<List>, String> {
+    match args[i].kind() {
+        ValueKind::List(list) => Ok(list),
+        _ => Err(format!("Argument {} must be a list.", i + 1)),
+    }
+}
+
+fn map_arg(args: &[Value], i: usize) -> Result<Reference<Map>, String> {
+    match args[i].kind() {
+        ValueKind::Map(map) => Ok(map),
+        _ => Err(format!("Argument {} must be a map.", i + 1)),
+    }
+}
+
+// index_arg reads a position within a string, counted in characters.
+fn index_arg(args: &[Value], i: usize) -> Result<usize, String> {
+    let n = number_arg(args, i)?;
+    if n < 0.0 || n.fract() != 0.0 {
+        return Err(format!(
+            "Argument {} must be a non-negative integer.",
+            i + 1
+        ));
+    }
+    Ok(n as usize)
+}
+
+fn new_string(allocator: &mut Allocator, s: String) -> Value {
+    Value::from(allocator.new_string(s))
+}
+
+fn load_time(vm: &mut VM) {
+    // clock is monotonic, counting seconds from when the module was loaded.
+    let start = Instant::now();
+    vm.define_native("clock", 0, move |_, _| {
+        Ok(Value::number(start.elapsed().as_secs_f64()))
+    });
+    // time is the wall-clock time, in seconds since the unix epoch.
+    vm.define_native("time", 0, |_, _| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| Value::number(d.as_secs_f64()))
+            .map_err(|_| "System time is before the unix epoch.".to_owned())
+    });
+}
+
+fn load_math(vm: &mut VM) {
+    vm.define_native("sqrt", 1, |_, args| {
+        Ok(Value::number(number_arg(args, 0)?.sqrt()))
+    });
+    vm.define_native("floor", 1, |_, args| {
+        Ok(Value::number(number_arg(args, 0)?.floor()))
+    });
+    vm.define_native("ceil", 1, |_, args| {
+        Ok(Value::number(number_arg(args, 0)?.ceil()))
+    });
+    vm.define_native("abs", 1, |_, args| {
+        Ok(Value::number(number_arg(args, 0)?.abs()))
+    });
+    vm.define_native("pow", 2, |_, args| {
+        Ok(Value::number(
+            number_arg(args, 0)?.powf(number_arg(args, 1)?),
+        ))
+    });
+    vm.define_native("min", 2, |_, args| {
+        Ok(Value::number(
+            number_arg(args, 0)?.min(number_arg(args, 1)?),
+        ))
+    });
+    vm.define_native("max", 2, |_, args| {
+        Ok(Value::number(
+            number_arg(args, 0)?.max(number_arg(args, 1)?),
+        ))
+    });
+
+    let rng = Rc::new(Cell::new(Rng::from_time()));
+    let state = Rc::clone(&rng);
+    // random returns a number in [0, 1).
+    vm.define_native("random", 0, move |_, _| {
+        let mut r = state.get();
+        let n = r.next_f64();
+        state.set(r);
+        Ok(Value::number(n))
+    });
+    // seed makes the numbers which random returns from then on reproducible.
+    vm.define_native("seed", 1, move |_, args| {
+        rng.set(Rng::new(number_arg(args, 0)?.to_bits()));
+        Ok(Value::NIL)
+    });
+}
+
+// Rng is a xorshift64* generator, which is plenty for scripts.
+#[derive(Copy, Clone)]
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // xorshift gets stuck at zero, and nearby seeds should not start out alike,
+        // so the seed is scrambled with splitmix64 first.
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        Self {
+            state: if z == 0 { 1 } else { z },
+        }
+    }
+
+    fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        Self::new(nanos)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        // the top 53 bits fill the mantissa of a double exactly.
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+fn load_strings(vm: &mut VM) {
+    // substring takes the characters from start up to, but not including, end.
+    vm.define_native("substring", 3, |allocator, args| {
+        let s = string_arg(allocator, args, 0)?;
+        let (start, end) = (index_arg(args, 1)?, index_arg(args, 2)?);
+        let len = s.chars().count();
+        if start > end || end > len {
+            return Err(format!(
+                "Substring range {}..{} is out of bounds for a string of length {}.",
+                start, end, len
+            ));
+        }
+        let sub: String = s.chars().skip(start).take(end - start).collect();
+        Ok(new_string(allocator, sub))
+    });
+    // index_of returns where needle first appears in the string, or -1.
+    vm.define_native("index_of", 2, |allocator, args| {
+        let s = string_arg(allocator, args, 0)?;
+        let needle = string_arg(allocator, args, 1)?;
+        let index = s
+            .find(needle)
+            .map_or(-1.0, |byte_pos| s[..byte_pos].chars().count() as f64);
+        Ok(Value::number(index))
+    });
+    vm.define_native("upper", 1, |allocator, args| {
+        let s = string_arg(allocator, args, 0)?.to_uppercase();
+        Ok(new_string(allocator, s))
+    });
+    vm.define_native("lower", 1, |allocator, args| {
+        let s = string_arg(allocator, args, 0)?.to_lowercase();
+        Ok(new_string(allocator, s))
+    });
+    // to_string formats any value the way `print` does.
+    vm.define_native("to_string", 1, |allocator, args| {
+        let s = args[0].display(allocator).to_string();
+        Ok(new_string(allocator, s))
+    });
+    // to_number returns nil when the string isn't a number.
+    vm.define_native("to_number", 1, |allocator, args| {
+        let s = string_arg(allocator, args, 0)?;
+        Ok(s.trim().parse().map_or(Value::NIL, Value::number))
+    });
+}
+
+fn load_io(vm: &mut VM) {
+    // read_line returns the next line from stdin without its line break, or nil at the end.
+    vm.define_native("read_line", 0, |allocator, _| {
+        let mut line = String::new();
+        let n = std::io::stdin()
+            .lock()
+            .read_line(&mut line)
+            .map_err(|e| format!("Could not read from stdin: {}.", e))?;
+        if n == 0 {
+            return Ok(Value::NIL);
+        }
+        let len = line.trim_end_matches(&['\n', '\r'][..]).len();
+        line.truncate(len);
+        Ok(new_string(allocator, line))
+    });
+    vm.define_native("read_file", 1, |allocator, args| {
+        let path = string_arg(allocator, args, 0)?;
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read file \"{}\": {}.", path, e))?;
+        Ok(new_string(allocator, contents))
+    });
+    vm.define_native("write_file", 2, |allocator, args| {
+        let path = string_arg(allocator, args, 0)?;
+        let contents = string_arg(allocator, args, 1)?;
+        std::fs::write(path, contents)
+            .map_err(|e| format!("Could not write file \"{}\": {}.", path, e))?;
+        Ok(Value::NIL)
+    });
+}
@@ -2,16 +2,20 @@
 pub struct Token<'a> {
     pub typ: TokenType,
     pub line: usize,
+    pub column: usize,
     pub source: &'a str,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash)]
+#[derive(Debug, Default, Eq, PartialEq, Clone, Copy, Hash)]
 pub enum TokenType {
     // single-character tokens
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
     Minus,
@@ -37,23 +41,32 @@ pub enum TokenType {
 
     // keywords
     And,
+    As,
+    Break,
+    Catch,
     Class,
+    Continue,
     Else,
     False,
+    Finally,
     For,
     Fun,
     If,
+    Import,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
 
     Error,
+    #[default]
     Eof,
 }
 
@@ -62,9 +75,3 @@ impl std::fmt::Display for TokenType {
         write!(f, "{:?}", self)
     }
 }
-
-impl std::default::Default for TokenType {
-    fn default() -> Self {
-        TokenType::Eof
-    }
-}
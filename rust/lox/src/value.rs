@@ -1,59 +1,341 @@
-use crate::allocator::Reference;
+use crate::allocator::{Allocator, Reference};
+use crate::class::{BoundMethod, Class, Instance};
 use crate::function::{Closure, NativeFn};
+use crate::list::List;
+use crate::map::Map;
+use crate::module::ScriptModule;
 use crate::Function;
 
+// ValueKind is what a value holds, for matching on.
+// a Value is turned into one with `kind`, and made from one with `From`.
 #[derive(Clone, Copy, Debug, PartialEq)]
-pub enum Value {
+pub enum ValueKind {
     Bool(bool),
     Nil,
     Number(f64),
     String(Reference<String>),
     Function(Reference<Function>),
     Closure(Reference<Closure>),
-    NativeFn(NativeFn),
+    NativeFn(Reference<NativeFn>),
+    Class(Reference<Class>),
+    Instance(Reference<Instance>),
+    BoundMethod(Reference<BoundMethod>),
+    List(Reference<List>),
+    Map(Reference<Map>),
+    Module(Reference<ScriptModule>),
 }
 
+// Value is a lox value, which holds a ValueKind as it is.
+// with the `nan_boxing` feature it is packed into the 64 bits of a double instead.
+#[cfg(not(feature = "nan_boxing"))]
+#[derive(Clone, Copy, PartialEq)]
+pub struct Value(ValueKind);
+
+#[cfg(not(feature = "nan_boxing"))]
 impl Value {
+    pub const NIL: Value = Value(ValueKind::Nil);
+
+    pub fn number(n: f64) -> Self {
+        Value(ValueKind::Number(n))
+    }
+
+    pub fn bool(b: bool) -> Self {
+        Value(ValueKind::Bool(b))
+    }
+
+    #[inline]
+    pub fn kind(self) -> ValueKind {
+        self.0
+    }
+
     pub fn is_falsy(&self) -> bool {
-        match self {
-            Self::Bool(v) => !v.clone(),
-            Self::Nil => true,
-            _ => false,
+        matches!(self.0, ValueKind::Bool(false) | ValueKind::Nil)
+    }
+}
+
+#[cfg(not(feature = "nan_boxing"))]
+impl From<ValueKind> for Value {
+    fn from(kind: ValueKind) -> Self {
+        Value(kind)
+    }
+}
+
+// a double is a quiet NaN when all of these bits are set, and no arithmetic produces one
+// with anything else set, so the rest of the bits are free to hold the other values.
+#[cfg(feature = "nan_boxing")]
+const QUIET_NAN: u64 = 0x7ffc_0000_0000_0000;
+// objects have the sign bit set as well, and their kind and heap index in the low bits.
+#[cfg(feature = "nan_boxing")]
+const SIGN_BIT: u64 = 1 << 63;
+#[cfg(feature = "nan_boxing")]
+const OBJECT_TAG_SHIFT: u32 = 46;
+#[cfg(feature = "nan_boxing")]
+const OBJECT_INDEX_MASK: u64 = (1 << OBJECT_TAG_SHIFT) - 1;
+#[cfg(feature = "nan_boxing")]
+const NIL_BITS: u64 = QUIET_NAN | 1;
+#[cfg(feature = "nan_boxing")]
+const FALSE_BITS: u64 = QUIET_NAN | 2;
+#[cfg(feature = "nan_boxing")]
+const TRUE_BITS: u64 = QUIET_NAN | 3;
+
+// Value is a lox value packed into the 64 bits of a double.
+// a number is the double itself, and everything else is hidden in the payload of a NaN.
+#[cfg(feature = "nan_boxing")]
+#[derive(Clone, Copy)]
+pub struct Value(u64);
+
+#[cfg(feature = "nan_boxing")]
+impl Value {
+    pub const NIL: Value = Value(NIL_BITS);
+
+    pub fn number(n: f64) -> Self {
+        // NaNs the program made itself could look like another value, so there is only one.
+        if n.is_nan() {
+            return Value(f64::NAN.to_bits());
         }
+        Value(n.to_bits())
+    }
+
+    pub fn bool(b: bool) -> Self {
+        Value(if b { TRUE_BITS } else { FALSE_BITS })
+    }
+
+    fn object(tag: u64, index: usize) -> Self {
+        debug_assert!(index as u64 <= OBJECT_INDEX_MASK);
+        Value(SIGN_BIT | QUIET_NAN | tag << OBJECT_TAG_SHIFT | index as u64)
     }
 
+    fn is_number(self) -> bool {
+        self.0 & QUIET_NAN != QUIET_NAN
+    }
+
+    #[inline]
+    pub fn kind(self) -> ValueKind {
+        if self.is_number() {
+            return ValueKind::Number(f64::from_bits(self.0));
+        }
+        if self.0 & SIGN_BIT == 0 {
+            return match self.0 {
+                TRUE_BITS => ValueKind::Bool(true),
+                FALSE_BITS => ValueKind::Bool(false),
+                _ => ValueKind::Nil,
+            };
+        }
+        let index = (self.0 & OBJECT_INDEX_MASK) as usize;
+        match (self.0 & !(SIGN_BIT | QUIET_NAN)) >> OBJECT_TAG_SHIFT {
+            0 => ValueKind::String(Reference::from_index(index)),
+            1 => ValueKind::Function(Reference::from_index(index)),
+            2 => ValueKind::Closure(Reference::from_index(index)),
+            3 => ValueKind::NativeFn(Reference::from_index(index)),
+            4 => ValueKind::Class(Reference::from_index(index)),
+            5 => ValueKind::Instance(Reference::from_index(index)),
+            6 => ValueKind::BoundMethod(Reference::from_index(index)),
+            7 => ValueKind::List(Reference::from_index(index)),
+            8 => ValueKind::Map(Reference::from_index(index)),
+            _ => ValueKind::Module(Reference::from_index(index)),
+        }
+    }
+
+    pub fn is_falsy(&self) -> bool {
+        self.0 == NIL_BITS || self.0 == FALSE_BITS
+    }
+}
+
+#[cfg(feature = "nan_boxing")]
+impl From<ValueKind> for Value {
+    fn from(kind: ValueKind) -> Self {
+        match kind {
+            ValueKind::Bool(b) => Value::bool(b),
+            ValueKind::Nil => Value::NIL,
+            ValueKind::Number(n) => Value::number(n),
+            ValueKind::String(id) => Value::object(0, id.index()),
+            ValueKind::Function(id) => Value::object(1, id.index()),
+            ValueKind::Closure(id) => Value::object(2, id.index()),
+            ValueKind::NativeFn(id) => Value::object(3, id.index()),
+            ValueKind::Class(id) => Value::object(4, id.index()),
+            ValueKind::Instance(id) => Value::object(5, id.index()),
+            ValueKind::BoundMethod(id) => Value::object(6, id.index()),
+            ValueKind::List(id) => Value::object(7, id.index()),
+            ValueKind::Map(id) => Value::object(8, id.index()),
+            ValueKind::Module(id) => Value::object(9, id.index()),
+        }
+    }
+}
+
+#[cfg(feature = "nan_boxing")]
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        // numbers compare as doubles, so that NaN isn't equal to itself and 0 equals -0.
+        if self.is_number() && other.is_number() {
+            return f64::from_bits(self.0) == f64::from_bits(other.0);
+        }
+        self.0 == other.0
+    }
+}
+
+impl std::fmt::Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.kind())
+    }
+}
+
+macro_rules! from_reference {
+    ($($kind:ident),*) => {
+        $(
+            impl From<Reference<$kind>> for Value {
+                fn from(id: Reference<$kind>) -> Self {
+                    ValueKind::$kind(id).into()
+                }
+            }
+        )*
+    };
+}
+
+from_reference!(
+    String,
+    Function,
+    Closure,
+    NativeFn,
+    Class,
+    Instance,
+    BoundMethod,
+    List,
+    Map
+);
+
+impl From<Reference<ScriptModule>> for Value {
+    fn from(id: Reference<ScriptModule>) -> Self {
+        ValueKind::Module(id).into()
+    }
+}
+
+impl Value {
     pub fn as_number(&self) -> f64 {
-        match self {
-            Self::Number(v) => v.clone(),
+        match self.kind() {
+            ValueKind::Number(v) => v,
             _ => unreachable!(),
         }
     }
 
     pub fn as_bool(&self) -> bool {
-        match self {
-            Self::Bool(v) => v.clone(),
+        match self.kind() {
+            ValueKind::Bool(v) => v,
             _ => unreachable!(),
         }
     }
 
-    pub fn as_string(&self) -> &Reference<String> {
-        match self {
-            Self::String(v) => v,
+    pub fn as_string(&self) -> Reference<String> {
+        match self.kind() {
+            ValueKind::String(v) => v,
             _ => unreachable!(),
         }
     }
+
+    // display formats the value the way `print` shows it,
+    // looking up the objects it refers to in the heap.
+    pub fn display(self, allocator: &Allocator) -> ValueDisplay<'_> {
+        ValueDisplay {
+            value: self,
+            allocator,
+            depth: 0,
+        }
+    }
+}
+
+// collections nested deeper than this are elided, which also stops
+// a list that contains itself from being printed forever.
+const MAX_DISPLAY_DEPTH: usize = 16;
+
+pub struct ValueDisplay<'a> {
+    value: Value,
+    allocator: &'a Allocator,
+    // how many collections this value is nested in.
+    depth: usize,
+}
+
+impl ValueDisplay<'_> {
+    // nested formats a value held by the collection being formatted.
+    fn nested(&self, value: Value) -> Self {
+        ValueDisplay {
+            value,
+            allocator: self.allocator,
+            depth: self.depth + 1,
+        }
+    }
+}
+
+impl std::fmt::Display for ValueDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let allocator = self.allocator;
+        let fn_name = |func_id: &Reference<Function>| {
+            allocator.deref(&allocator.deref(func_id).name).as_str()
+        };
+        match self.value.kind() {
+            ValueKind::String(id) => write!(f, "{}", allocator.deref(&id)),
+            ValueKind::Function(id) => write!(f, "<fn {}>", fn_name(&id)),
+            ValueKind::Closure(id) => write!(f, "<fn {}>", fn_name(&allocator.deref(&id).func_id)),
+            ValueKind::NativeFn(id) => write!(
+                f,
+                "<native fn {}>",
+                allocator.deref(&allocator.deref(&id).name)
+            ),
+            ValueKind::Class(id) => write!(f, "{}", allocator.deref(&allocator.deref(&id).name)),
+            ValueKind::Instance(id) => {
+                let class = allocator.deref(&allocator.deref(&id).class);
+                write!(f, "{} instance", allocator.deref(&class.name))
+            }
+            ValueKind::BoundMethod(id) => {
+                let method = allocator.deref(&allocator.deref(&id).method);
+                write!(f, "<fn {}>", fn_name(&method.func_id))
+            }
+            ValueKind::List(id) => {
+                if self.depth >= MAX_DISPLAY_DEPTH {
+                    return write!(f, "[...]");
+                }
+                write!(f, "[")?;
+                for (i, &item) in allocator.deref(&id).items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", self.nested(item))?;
+                }
+                write!(f, "]")
+            }
+            ValueKind::Map(id) => {
+                if self.depth >= MAX_DISPLAY_DEPTH {
+                    return write!(f, "{{...}}");
+                }
+                write!(f, "{{")?;
+                for (i, &(k, v)) in allocator.deref(&id).iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", self.nested(k), self.nested(v))?;
+                }
+                write!(f, "}}")
+            }
+            ValueKind::Module(id) => write!(f, "<module {}>", allocator.deref(&id).path),
+            _ => write!(f, "{}", self.value),
+        }
+    }
 }
 
 impl std::fmt::Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Nil => write!(f, "nil"),
-            Self::Bool(v) => write!(f, "{}", v),
-            Self::Number(v) => write!(f, "{}", v),
-            Self::String(id) => write!(f, "<string {}>", id),
-            Self::Function(id) => write!(f, "<fn {}>", id),
-            Self::Closure(id) => write!(f, "<closure {}>", id),
-            Self::NativeFn(_) => write!(f, "<native fn>"),
+        match self.kind() {
+            ValueKind::Nil => write!(f, "nil"),
+            ValueKind::Bool(v) => write!(f, "{}", v),
+            ValueKind::Number(v) => write!(f, "{}", v),
+            ValueKind::String(id) => write!(f, "<string {}>", id),
+            ValueKind::Function(id) => write!(f, "<fn {}>", id),
+            ValueKind::Closure(id) => write!(f, "<closure {}>", id),
+            ValueKind::NativeFn(id) => write!(f, "<native fn {}>", id),
+            ValueKind::Class(id) => write!(f, "<class {}>", id),
+            ValueKind::Instance(id) => write!(f, "<instance {}>", id),
+            ValueKind::BoundMethod(id) => write!(f, "<bound method {}>", id),
+            ValueKind::List(id) => write!(f, "<list {}>", id),
+            ValueKind::Map(id) => write!(f, "<map {}>", id),
+            ValueKind::Module(id) => write!(f, "<module {}>", id),
         }
     }
 }
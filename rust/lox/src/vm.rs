@@ -1,60 +1,123 @@
-use crate::allocator::Table;
+use crate::allocator::{Roots, Trace};
+use crate::bytecode::{self, LoadError};
+#[cfg(feature = "debug_trace_execution")]
+use crate::chunk::disassemble_instruction;
 use crate::chunk::OpCode;
-use crate::function::{Closure, NativeFn};
-use crate::value::Value;
-use crate::{Allocator, Chunk, Parser, Reference};
-use std::collections::HashMap;
+use crate::class::{BoundMethod, Class, Instance};
+use crate::debugger::{Breakpoint, Debugger, Resume, Stop, StopReason};
+use crate::embed::{Error, FromValue, Handle, Handles, IntoValue};
+use crate::function::{Closure, NativeFn, Upvalue};
+use crate::globals::Globals;
+use crate::list::List;
+use crate::map::Map;
+use crate::module::{self, FileResolver, ModuleResolver, ScriptModule};
+use crate::optimizer::OptimizationLevel;
+use crate::stdlib::{self, Module};
+use crate::value::{Value, ValueKind};
+use crate::{Allocator, Chunk, CompileError, Function, Parser, Reference};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::io::Write;
+use std::mem;
+use std::rc::Rc;
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum InterpretResult {
     Ok,
-    CompileError(String),
-    RuntimeError,
+    CompileError(Vec<CompileError>),
+    RuntimeError(RuntimeError),
+    // the script was suspended by one of the limits set with `set_limits`,
+    // and may be carried on with `resume`.
+    LimitExceeded(Limit),
 }
 
+// Limits caps what a script may use, so that untrusted scripts can't hang the host
+// or exhaust its memory. nothing is limited by default.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Limits {
+    // how many instructions each `interpret` may execute.
+    pub fuel: Option<u64>,
+    // how many bytes the heap may hold, not counting garbage.
+    pub max_heap: Option<usize>,
+    // how many values the stack may hold, which deep recursion uses up.
+    pub max_stack: Option<usize>,
+}
+
+// Limit is the limit which suspended a script.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Limit {
+    Fuel,
+    Heap,
+    Stack,
+}
+
+impl std::fmt::Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Limit::Fuel => write!(f, "Ran out of fuel."),
+            Limit::Heap => write!(f, "Heap limit exceeded."),
+            Limit::Stack => write!(f, "Stack limit exceeded."),
+        }
+    }
+}
+
+// RuntimeError is an error raised while running a script,
+// along with where it happened and the calls that led there.
+#[derive(Debug, Eq, PartialEq)]
+pub struct RuntimeError {
+    pub message: String,
+    pub line: usize,
+    pub trace: Vec<TraceFrame>,
+}
+
+// TraceFrame is a single call frame in a stack trace, innermost first.
+#[derive(Debug, Eq, PartialEq)]
+pub struct TraceFrame {
+    pub function: String,
+    pub line: usize,
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+        for frame in &self.trace {
+            write!(f, "\n[line {}] in {}", frame.line, frame.function)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
 macro_rules! binary_op {
     ( $vm:ident, $constructor:expr, $op:tt ) => {
         {
 
-            match ($vm.pop(), $vm.pop()) {
-                (Value::Number(b), Value::Number(a)) => {
+            match ($vm.pop().kind(), $vm.pop().kind()) {
+                (ValueKind::Number(b), ValueKind::Number(a)) => {
                     $vm.push($constructor(a $op b));
                 }
                 _ => {
-                    eprintln!("Operand must be numbers.");
-                    return InterpretResult::RuntimeError;
+                    return Err("Operand must be numbers.".to_string().into());
                 }
             }
         }
     };
 }
 
-fn native_clock(_: &Allocator, _args: &[Value]) -> Value {
-    Value::Number(1234_f64)
-}
-
-fn native_max(_: &Allocator, args: &[Value]) -> Value {
-    if let Value::Number(a) = args[0] {
-        if let Value::Number(b) = args[1] {
-            return if a > b {
-                args[0].clone()
-            } else {
-                args[1].clone()
-            };
-        }
-    }
+const FRAMES_MAX: usize = 64;
 
-    panic!("panic: Operand must be numbers.");
+// Exception is what unwinds the stack: a value thrown by the script, or an error raised
+// by the VM, which becomes an instance of the `Error` class if a `try` statement catches it.
+enum Exception {
+    Thrown(Value),
+    Error(String),
 }
 
-fn native_panic(allocator: &Allocator, args: &[Value]) -> Value {
-    let arg = args[0];
-    let s = if let Value::String(s) = arg {
-        allocator.deref(&s)
-    } else {
-        "unknown"
-    };
-    panic!("panic: {}", s);
+impl From<String> for Exception {
+    fn from(message: String) -> Self {
+        Exception::Error(message)
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -62,6 +125,12 @@ pub struct CallFrame {
     pub closure_id: Reference<Closure>,
     ip: usize,
     slot: usize,
+    // the module whose globals the frame uses, copied from its closure.
+    module: Option<Reference<ScriptModule>>,
+    // the line the debugger last saw the frame run, and where the frame got to it.
+    // see `debug_line`.
+    line: Option<usize>,
+    line_start: usize,
 }
 
 impl CallFrame {
@@ -70,6 +139,9 @@ impl CallFrame {
             closure_id,
             ip: 0,
             slot: 0,
+            module: None,
+            line: None,
+            line_start: 0,
         }
     }
 }
@@ -77,216 +149,995 @@ impl CallFrame {
 pub struct VM {
     pub frames: Vec<CallFrame>,
     pub stack: Vec<Value>,
-    pub globals: Table,
+    pub globals: Globals,
     pub allocator: Allocator,
+    // upvalues still pointing at a stack slot, sorted by their location.
+    open_upvalues: Vec<Reference<Upvalue>>,
+    init_string: Reference<String>,
+    // the class of the errors raised by the VM, with their `message` and `line` as fields.
+    error_class: Reference<Class>,
+    // where `print` writes to.
+    output: Box<dyn Write>,
+    // the deepest the call stack may grow, including the frame of the script itself.
+    max_frames: usize,
+    limits: Limits,
+    // how many more instructions the script may execute, if that is limited.
+    fuel: Option<u64>,
+    optimization: OptimizationLevel,
+    // every module imported so far by its resolved path, see `import`.
+    modules: HashMap<String, Reference<ScriptModule>>,
+    // the modules whose scripts are running, along with the index of their frames.
+    importing: Vec<(usize, Reference<ScriptModule>)>,
+    // the natives, which every module gets as globals of its own.
+    natives: Vec<Reference<NativeFn>>,
+    resolver: Box<dyn ModuleResolver>,
+    // the path of the main script, which its imports are relative to.
+    script_path: String,
+    // the values which Rust holds onto, see `handle`.
+    handles: Rc<RefCell<Handles>>,
+    // whether the bottom frame runs a function called from Rust rather than a script.
+    calling: bool,
+    debugger: Option<Box<dyn Debugger>>,
+    // the lines which the debugger stops at.
+    breakpoints: BTreeSet<Breakpoint>,
+    // where the debugger stops next, unless a breakpoint comes first.
+    stepping: Option<Stepping>,
+}
+
+// Stepping is a step the debugger asked for, along with how many frames there were
+// when it did, as stepping over or out of a function depends on the frames it returns to.
+#[derive(Copy, Clone)]
+enum Stepping {
+    In,
+    Over(usize),
+    Out(usize),
+}
+
+// VMRoots borrows everything the VM holds onto outside of the heap, except for the globals
+// which the compiler is compiling against, as it needs to borrow them mutably at the same time.
+struct VMRoots<'v> {
+    stack: &'v [Value],
+    frames: &'v [CallFrame],
+    open_upvalues: &'v [Reference<Upvalue>],
+    init_string: Reference<String>,
+    error_class: Reference<Class>,
+    // the main script's globals while a module is compiled against its own.
+    globals: Option<&'v Globals>,
+    modules: &'v HashMap<String, Reference<ScriptModule>>,
+    natives: &'v [Reference<NativeFn>],
+    handles: &'v RefCell<Handles>,
+}
+
+impl Roots for VMRoots<'_> {
+    fn mark_roots(&self, allocator: &mut Allocator) {
+        for &v in self.stack {
+            allocator.mark_value(v);
+        }
+        for frame in self.frames {
+            allocator.mark_object(frame.closure_id);
+        }
+        for &upvalue in self.open_upvalues {
+            allocator.mark_object(upvalue);
+        }
+        allocator.mark_object(self.init_string);
+        allocator.mark_object(self.error_class);
+        if let Some(globals) = self.globals {
+            globals.mark(allocator);
+        }
+        for &module in self.modules.values() {
+            allocator.mark_object(module);
+        }
+        for &native in self.natives {
+            allocator.mark_object(native);
+        }
+        self.handles.borrow().mark(allocator);
+    }
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl VM {
+    // new creates a VM with the whole standard library loaded.
     pub fn new() -> Self {
+        Self::with_modules(&Module::ALL)
+    }
+
+    // with_modules creates a VM with only the given standard library modules loaded.
+    pub fn with_modules(modules: &[Module]) -> Self {
+        let mut allocator = Allocator::default();
+        let init_string = allocator.new_string("init".to_owned());
+        let error_name = allocator.new_string("Error".to_owned());
+        let error_class = allocator.alloc(Class::new(error_name));
+
         let mut vm = Self {
             frames: vec![],
             stack: vec![],
             globals: Default::default(),
-            allocator: Default::default(),
+            allocator,
+            open_upvalues: vec![],
+            init_string,
+            error_class,
+            output: Box::new(std::io::stdout()),
+            max_frames: FRAMES_MAX,
+            limits: Limits::default(),
+            fuel: None,
+            optimization: OptimizationLevel::None,
+            modules: HashMap::new(),
+            importing: vec![],
+            natives: vec![],
+            resolver: Box::new(FileResolver),
+            script_path: String::new(),
+            handles: Rc::default(),
+            calling: false,
+            debugger: None,
+            breakpoints: BTreeSet::new(),
+            stepping: None,
         };
 
-        vm.define_native("clock".to_string(), NativeFn(native_clock));
-        vm.define_native("max".to_string(), NativeFn(native_max));
-        vm.define_native("panic".to_string(), NativeFn(native_panic));
+        stdlib::load_core(&mut vm);
+        for &module in modules {
+            vm.load_module(module);
+        }
 
         vm
     }
 
+    // load_module makes the natives of a standard library module available to scripts.
+    pub fn load_module(&mut self, module: Module) {
+        stdlib::load(self, module);
+    }
+
     pub fn interpret(&mut self, src: &str) -> InterpretResult {
-        let mut parser = Parser::new(&mut self.allocator);
+        match self.compile(src) {
+            Ok(func_id) => self.execute(func_id),
+            Err(msg) => InterpretResult::CompileError(msg),
+        }
+    }
 
-        let func_id = match parser.compile(src) {
-            Ok(func_id) => func_id,
-            Err(msg) => return InterpretResult::CompileError(msg),
+    // execute runs a script function, either compiled or loaded from bytecode.
+    // a script which a limit suspended and which wasn't resumed is abandoned.
+    pub fn execute(&mut self, func_id: Reference<Function>) -> InterpretResult {
+        self.reset();
+        self.fuel = self.limits.fuel;
+        self.calling = false;
+        self.push(Value::from(func_id));
+        let closure_id = self.alloc(Closure::new(func_id));
+        self.frames.push(CallFrame::new(closure_id));
+
+        let result = self.run();
+        self.finish(result)
+    }
+
+    // resume carries on with a script which a limit suspended, e.g. after topping up its fuel
+    // with `add_fuel` or raising the limits. it does nothing if no script is suspended.
+    pub fn resume(&mut self) -> InterpretResult {
+        if self.frames.is_empty() {
+            return InterpretResult::Ok;
+        }
+        let result = self.run();
+        self.finish(result)
+    }
+
+    fn finish(&mut self, result: Result<Option<Limit>, String>) -> InterpretResult {
+        match result {
+            Ok(None) => {
+                self.pop(); // what the script returned
+                InterpretResult::Ok
+            }
+            Ok(Some(limit)) => InterpretResult::LimitExceeded(limit),
+            Err(message) => {
+                let err = self.runtime_error(message);
+                self.reset();
+                self.stepping = None;
+                InterpretResult::RuntimeError(err)
+            }
+        }
+    }
+
+    // compile turns the source into the function for its top-level script without running it.
+    // nothing else holds onto the function, so it may be swept by the next collection.
+    pub fn compile(&mut self, src: &str) -> Result<Reference<Function>, Vec<CompileError>> {
+        let optimization = self.optimization;
+        let (allocator, globals, roots) = self.split_roots();
+        let mut parser = Parser::new(allocator, globals);
+        parser.set_roots(&roots);
+        parser.set_optimization_level(optimization);
+        parser.compile(src)
+    }
+
+    // load reads a script compiled with `bytecode::serialize` without running it,
+    // so that it doesn't need to be compiled again.
+    pub fn load(&mut self, bytes: &[u8]) -> Result<Reference<Function>, LoadError> {
+        bytecode::deserialize(&mut self.allocator, &mut self.globals, bytes)
+    }
+
+    // set_optimization_level sets how scripts compiled from then on are optimized.
+    pub fn set_optimization_level(&mut self, level: OptimizationLevel) {
+        self.optimization = level;
+    }
+
+    // set_max_frames limits how deeply calls may nest before raising "Stack overflow.".
+    pub fn set_max_frames(&mut self, max_frames: usize) {
+        self.max_frames = max_frames;
+    }
+
+    // call calls the global function of the main script with the given name, and returns
+    // what it returns. a script which a limit suspended is abandoned, and so is the call
+    // if a limit suspends it in turn.
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Value, Error> {
+        let callee = self.get_global(name)?;
+        self.apply(callee, args)
+    }
+
+    // apply calls any value which scripts can call, e.g. a function which one returned.
+    pub fn apply(&mut self, callee: Value, args: &[Value]) -> Result<Value, Error> {
+        self.reset();
+        self.fuel = self.limits.fuel;
+        self.calling = true;
+        self.push(callee);
+        self.stack.extend_from_slice(args);
+
+        let result = match self.call_value(args.len()) {
+            // natives, and classes without an initializer, return right away.
+            Ok(()) if self.frames.is_empty() => Ok(None),
+            Ok(()) => self.run(),
+            Err(message) => Err(message),
         };
+        self.stepping = None;
+        match result {
+            Ok(None) => Ok(self.pop()),
+            Ok(Some(limit)) => {
+                self.reset();
+                Err(Error::LimitExceeded(limit))
+            }
+            Err(message) => {
+                let err = self.runtime_error(message);
+                self.reset();
+                Err(Error::Runtime(err))
+            }
+        }
+    }
 
-        self.push(Value::Function(func_id));
-        let closure_id = self.allocator.alloc(Closure::new(func_id));
-        self.frames.push(CallFrame::new(closure_id));
+    // get_global converts the value of a global variable of the main script into a Rust type.
+    pub fn get_global<T: FromValue>(&self, name: &str) -> Result<T, Error> {
+        let value = self
+            .allocator
+            .find_string(name)
+            .and_then(|key| self.globals.get(&key).copied())
+            .ok_or_else(|| Error::UndefinedVariable(name.to_owned()))?;
+        T::from_value(value, self)
+    }
 
-        let ret = self.run();
+    // set_global defines a global variable of the main script, or replaces its value.
+    pub fn set_global<T: IntoValue>(&mut self, name: &str, value: T) {
+        let value = value.into_value(self);
+        // keeps the value alive in case interning the name triggers a collection.
+        self.push(value);
+        let name = self.intern(name.to_owned());
+        self.pop();
+        self.globals.insert(name, value);
+    }
+
+    // handle keeps a value alive until the handle is dropped.
+    pub fn handle<T: IntoValue>(&mut self, value: T) -> Handle {
+        let value = value.into_value(self);
+        self.root(value)
+    }
+
+    pub(crate) fn root(&self, value: Value) -> Handle {
+        Handle::new(value, &self.handles)
+    }
+
+    // set_limits sets what scripts may use from then on, refilling their fuel.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+        self.fuel = limits.fuel;
+        self.allocator.set_max_heap(limits.max_heap);
+    }
+
+    // fuel is how many more instructions the script may execute,
+    // or None if that isn't limited.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    // add_fuel lets the script execute more instructions, e.g. before resuming
+    // one which ran out. it does nothing if the fuel isn't limited.
+    pub fn add_fuel(&mut self, fuel: u64) {
+        if let Some(remaining) = &mut self.fuel {
+            *remaining = remaining.saturating_add(fuel);
+        }
+    }
+
+    // set_output redirects what `print` writes, which goes to stdout by default.
+    pub fn set_output<W: Write + 'static>(&mut self, output: W) {
+        self.output = Box::new(output);
+    }
+
+    // set_resolver changes where imported modules are loaded from, which is the filesystem
+    // by default.
+    pub fn set_resolver<R: ModuleResolver + 'static>(&mut self, resolver: R) {
+        self.resolver = Box::new(resolver);
+    }
+
+    // set_script_path sets the path of the main script, which its imports are relative to.
+    // without one they are relative to the working directory.
+    pub fn set_script_path(&mut self, path: &str) {
+        self.script_path = path.to_owned();
+    }
+
+    // set_debugger has the debugger told whenever the script stops at a breakpoint or a step.
+    pub fn set_debugger<D: Debugger + 'static>(&mut self, debugger: D) {
+        self.debugger = Some(Box::new(debugger));
+    }
+
+    pub fn remove_debugger(&mut self) {
+        self.debugger = None;
+    }
+
+    // set_breakpoint stops the script before it runs the given line of the main script
+    // or of a module, see `Breakpoint` for their paths.
+    pub fn set_breakpoint(&mut self, path: &str, line: usize) {
+        self.breakpoints.insert(Breakpoint::new(path, line));
+    }
+
+    pub fn clear_breakpoint(&mut self, path: &str, line: usize) {
+        self.breakpoints.remove(&Breakpoint::new(path, line));
+    }
+
+    // pause stops the script at the next line it runs, e.g. the first one of the next script.
+    pub fn pause(&mut self) {
+        self.stepping = Some(Stepping::In);
+    }
+
+    // backtrace describes where each frame of a stopped or suspended script is,
+    // innermost first.
+    pub fn backtrace(&self) -> Vec<TraceFrame> {
+        // the top frame hasn't run the instruction its ip is at yet.
+        self.trace(0)
+    }
+
+    // locals returns the local variables in scope in a frame of a stopped or suspended script,
+    // `depth` frames down from the top one, innermost last.
+    pub fn locals(&self, depth: usize) -> Vec<(String, Value)> {
+        let index = match self.frames.len().checked_sub(depth + 1) {
+            Some(index) => index,
+            None => return vec![],
+        };
+        let frame = &self.frames[index];
+        let closure = self.allocator.deref(&frame.closure_id);
+        let function = self.allocator.deref(&closure.func_id);
+        // the frames below the top one are just past the call they made.
+        let offset = if depth == 0 { frame.ip } else { frame.ip - 1 };
+        // a local still in scope has been popped already after `break` or `continue`.
+        let top = self
+            .frames
+            .get(index + 1)
+            .map_or(self.stack.len(), |above| above.slot);
+        function
+            .chunk
+            .locals_at(offset)
+            .filter(|local| frame.slot + local.slot < top)
+            .map(|local| {
+                let name = self.allocator.deref(&local.name).clone();
+                (name, self.stack[frame.slot + local.slot])
+            })
+            .collect()
+    }
+
+    // local looks up a local variable by name, as the code of the frame would see it.
+    pub fn local(&self, depth: usize, name: &str) -> Option<Value> {
+        self.locals(depth)
+            .into_iter()
+            .rev()
+            .find(|(local, _)| local == name)
+            .map(|(_, value)| value)
+    }
+
+    // global looks up a global variable by name in the globals the frame uses,
+    // which are those of its module when it runs the code of one.
+    pub fn global(&self, depth: usize, name: &str) -> Option<Value> {
+        let index = self.frames.len().checked_sub(depth + 1)?;
+        let globals = match self.frames[index].module {
+            Some(module_id) => &self.allocator.deref(&module_id).globals,
+            None => &self.globals,
+        };
+        let key = self.allocator.find_string(name)?;
+        globals.get(&key).copied()
+    }
+
+    // the path of the file whose code the frame runs, see `Breakpoint`.
+    fn frame_path(&self, frame: &CallFrame) -> &str {
+        match frame.module {
+            Some(module_id) => &self.allocator.deref(&module_id).path,
+            None => &self.script_path,
+        }
+    }
+
+    // builds the error from the frames which were active when it was raised.
+    fn runtime_error(&self, message: String) -> RuntimeError {
+        // the ip has already moved past the failing instruction.
+        let trace = self.trace(1);
+        RuntimeError {
+            message,
+            line: trace.first().map_or(0, |frame| frame.line),
+            trace,
+        }
+    }
+
+    // trace describes the frames, innermost first. the top one is `top_back` instructions
+    // ahead of where it is, while the others are always just past the call they made.
+    fn trace(&self, top_back: usize) -> Vec<TraceFrame> {
+        let top = self.frames.len().saturating_sub(1);
+        self.frames
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, frame)| {
+                let closure = self.allocator.deref(&frame.closure_id);
+                let function = self.allocator.deref(&closure.func_id);
+                // the bottom frame always runs the top-level script,
+                // and imported modules run theirs in frames of their own.
+                let module = self.importing.iter().find(|&&(frame, _)| frame == i);
+                let name = match (i, module) {
+                    (0, _) if !self.calling => "script".to_owned(),
+                    (_, Some((_, module_id))) => self.allocator.deref(module_id).path.clone(),
+                    _ => format!("{}()", self.allocator.deref(&function.name)),
+                };
+                let back = if i == top { top_back } else { 1 };
+                TraceFrame {
+                    function: name,
+                    line: function.chunk.line(frame.ip - back),
+                }
+            })
+            .collect()
+    }
+
+    // unwind hands the exception to the innermost handler covering where a frame is,
+    // going from the top frame down. the frames above the handler's are discarded,
+    // and its frame carries on at the handler with the thrown value on the stack.
+    // when nothing catches the exception, the frames are left for the stack trace.
+    fn unwind(&mut self, exception: Exception) -> Result<(), String> {
+        let caught = self.frames.iter().enumerate().rev().find_map(|(i, frame)| {
+            let closure = self.allocator.deref(&frame.closure_id);
+            let function = self.allocator.deref(&closure.func_id);
+            // the ip has already moved past the instruction which threw or made the call.
+            function
+                .chunk
+                .handler(frame.ip - 1)
+                .map(|handler| (i, handler))
+        });
+        let (frame_index, handler) = match caught {
+            Some(caught) => caught,
+            None => return Err(self.uncaught_message(exception)),
+        };
+
+        let value = match exception {
+            Exception::Thrown(value) => value,
+            Exception::Error(message) => self.error_value(message),
+        };
+        self.frames.truncate(frame_index + 1);
+        self.abandon_imports();
+        let frame = self.current_frame_mut();
+        frame.ip = handler.target;
+        let depth = frame.slot + handler.depth;
+        self.close_upvalues(depth);
+        self.stack.truncate(depth);
+        self.push(value);
+        Ok(())
+    }
+
+    // error_value makes the `Error` instance which is caught in place of an error
+    // raised by the VM, with the line of the instruction which raised it.
+    fn error_value(&mut self, message: String) -> Value {
+        let frame = self.current_frame();
+        let line = self.current_chunk().line(frame.ip - 1);
+
+        // everything is kept on the stack in case one of the allocations triggers a collection.
+        let message = self.intern(message);
+        self.push(Value::from(message));
+        let message_key = self.intern("message".to_owned());
+        self.push(Value::from(message_key));
+        let line_key = self.intern("line".to_owned());
+        self.push(Value::from(line_key));
+        let instance_id = self.alloc(Instance::new(self.error_class));
+        self.stack.truncate(self.stack.len() - 3);
+
+        let fields = &mut self.allocator.deref_mut(&instance_id).fields;
+        fields.insert(message_key, Value::from(message));
+        fields.insert(line_key, Value::number(line as f64));
+        Value::from(instance_id)
+    }
+
+    // uncaught_message is the message of the runtime error for an exception nothing caught.
+    // an error raised by the VM keeps its message even if it was caught and thrown again.
+    fn uncaught_message(&self, exception: Exception) -> String {
+        let value = match exception {
+            Exception::Error(message) => return message,
+            Exception::Thrown(value) => value,
+        };
+        if let ValueKind::Instance(instance_id) = value.kind() {
+            let instance = self.allocator.deref(&instance_id);
+            let message = instance
+                .fields
+                .iter()
+                .find(|(key, _)| self.allocator.deref(*key) == "message")
+                .map(|(_, message)| message.kind());
+            if let (true, Some(ValueKind::String(message))) =
+                (instance.class == self.error_class, message)
+            {
+                return self.allocator.deref(&message).clone();
+            }
+        }
+        format!("Uncaught exception: {}", value.display(&self.allocator))
+    }
+
+    // discards whatever the failed script left behind, keeping globals,
+    // so that the VM can go on interpreting.
+    fn reset(&mut self) {
+        self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
+        self.abandon_imports();
+    }
+
+    // forgets the modules whose scripts were discarded before they finished running,
+    // so that importing one again runs it again rather than handing out part of it.
+    fn abandon_imports(&mut self) {
+        while let Some(&(frame, module_id)) = self.importing.last() {
+            if frame < self.frames.len() {
+                break;
+            }
+            self.importing.pop();
+            self.modules.remove(&self.allocator.deref(&module_id).path);
+        }
+    }
+
+    // run executes the frames until the script returns or a limit suspends it,
+    // letting `try` statements catch what is thrown on the way.
+    // it fails with whatever none of them caught.
+    fn run(&mut self) -> Result<Option<Limit>, String> {
+        loop {
+            match self.dispatch() {
+                Ok(limit) => return Ok(limit),
+                Err(exception) => self.unwind(exception)?,
+            }
+        }
+    }
+
+    // exceeded_limit checks the limits before each instruction, which uses up a unit of fuel
+    // if none of them suspends the script. the frames are left as they are for `resume`.
+    fn exceeded_limit(&mut self) -> Option<Limit> {
+        if matches!(self.limits.max_stack, Some(max) if self.stack.len() > max) {
+            return Some(Limit::Stack);
+        }
+        if self.allocator.exceeds_max_heap() {
+            self.collect_garbage();
+            if self.allocator.exceeds_max_heap() {
+                return Some(Limit::Heap);
+            }
+        }
+        match &mut self.fuel {
+            Some(0) => Some(Limit::Fuel),
+            Some(fuel) => {
+                *fuel -= 1;
+                None
+            }
+            None => None,
+        }
+    }
 
-        println!("== VM ==");
-        println!("== globals ==");
-        for (k, v) in &self.globals {
-            println!("{:?}: {:?}", k, v);
+    // debug_line stops the script for the debugger when the top frame is about to run
+    // a line other than the one it last ran, and a breakpoint or the step asks for it.
+    fn debug_line(&mut self) {
+        let frame = self.current_frame();
+        let line = self.current_chunk().line(frame.ip);
+        if frame.line == Some(line) {
+            return;
         }
+        let frame = self.current_frame_mut();
+        frame.line = Some(line);
+        frame.line_start = frame.ip;
+
+        let depth = self.frames.len();
+        let breakpoint = Breakpoint::new(self.frame_path(self.current_frame()), line);
+        let reason = if self.breakpoints.contains(&breakpoint) {
+            StopReason::Breakpoint
+        } else {
+            match self.stepping {
+                Some(Stepping::In) => StopReason::Step,
+                Some(Stepping::Over(from)) if depth <= from => StopReason::Step,
+                Some(Stepping::Out(from)) if depth < from => StopReason::Step,
+                _ => return,
+            }
+        };
 
-        ret
+        // the debugger and the breakpoints are taken out while it looks into the VM.
+        let mut debugger = self.debugger.take().expect("debugger is set");
+        let mut breakpoints = mem::take(&mut self.breakpoints);
+        let resume = debugger.stopped(&mut Stop {
+            reason,
+            path: self.frame_path(self.current_frame()),
+            line,
+            vm: self,
+            breakpoints: &mut breakpoints,
+        });
+        self.debugger = Some(debugger);
+        self.breakpoints = breakpoints;
+        self.stepping = match resume {
+            Resume::Continue => None,
+            Resume::StepIn => Some(Stepping::In),
+            Resume::StepOver => Some(Stepping::Over(depth)),
+            Resume::StepOut => Some(Stepping::Out(depth)),
+        };
     }
 
-    // dispatch instructions
-    fn run(&mut self) -> InterpretResult {
+    // dispatch instructions until the script returns, or a limit suspends it.
+    fn dispatch(&mut self) -> Result<Option<Limit>, Exception> {
         loop {
-            let instruction = self.current_chunk().instructions[self.current_frame().ip];
+            if let Some(limit) = self.exceeded_limit() {
+                return Ok(Some(limit));
+            }
+            if self.debugger.is_some() {
+                self.debug_line();
+            }
+            let ip = self.current_frame().ip;
+            let (instruction, next) = self.current_chunk().read(ip);
+            #[cfg(feature = "debug_trace_execution")]
             {
-                print!("id: {} ", self.current_frame().closure_id);
-                for value in self.stack.iter() {
-                    print!("[{}]", value);
+                print!("          ");
+                for &value in self.stack.iter() {
+                    print!("[ {} ]", value.display(&self.allocator));
                 }
                 println!();
+                disassemble_instruction(self.current_chunk(), ip);
             }
-            self.current_frame_mut().ip += 1;
+            self.current_frame_mut().ip = next;
 
             match instruction {
                 OpCode::Return => {
                     let value = self.pop();
                     let frame = self.frames.pop().unwrap();
 
+                    self.close_upvalues(frame.slot);
+                    self.stack.truncate(frame.slot);
+
+                    // what the bottom frame returns is left for whoever called into the VM.
                     if self.frames.is_empty() {
-                        return InterpretResult::Ok;
+                        self.stepping = None;
+                        self.push(value);
+                        return Ok(None);
+                    }
+                    // a module has been imported once its script returns.
+                    if let Some(&(frame, _)) = self.importing.last() {
+                        if frame == self.frames.len() {
+                            self.importing.pop();
+                        }
                     }
 
-                    self.stack.truncate(frame.slot);
                     self.push(value);
                 }
                 OpCode::Print => {
-                    print!("{}\n", self.pop());
+                    let value = self.pop();
+                    writeln!(self.output, "{}", value.display(&self.allocator))
+                        .map_err(|e| format!("Failed to print: {}.", e))?;
                 }
                 OpCode::JumpIfFalse(offset) => {
                     if self.peek(0).is_falsy() {
                         self.current_frame_mut().ip += offset;
                     }
                 }
+                OpCode::JumpIfTrue(offset) => {
+                    if !self.peek(0).is_falsy() {
+                        self.current_frame_mut().ip += offset;
+                    }
+                }
                 OpCode::Jump(offset) => {
                     self.current_frame_mut().ip += offset;
                 }
                 OpCode::Loop(offset) => {
-                    self.current_frame_mut().ip -= offset;
+                    let frame = self.current_frame_mut();
+                    frame.ip -= offset;
+                    // going back to where the line started is another iteration of a loop
+                    // on a single line, which stops there again. the increment of a `for`
+                    // loop jumping back to its condition on the same line doesn't.
+                    if frame.ip >= frame.line_start {
+                        frame.line = None;
+                    }
                 }
                 OpCode::Pop => {
                     self.pop(); // discard the result
                 }
-                OpCode::GetGlobal(index) => {
-                    let str_id = self.current_chunk().read_string(index);
-                    let v = match self.globals.get(str_id) {
-                        Some(v) => v.clone(),
-                        None => {
-                            eprintln!("Undefined global variable: '{}'.", str_id);
-                            return InterpretResult::RuntimeError;
-                        }
+                OpCode::GetGlobal(slot) => match self.globals().get_slot(slot) {
+                    Some(v) => self.push(v),
+                    None => return Err(self.undefined_global(slot).into()),
+                },
+                OpCode::SetGlobal(slot) => {
+                    if self.globals().get_slot(slot).is_none() {
+                        return Err(self.undefined_global(slot).into());
+                    }
+                    let v = *self.peek(0);
+                    self.globals_mut().set_slot(slot, v);
+                }
+                OpCode::DefineGlobal(slot) => {
+                    let v = self.pop();
+                    self.globals_mut().set_slot(slot, v);
+                    // a module's globals are part of the module object.
+                    if let Some(module_id) = self.current_frame().module {
+                        self.allocator.resize(&module_id);
+                    }
+                }
+                OpCode::GetLocal(index) => {
+                    let v = *self.get(index + self.current_frame().slot);
+                    self.push(v);
+                }
+                OpCode::SetLocal(index) => {
+                    let slot = index + self.current_frame().slot;
+                    self.stack[slot] = *self.peek(0);
+                }
+                OpCode::GetUpvalue(index) => {
+                    let upvalue_id = self.current_closure().upvalues[index];
+                    let upvalue = self.allocator.deref(&upvalue_id);
+                    let v = match upvalue.closed {
+                        Some(v) => v,
+                        None => *self.get(upvalue.location),
                     };
                     self.push(v);
                 }
-                OpCode::SetGlobal(index) => {
-                    let str_id = self.current_chunk().read_string(index);
-                    match self.globals.get(str_id) {
-                        Some(_) => {
-                            self.globals.insert(str_id.clone(), self.peek(0).clone());
-                        }
+                OpCode::SetUpvalue(index) => {
+                    let upvalue_id = self.current_closure().upvalues[index];
+                    let v = *self.peek(0);
+                    let upvalue = self.allocator.deref_mut(&upvalue_id);
+                    match upvalue.closed {
+                        Some(_) => upvalue.closed = Some(v),
                         None => {
-                            let str_id = str_id.clone();
-                            self.globals.remove(&str_id);
-                            eprintln!("Undefined global variable: '{}'.", str_id);
-                            return InterpretResult::RuntimeError;
+                            let location = upvalue.location;
+                            self.stack[location] = v;
                         }
                     }
                 }
-                OpCode::DefineGlobal(index) => {
-                    let str_id = self.current_chunk().read_string(index);
-                    self.globals.insert(str_id.clone(), self.peek(0).clone());
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
                     self.pop();
                 }
-                OpCode::GetLocal(index) => {
-                    let v = self.get(index + self.current_frame().slot).clone();
-                    self.push(v);
-                }
-                OpCode::SetLocal(index) => {
-                    self.stack
-                        .insert(index + self.current_frame().slot, self.peek(0).clone());
-                }
                 OpCode::Constant(index) => {
-                    let v = self.current_chunk().values[index].clone();
+                    let v = self.current_chunk().values[index];
                     self.push(v);
                 }
                 OpCode::Call(arg_num) => {
-                    let callee = self.peek(arg_num);
+                    self.call_value(arg_num)?;
+                }
+                OpCode::Class(index) => {
+                    let name = self.current_chunk().read_string(index);
+                    let class_id = self.alloc(Class::new(name));
+                    self.push(Value::from(class_id));
+                }
+                OpCode::GetProperty(index) => {
+                    let name = self.current_chunk().read_string(index);
+                    match self.peek(0).kind() {
+                        ValueKind::Instance(instance_id) => {
+                            let instance = self.allocator.deref(&instance_id);
 
-                    match callee {
-                        Value::Closure(_) => {
-                            self.frames.push(self.call(arg_num));
+                            // fields shadow methods.
+                            if let Some(&v) = instance.fields.get(&name) {
+                                self.pop();
+                                self.push(v);
+                            } else {
+                                self.bind_method(instance.class, name)?;
+                            }
+                        }
+                        ValueKind::Module(module_id) => {
+                            let v = self.module_variable(module_id, name)?;
+                            self.pop();
+                            self.push(v);
                         }
-                        Value::NativeFn(_) => {
-                            self.call_native_fn(arg_num);
+                        _ => {
+                            return Err("Only instances have properties.".to_string().into());
+                        }
+                    }
+                }
+                OpCode::SetProperty(index) => {
+                    let instance_id = match self.peek(1).kind() {
+                        ValueKind::Instance(instance_id) => instance_id,
+                        _ => {
+                            return Err("Only instances have fields.".to_string().into());
                         }
+                    };
+                    let name = self.current_chunk().read_string(index);
+                    let v = self.pop();
+                    let instance = self.allocator.deref_mut(&instance_id);
+                    instance.fields.insert(name, v);
+                    self.allocator.resize(&instance_id);
+                    self.pop(); // instance
+                    self.push(v);
+                }
+                OpCode::Method(index) => {
+                    let name = self.current_chunk().read_string(index);
+                    let method = self.pop();
+                    if let ValueKind::Class(class_id) = self.peek(0).kind() {
+                        let class = self.allocator.deref_mut(&class_id);
+                        class.methods.insert(name, method);
+                        self.allocator.resize(&class_id);
+                    }
+                }
+                OpCode::Inherit => {
+                    let superclass_id = match self.peek(1).kind() {
+                        ValueKind::Class(class_id) => class_id,
                         _ => {
-                            eprintln!("Operand must be a closure or native function.");
-                            return InterpretResult::RuntimeError;
+                            return Err("Superclass must be a class.".to_string().into());
                         }
+                    };
+                    if let ValueKind::Class(subclass_id) = self.pop().kind() {
+                        // copy down the inherited methods. the subclass's own methods
+                        // are defined afterwards and override them.
+                        let methods = self.allocator.deref(&superclass_id).methods.clone();
+                        self.allocator
+                            .deref_mut(&subclass_id)
+                            .methods
+                            .extend(methods);
+                        self.allocator.resize(&subclass_id);
                     }
                 }
+                OpCode::GetSuper(index) => {
+                    let name = self.current_chunk().read_string(index);
+                    if let ValueKind::Class(superclass_id) = self.pop().kind() {
+                        self.bind_method(superclass_id, name)?;
+                    }
+                }
+                OpCode::SuperInvoke(index, arg_num) => {
+                    let name = self.current_chunk().read_string(index);
+                    // only corrupt bytecode has anything else in place of the superclass.
+                    match self.pop().kind() {
+                        ValueKind::Class(superclass_id) => {
+                            self.invoke_from_class(superclass_id, name, arg_num)?;
+                        }
+                        _ => return Err("Superclass must be a class.".to_string().into()),
+                    }
+                }
+                OpCode::Invoke(index, arg_num) => {
+                    let name = self.current_chunk().read_string(index);
+                    self.invoke(name, arg_num)?;
+                }
                 OpCode::Closure(index) => {
-                    let func_id = match self.current_chunk().values[index].clone() {
-                        Value::Function(func_id) => func_id,
+                    let func_id = match self.current_chunk().values[index].kind() {
+                        ValueKind::Function(func_id) => func_id,
                         _ => {
-                            eprintln!("Value must be a function.");
-                            return InterpretResult::RuntimeError;
+                            return Err("Value must be a function.".to_string().into());
                         }
                     };
 
-                    let closure = Closure::new(func_id);
-                    let closure_id = self.allocator.alloc(closure);
-                    self.push(Value::Closure(closure_id));
+                    let mut closure = Closure::new(func_id);
+                    closure.module = self.current_frame().module;
+                    let upvalues = self.allocator.deref(&func_id).upvalues.clone();
+                    for upvalue in upvalues {
+                        let upvalue_id = if upvalue.is_local {
+                            self.capture_upvalue(self.current_frame().slot + upvalue.index)
+                        } else {
+                            self.current_closure().upvalues[upvalue.index]
+                        };
+                        closure.upvalues.push(upvalue_id);
+                    }
+                    let closure_id = self.alloc(closure);
+                    self.push(Value::from(closure_id));
+                }
+                OpCode::BuildList(item_num) => {
+                    // the items stay on the stack while the list is allocated,
+                    // so that a collection can't free them in between.
+                    let first = self.stack.len() - item_num;
+                    let items = self.stack[first..].to_vec();
+                    let list_id = self.alloc(List::new(items));
+                    self.stack.truncate(first);
+                    self.push(Value::from(list_id));
                 }
-                OpCode::Nil => self.push(Value::Nil),
-                OpCode::True => self.push(Value::Bool(true)),
-                OpCode::False => self.push(Value::Bool(false)),
+                OpCode::BuildMap(entry_num) => {
+                    // like the items of a list, the keys and values stay on the stack
+                    // until the map holding them has been allocated.
+                    let first = self.stack.len() - entry_num * 2;
+                    let map_id = self.alloc(Map::new());
+                    let map = self.allocator.deref_mut(&map_id);
+                    for entry in self.stack[first..].chunks(2) {
+                        map.insert(entry[0], entry[1])?;
+                    }
+                    self.allocator.resize(&map_id);
+                    self.stack.truncate(first);
+                    self.push(Value::from(map_id));
+                }
+                OpCode::GetIndex => {
+                    let index = self.pop();
+                    let v = match self.pop().kind() {
+                        ValueKind::List(list_id) => {
+                            let list = self.allocator.deref(&list_id);
+                            list.items[list.index(index)?]
+                        }
+                        ValueKind::Map(map_id) => match self.allocator.deref(&map_id).get(index)? {
+                            Some(v) => v,
+                            None => {
+                                return Err(format!(
+                                    "Undefined key '{}'.",
+                                    index.display(&self.allocator)
+                                )
+                                .into());
+                            }
+                        },
+                        _ => return Err("Only lists and maps can be indexed.".to_string().into()),
+                    };
+                    self.push(v);
+                }
+                OpCode::SetIndex => {
+                    let v = self.pop();
+                    let index = self.pop();
+                    match self.pop().kind() {
+                        ValueKind::List(list_id) => {
+                            let list = self.allocator.deref_mut(&list_id);
+                            let i = list.index(index)?;
+                            list.items[i] = v;
+                        }
+                        ValueKind::Map(map_id) => {
+                            self.allocator.deref_mut(&map_id).insert(index, v)?;
+                            self.allocator.resize(&map_id);
+                        }
+                        _ => return Err("Only lists and maps can be indexed.".to_string().into()),
+                    }
+                    self.push(v);
+                }
+                OpCode::Nil => self.push(Value::NIL),
+                OpCode::True => self.push(Value::bool(true)),
+                OpCode::False => self.push(Value::bool(false)),
                 OpCode::Equal => {
                     let (b, a) = (self.pop(), self.pop());
-                    self.push(Value::Bool(b == a));
+                    self.push(Value::bool(b == a));
                 }
-                OpCode::Greater => binary_op!(self, Value::Bool, >),
-                OpCode::Less => binary_op!(self, Value::Bool, <),
+                OpCode::Greater => binary_op!(self, Value::bool, >),
+                OpCode::Less => binary_op!(self, Value::bool, <),
                 OpCode::Add => {
-                    match (self.pop(), self.pop()) {
-                        (Value::Number(b), Value::Number(a)) => {
+                    match (self.pop().kind(), self.pop().kind()) {
+                        (ValueKind::Number(b), ValueKind::Number(a)) => {
                             // numerical
-                            self.push(Value::Number(a + b));
+                            self.push(Value::number(a + b));
                         }
-                        (Value::String(ref b), Value::String(ref a)) => {
+                        (ValueKind::String(b), ValueKind::String(a)) => {
                             // string
-                            let b = self.allocator.deref(b);
-                            let a = self.allocator.deref(a);
-                            let concat_str_id = self.allocator.new_string(format!("{}{}", a, b));
-                            self.push(Value::String(concat_str_id));
+                            let b = self.allocator.deref(&b);
+                            let a = self.allocator.deref(&a);
+                            let concat = format!("{}{}", a, b);
+                            let concat_str_id = self.intern(concat);
+                            self.push(Value::from(concat_str_id));
                         }
                         _ => {
-                            let frame = self.current_frame();
-                            let chunk = self.current_chunk();
-                            eprintln!(
-                                "L:{:?}: Operand must be numbers or strings.",
-                                chunk.lines[frame.ip - 1]
-                            );
-                            return InterpretResult::RuntimeError;
+                            return Err("Operands must be two numbers or two strings."
+                                .to_string()
+                                .into());
                         }
                     }
                 }
-                OpCode::Subtract => binary_op!(self, Value::Number, -),
-                OpCode::Multiply => binary_op!(self, Value::Number, *),
-                OpCode::Divide => binary_op!(self, Value::Number, /),
-                OpCode::Negate => match self.pop() {
-                    Value::Number(v) => {
-                        self.push(Value::Number(-v));
+                OpCode::Subtract => binary_op!(self, Value::number, -),
+                OpCode::Multiply => binary_op!(self, Value::number, *),
+                OpCode::Divide => binary_op!(self, Value::number, /),
+                OpCode::Negate => match self.pop().kind() {
+                    ValueKind::Number(v) => {
+                        self.push(Value::number(-v));
                     }
                     _ => {
-                        eprintln!("Operand must be a number.");
-                        return InterpretResult::RuntimeError;
+                        return Err("Operand must be a number.".to_string().into());
                     }
                 },
+                OpCode::Throw => return Err(Exception::Thrown(self.pop())),
+                OpCode::Import(index) => {
+                    let path = self.current_chunk().read_string(index);
+                    self.import(path)?;
+                }
                 OpCode::Not => {
                     let v = self.pop();
-                    match v {
-                        Value::Bool(_) | Value::Nil => {
-                            self.push(Value::Bool(v.is_falsy()));
+                    match v.kind() {
+                        ValueKind::Bool(_) | ValueKind::Nil => {
+                            self.push(Value::bool(v.is_falsy()));
                         }
                         _ => {
-                            eprintln!("Operand must be a number.");
-                            return InterpretResult::RuntimeError;
+                            return Err("Operand must be a number.".to_string().into());
                         }
                     }
                 }
@@ -300,7 +1151,7 @@ impl VM {
 
     fn pop(&mut self) -> Value {
         match self.stack.pop() {
-            Some(v) => return v,
+            Some(v) => v,
             _ => panic!("VM tried to get value from empty stack"),
         }
     }
@@ -316,9 +1167,173 @@ impl VM {
         }
     }
 
-    fn define_native(&mut self, name: String, native: NativeFn) {
-        let name = self.allocator.new_string(name);
-        self.globals.insert(name, Value::NativeFn(native));
+    // define_native registers a host function as a global which scripts can call
+    // with exactly `arity` arguments.
+    pub fn define_native<F>(&mut self, name: &str, arity: usize, body: F)
+    where
+        F: Fn(&mut Allocator, &[Value]) -> Result<Value, String> + 'static,
+    {
+        let name = self.intern(name.to_owned());
+        // keeps the name alive in case allocating the function triggers a collection.
+        self.push(Value::from(name));
+        let native_id = self.alloc(NativeFn::new(name, arity, body));
+        self.pop();
+        self.globals.insert(name, Value::from(native_id));
+        self.natives.push(native_id);
+    }
+
+    fn undefined_global(&self, slot: usize) -> String {
+        let name = self.globals().name(slot);
+        format!("Undefined variable '{}'.", self.allocator.deref(&name))
+    }
+
+    // the globals of the module the current frame runs in.
+    fn globals(&self) -> &Globals {
+        match self.current_frame().module {
+            Some(module_id) => &self.allocator.deref(&module_id).globals,
+            None => &self.globals,
+        }
+    }
+
+    fn globals_mut(&mut self) -> &mut Globals {
+        match self.current_frame().module {
+            Some(module_id) => &mut self.allocator.deref_mut(&module_id).globals,
+            None => &mut self.globals,
+        }
+    }
+
+    // import pushes the module at the path, which is relative to the importing module.
+    // a module only runs the first time it is imported: its script gets a frame whose
+    // first slot holds the module, and returns it where the import pushes it.
+    fn import(&mut self, path: Reference<String>) -> Result<(), String> {
+        let importer = match self.current_frame().module {
+            Some(module_id) => &self.allocator.deref(&module_id).path,
+            None => &self.script_path,
+        };
+        let path = module::resolve_path(importer, self.allocator.deref(&path).as_str());
+
+        let importing = self
+            .importing
+            .iter()
+            .map(|(_, module_id)| self.allocator.deref(module_id).path.as_str());
+        if let Some(start) = importing.clone().position(|p| p == path) {
+            let cycle: Vec<&str> = importing.skip(start).chain(Some(path.as_str())).collect();
+            return Err(format!("Import cycle: {}.", cycle.join(" -> ")));
+        }
+        if let Some(&module_id) = self.modules.get(&path) {
+            self.push(Value::from(module_id));
+            return Ok(());
+        }
+
+        let source = self
+            .resolver
+            .load(&path)
+            .map_err(|e| format!("Could not import \"{}\": {}.", path, e))?;
+        let mut globals = Globals::new();
+        for &native_id in &self.natives {
+            globals.insert(
+                self.allocator.deref(&native_id).name,
+                Value::from(native_id),
+            );
+        }
+        let func_id = self
+            .compile_module(&source, &mut globals)
+            .map_err(|errors| {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                format!("Could not compile \"{}\":\n{}", path, errors.join("\n"))
+            })?;
+
+        // the function is kept on the stack while the module is allocated,
+        // which then takes its place.
+        self.push(Value::from(func_id));
+        let module_id = self.alloc(ScriptModule::new(path.clone(), globals));
+        let slot = self.stack.len() - 1;
+        self.stack[slot] = Value::from(module_id);
+        let mut closure = Closure::new(func_id);
+        closure.module = Some(module_id);
+        let closure_id = self.alloc(closure);
+        self.call_closure(closure_id, 0)?;
+
+        self.modules.insert(path, module_id);
+        self.importing.push((self.frames.len() - 1, module_id));
+        Ok(())
+    }
+
+    // a module's variables are its properties.
+    fn module_variable(
+        &self,
+        module_id: Reference<ScriptModule>,
+        name: Reference<String>,
+    ) -> Result<Value, String> {
+        match self.allocator.deref(&module_id).globals.get(&name) {
+            Some(&v) => Ok(v),
+            None => Err(format!(
+                "Undefined property '{}'.",
+                self.allocator.deref(&name)
+            )),
+        }
+    }
+
+    fn compile_module(
+        &mut self,
+        source: &str,
+        globals: &mut Globals,
+    ) -> Result<Reference<Function>, Vec<CompileError>> {
+        let roots = VMRoots {
+            stack: &self.stack,
+            frames: &self.frames,
+            open_upvalues: &self.open_upvalues,
+            init_string: self.init_string,
+            error_class: self.error_class,
+            globals: Some(&self.globals),
+            modules: &self.modules,
+            natives: &self.natives,
+            handles: &self.handles,
+        };
+        let mut parser = Parser::new(&mut self.allocator, globals);
+        parser.set_roots(&roots);
+        parser.set_optimization_level(self.optimization);
+        parser.compile_module(source)
+    }
+
+    fn split_roots(&mut self) -> (&mut Allocator, &mut Globals, VMRoots<'_>) {
+        let roots = VMRoots {
+            stack: &self.stack,
+            frames: &self.frames,
+            open_upvalues: &self.open_upvalues,
+            init_string: self.init_string,
+            error_class: self.error_class,
+            globals: None,
+            modules: &self.modules,
+            natives: &self.natives,
+            handles: &self.handles,
+        };
+        (&mut self.allocator, &mut self.globals, roots)
+    }
+
+    fn collect_garbage(&mut self) {
+        let (allocator, globals, roots) = self.split_roots();
+        roots.mark_roots(allocator);
+        globals.mark(allocator);
+        allocator.collect_garbage();
+    }
+
+    // every allocation made while running goes through here,
+    // so that a collection can kick in once the heap has grown enough.
+    fn alloc<T: Trace + 'static>(&mut self, obj: T) -> Reference<T> {
+        if self.allocator.should_gc() {
+            // the new object isn't reachable from any root yet.
+            obj.trace(&mut self.allocator);
+            self.collect_garbage();
+        }
+        self.allocator.alloc(obj)
+    }
+
+    pub(crate) fn intern(&mut self, s: String) -> Reference<String> {
+        if self.allocator.should_gc() {
+            self.collect_garbage();
+        }
+        self.allocator.new_string(s)
     }
 
     fn current_frame_mut(&mut self) -> &mut CallFrame {
@@ -329,28 +1344,195 @@ impl VM {
         self.frames.last().unwrap()
     }
 
+    fn current_closure(&self) -> &Closure {
+        self.allocator.deref(&self.current_frame().closure_id)
+    }
+
     fn current_chunk(&self) -> &Chunk {
-        let frame = self.current_frame();
-        let closure = self.allocator.deref(&frame.closure_id);
+        let closure = self.current_closure();
         let function = self.allocator.deref(&closure.func_id);
         &function.chunk
     }
 
-    fn call(&self, arg_num: usize) -> CallFrame {
-        if let Value::Closure(callee_id) = self.peek(arg_num) {
-            let mut new_frame = CallFrame::new(*callee_id);
-            new_frame.slot = self.stack.len() - arg_num - 1;
-            return new_frame;
+    // reuses an open upvalue for the same stack slot so that closures
+    // capturing the same variable share it.
+    fn capture_upvalue(&mut self, location: usize) -> Reference<Upvalue> {
+        let pos = self
+            .open_upvalues
+            .iter()
+            .position(|id| self.allocator.deref(id).location >= location);
+
+        if let Some(pos) = pos {
+            let upvalue_id = self.open_upvalues[pos];
+            if self.allocator.deref(&upvalue_id).location == location {
+                return upvalue_id;
+            }
         }
-        panic!("unreachable")
+
+        let upvalue_id = self.alloc(Upvalue::new(location));
+        match pos {
+            Some(pos) => self.open_upvalues.insert(pos, upvalue_id),
+            None => self.open_upvalues.push(upvalue_id),
+        }
+        upvalue_id
     }
 
-    fn call_native_fn(&mut self, arg_num: usize) {
-        if let Value::NativeFn(f) = self.peek(arg_num) {
-            let result = f.0(&self.allocator, &self.stack[self.stack.len() - arg_num..]);
-            self.push(result);
-            return;
+    // moves every variable at or above `last` off the stack into its upvalue.
+    fn close_upvalues(&mut self, last: usize) {
+        while let Some(&upvalue_id) = self.open_upvalues.last() {
+            let location = self.allocator.deref(&upvalue_id).location;
+            if location < last {
+                break;
+            }
+            let v = self.stack[location];
+            self.allocator.deref_mut(&upvalue_id).closed = Some(v);
+            self.open_upvalues.pop();
+        }
+    }
+
+    fn call_value(&mut self, arg_num: usize) -> Result<(), String> {
+        match self.peek(arg_num).kind() {
+            ValueKind::Closure(closure_id) => self.call_closure(closure_id, arg_num),
+            ValueKind::NativeFn(native_id) => self.call_native_fn(native_id, arg_num),
+            ValueKind::Class(class_id) => {
+                // the new instance replaces the class in the callee slot,
+                // where the initializer expects its receiver.
+                let instance_id = self.alloc(Instance::new(class_id));
+                let slot = self.stack.len() - arg_num - 1;
+                self.stack[slot] = Value::from(instance_id);
+
+                match self
+                    .allocator
+                    .deref(&class_id)
+                    .methods
+                    .get(&self.init_string)
+                    .map(|v| v.kind())
+                {
+                    Some(ValueKind::Closure(initializer)) => {
+                        self.call_closure(initializer, arg_num)?;
+                    }
+                    _ if arg_num != 0 => {
+                        return Err(format!("Expected 0 arguments but got {}.", arg_num));
+                    }
+                    _ => {}
+                }
+                Ok(())
+            }
+            ValueKind::BoundMethod(bound_id) => {
+                let bound = self.allocator.deref(&bound_id);
+                let (receiver, method) = (bound.receiver, bound.method);
+                let slot = self.stack.len() - arg_num - 1;
+                self.stack[slot] = receiver;
+                self.call_closure(method, arg_num)
+            }
+            _ => Err("Can only call functions and classes.".to_string()),
         }
-        panic!("unreachable")
+    }
+
+    fn call_closure(
+        &mut self,
+        closure_id: Reference<Closure>,
+        arg_num: usize,
+    ) -> Result<(), String> {
+        let closure = self.allocator.deref(&closure_id);
+        let (func_id, module) = (closure.func_id, closure.module);
+        let arity = self.allocator.deref(&func_id).arity;
+        if arg_num != arity {
+            return Err(format!("Expected {} arguments but got {}.", arity, arg_num));
+        }
+        if self.frames.len() >= self.max_frames {
+            return Err("Stack overflow.".to_owned());
+        }
+
+        let mut new_frame = CallFrame::new(closure_id);
+        new_frame.slot = self.stack.len() - arg_num - 1;
+        new_frame.module = module;
+        self.frames.push(new_frame);
+        Ok(())
+    }
+
+    fn invoke(&mut self, name: Reference<String>, arg_num: usize) -> Result<(), String> {
+        let instance_id = match self.peek(arg_num).kind() {
+            ValueKind::Instance(instance_id) => instance_id,
+            ValueKind::Module(module_id) => {
+                let slot = self.stack.len() - arg_num - 1;
+                self.stack[slot] = self.module_variable(module_id, name)?;
+                return self.call_value(arg_num);
+            }
+            _ => return Err("Only instances have methods.".to_string()),
+        };
+        let instance = self.allocator.deref(&instance_id);
+
+        // a field holding a callable takes precedence over a method.
+        if let Some(&field) = instance.fields.get(&name) {
+            let slot = self.stack.len() - arg_num - 1;
+            self.stack[slot] = field;
+            return self.call_value(arg_num);
+        }
+
+        let class_id = instance.class;
+        self.invoke_from_class(class_id, name, arg_num)
+    }
+
+    fn invoke_from_class(
+        &mut self,
+        class_id: Reference<Class>,
+        name: Reference<String>,
+        arg_num: usize,
+    ) -> Result<(), String> {
+        let method = self.allocator.deref(&class_id).methods.get(&name);
+        match method.map(|v| v.kind()) {
+            Some(ValueKind::Closure(method)) => self.call_closure(method, arg_num),
+            _ => Err(format!(
+                "Undefined property '{}'.",
+                self.allocator.deref(&name)
+            )),
+        }
+    }
+
+    // replaces the receiver on top of the stack with the method bound to it.
+    fn bind_method(
+        &mut self,
+        class_id: Reference<Class>,
+        name: Reference<String>,
+    ) -> Result<(), String> {
+        let method = self.allocator.deref(&class_id).methods.get(&name);
+        let method = match method.map(|v| v.kind()) {
+            Some(ValueKind::Closure(method)) => method,
+            _ => {
+                return Err(format!(
+                    "Undefined property '{}'.",
+                    self.allocator.deref(&name)
+                ))
+            }
+        };
+
+        let receiver = *self.peek(0);
+        let bound_id = self.alloc(BoundMethod::new(receiver, method));
+        self.pop();
+        self.push(Value::from(bound_id));
+        Ok(())
+    }
+
+    fn call_native_fn(
+        &mut self,
+        native_id: Reference<NativeFn>,
+        arg_num: usize,
+    ) -> Result<(), String> {
+        let native = self.allocator.deref(&native_id);
+        if arg_num != native.arity {
+            return Err(format!(
+                "Expected {} arguments but got {}.",
+                native.arity, arg_num
+            ));
+        }
+        let body = Rc::clone(&native.body);
+
+        let args_start = self.stack.len() - arg_num;
+        let result = body(&mut self.allocator, &self.stack[args_start..])?;
+        // drops the arguments and the callee itself.
+        self.stack.truncate(args_start - 1);
+        self.push(result);
+        Ok(())
     }
 }
@@ -0,0 +1,197 @@
+extern crate lox;
+use lox::*;
+
+const SOURCE: &str = r#"
+fun make_adder(a) {
+    fun add(b) {
+        return a + b;
+    }
+    return add;
+}
+class Point {
+    init(x, y) {
+        this.x = x;
+        this.y = y;
+    }
+    sum() {
+        return this.x + this.y;
+    }
+}
+var items = [1, 2.5, "three", nil, true];
+var names = {"a": 1, "b": false};
+for (var i = 0; i < 3; i = i + 1) {
+    print make_adder(i)(10);
+}
+print Point(1, 2).sum();
+print items;
+print names;
+"#;
+
+const EXPECTED: &str = "10\n11\n12\n3\n[1, 2.5, three, nil, true]\n{a: 1, b: false}\n";
+
+fn compile(source: &str) -> Vec<u8> {
+    let mut vm = VM::new();
+    match vm.compile(source) {
+        Ok(func_id) => serialize(&vm.allocator, &vm.globals, func_id),
+        Err(errors) => panic!("unexpected compile errors: {:?}", errors),
+    }
+}
+
+// the same checksum as the bytecode uses, to make contents which get past it.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn fix_checksum(bytes: &mut [u8]) {
+    let checksum = crc32(&bytes[10..]);
+    bytes[6..10].copy_from_slice(&checksum.to_le_bytes());
+}
+
+#[test]
+fn run_loaded_bytecode() {
+    let bytes = compile(SOURCE);
+    assert!(is_bytecode(&bytes));
+    assert!(!is_bytecode(SOURCE.as_bytes()));
+
+    // a fresh VM has none of the strings the script was compiled with.
+    let mut vm = VM::new();
+    let out = OutputBuffer::new();
+    vm.set_output(out.clone());
+    let func_id = vm.load(&bytes).expect("failed to load");
+    assert_eq!(InterpretResult::Ok, vm.execute(func_id));
+    assert_eq!(EXPECTED, out.contents());
+}
+
+#[test]
+fn loaded_bytecode_keeps_lines() {
+    let bytes = compile("var a = 1;\n\nfun f() {\n    return a + nil;\n}\nf();\n");
+    let mut vm = VM::new();
+    let func_id = vm.load(&bytes).expect("failed to load");
+    match vm.execute(func_id) {
+        InterpretResult::RuntimeError(e) => {
+            assert_eq!(
+                "Operands must be two numbers or two strings.\n[line 3] in f()\n[line 5] in script",
+                e.to_string()
+            );
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn load_errors() {
+    let bytes = compile(SOURCE);
+    let mut vm = VM::new();
+
+    assert_eq!(
+        Err(LoadError::NotBytecode),
+        vm.load(SOURCE.as_bytes()).map(|_| ())
+    );
+    assert_eq!(
+        Err(LoadError::UnexpectedEnd),
+        vm.load(&bytes[..8]).map(|_| ())
+    );
+
+    let mut other_version = bytes.clone();
+    other_version[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+    assert_eq!(
+        Err(LoadError::UnsupportedVersion(FORMAT_VERSION + 1)),
+        vm.load(&other_version).map(|_| ())
+    );
+
+    let mut damaged = bytes.clone();
+    let last = damaged.len() - 1;
+    damaged[last] ^= 0xff;
+    assert_eq!(
+        Err(LoadError::ChecksumMismatch),
+        vm.load(&damaged).map(|_| ())
+    );
+
+    let mut truncated = bytes[..bytes.len() - 1].to_vec();
+    fix_checksum(&mut truncated);
+    assert_eq!(
+        Err(LoadError::UnexpectedEnd),
+        vm.load(&truncated).map(|_| ())
+    );
+
+    let mut extended = bytes.clone();
+    extended.push(0);
+    fix_checksum(&mut extended);
+    assert_eq!(
+        Err(LoadError::Corrupt(
+            "Unexpected data after the script.".to_owned()
+        )),
+        vm.load(&extended).map(|_| ())
+    );
+}
+
+#[test]
+fn load_corrupt_bytecode_without_panicking() {
+    let bytes = compile(SOURCE);
+    let mut vm = VM::new();
+    vm.set_output(OutputBuffer::new());
+    // the corrupt code may well loop forever.
+    vm.set_limits(Limits {
+        fuel: Some(10_000),
+        ..Limits::default()
+    });
+    for i in 10..bytes.len() {
+        for &b in &[0x00, 0x7f, 0x80, 0xff] {
+            let mut corrupt = bytes.clone();
+            corrupt[i] = b;
+            fix_checksum(&mut corrupt);
+            // whatever is loaded, it must be an error rather than a panic,
+            // and so must running it.
+            if let Ok(func_id) = vm.load(&corrupt) {
+                let _ = vm.execute(func_id);
+            }
+        }
+    }
+}
+
+#[test]
+fn load_bytecode_reading_past_the_stack() {
+    let bytes = compile("{ var a = 1; print a; }");
+    // GetLocal 1 followed by Print.
+    let get_local = bytes
+        .windows(3)
+        .position(|w| w == [9, 1, 1])
+        .expect("no GetLocal in the code");
+    let mut corrupt = bytes.clone();
+    corrupt[get_local + 1] = 200;
+    fix_checksum(&mut corrupt);
+    let mut vm = VM::new();
+    match vm.load(&corrupt) {
+        Err(LoadError::Corrupt(message)) => {
+            assert_eq!("Invalid stack use by GetLocal(200) at offset 2.", message)
+        }
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn load_into_vm_with_other_globals() {
+    let bytes = compile(SOURCE);
+
+    // the loading VM already has plenty of globals, so every slot moves
+    // and takes more bytes than it did.
+    let mut vm = VM::new();
+    let declarations: Vec<String> = (0..300).map(|i| format!("var g{} = {};", i, i)).collect();
+    assert_eq!(InterpretResult::Ok, vm.interpret(&declarations.join("\n")));
+
+    let out = OutputBuffer::new();
+    vm.set_output(out.clone());
+    let func_id = vm.load(&bytes).expect("failed to load");
+    assert_eq!(InterpretResult::Ok, vm.execute(func_id));
+    assert_eq!(EXPECTED, out.contents());
+    assert_eq!(InterpretResult::Ok, vm.interpret("print g299 + items[0];"));
+    assert_eq!(format!("{}300\n", EXPECTED), out.contents());
+}
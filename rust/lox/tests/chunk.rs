@@ -0,0 +1,117 @@
+extern crate lox;
+use lox::*;
+
+// reads every instruction in the chunk back, in order.
+fn read_all(chunk: &Chunk) -> Vec<OpCode> {
+    let mut ops = Vec::new();
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        let (op, next) = chunk.read(offset);
+        ops.push(op);
+        offset = next;
+    }
+    ops
+}
+
+#[test]
+fn encode_and_read_instructions() {
+    let ops = vec![
+        OpCode::Constant(1),
+        OpCode::GetLocal(3),
+        OpCode::JumpIfFalse(300),
+        OpCode::Invoke(2, 4),
+        OpCode::BuildMap(2),
+        OpCode::Add,
+        OpCode::Return,
+    ];
+    let mut chunk = Chunk::new();
+    for op in &ops {
+        chunk.add_instruction(*op, 0);
+    }
+    assert_eq!(ops, read_all(&chunk));
+    // opcodes take a byte, jumps and item counts 2, and the other operands a byte each.
+    assert_eq!(2 + 2 + 3 + 3 + 3 + 1 + 1, chunk.code.len());
+}
+
+#[test]
+fn encode_long_constants() {
+    let mut chunk = Chunk::new();
+    chunk.add_instruction(OpCode::Constant(255), 0);
+    chunk.add_instruction(OpCode::Constant(256), 0);
+    chunk.add_instruction(OpCode::Invoke(70_000, 1), 0);
+    chunk.add_instruction(OpCode::Return, 0);
+    assert_eq!(
+        vec![
+            OpCode::Constant(255),
+            OpCode::Constant(256),
+            OpCode::Invoke(70_000, 1),
+            OpCode::Return,
+        ],
+        read_all(&chunk)
+    );
+    // indices above 255 take 3 bytes.
+    assert_eq!(2 + 4 + 5 + 1, chunk.code.len());
+}
+
+#[test]
+fn line_table_is_run_length_encoded() {
+    let mut chunk = Chunk::new();
+    chunk.add_instruction(OpCode::Nil, 1);
+    chunk.add_instruction(OpCode::Constant(0), 1);
+    chunk.add_instruction(OpCode::Pop, 1);
+    chunk.add_instruction(OpCode::True, 3);
+    chunk.add_instruction(OpCode::Return, 3);
+    assert_eq!(
+        vec![
+            LineStart { offset: 0, line: 1 },
+            LineStart { offset: 4, line: 3 },
+        ],
+        chunk.lines
+    );
+    let lines: Vec<usize> = (0..chunk.code.len()).map(|i| chunk.line(i)).collect();
+    assert_eq!(vec![1, 1, 1, 1, 3, 3], lines);
+}
+
+#[test]
+fn patch_jump_offset() {
+    let mut chunk = Chunk::new();
+    chunk.add_instruction(OpCode::Jump(0), 0);
+    chunk.add_instruction(OpCode::Return, 0);
+    chunk.patch_jump(0, 1_000);
+    assert_eq!(vec![OpCode::Jump(1_000), OpCode::Return], read_all(&chunk));
+}
+
+#[test]
+fn run_many_constants() {
+    // every number literal is a constant of its own.
+    let sum: Vec<String> = (0..300).map(|i| format!("{}", i)).collect();
+    let source = format!("print {};", sum.join(" + "));
+    let mut vm = VM::new();
+    let out = OutputBuffer::new();
+    vm.set_output(out.clone());
+    assert_eq!(InterpretResult::Ok, vm.interpret(&source));
+    assert_eq!("44850\n", out.contents());
+}
+
+#[test]
+fn run_operand_limits() {
+    let args = vec!["nil"; 256].join(", ");
+    let params: Vec<String> = (0..256).map(|i| format!("p{}", i)).collect();
+    let cases = [
+        (
+            format!("fun f() {{}} f({});", args),
+            "Can't have more than 255 arguments.",
+        ),
+        (
+            format!("fun f({}) {{}}", params.join(", ")),
+            "Can't have more than 255 parameters.",
+        ),
+    ];
+    for (source, message) in cases.iter() {
+        let mut vm = VM::new();
+        match vm.interpret(source) {
+            InterpretResult::CompileError(errors) => assert_eq!(*message, errors[0].message),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+// lox runs the binary with the arguments, feeding it the input on stdin.
+fn lox(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run lox");
+    child
+        .stdin
+        .take()
+        .expect("no stdin")
+        .write_all(input.as_bytes())
+        .expect("failed to write stdin");
+    child.wait_with_output().expect("failed to wait for lox")
+}
+
+// script writes the source into a file of its own for a test to run.
+fn script(name: &str, source: &str) -> String {
+    let path: PathBuf = [env!("CARGO_TARGET_TMPDIR"), name].iter().collect();
+    fs::write(&path, source).expect("failed to write script");
+    path.to_str().expect("path is not UTF-8").to_owned()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn run_file() {
+    let path = script("cli_run_file.lox", "print 1 + 2;");
+    let output = lox(&[&path], "");
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("3\n", stdout(&output));
+    assert_eq!("", stderr(&output));
+}
+
+#[test]
+fn exit_codes() {
+    // the exit codes are those of sysexits.h.
+    let output = lox(&["a.lox", "b.lox"], "");
+    assert_eq!(Some(64), output.status.code());
+    assert!(stderr(&output).starts_with("Usage: lox [path]\n"));
+
+    let path = script("cli_compile_error.lox", "print 1 +;");
+    let output = lox(&[&path], "");
+    assert_eq!(Some(65), output.status.code());
+    assert_eq!(
+        "[line 0:9] Error at ';': Expect expression.\n",
+        stderr(&output)
+    );
+
+    // the output up to the error is still there.
+    let path = script("cli_runtime_error.lox", "print 1;\nprint x;");
+    let output = lox(&[&path], "");
+    assert_eq!(Some(70), output.status.code());
+    assert_eq!("1\n", stdout(&output));
+    assert_eq!(
+        "Undefined variable 'x'.\n[line 1] in script\n",
+        stderr(&output)
+    );
+
+    let path = script("cli_missing.lox", "");
+    fs::remove_file(&path).expect("failed to remove script");
+    let output = lox(&[&path], "");
+    assert_eq!(Some(74), output.status.code());
+    assert!(stderr(&output).starts_with(&format!("Could not read file \"{}\"", path)));
+}
+
+#[test]
+fn repl() {
+    // globals carry over between lines, and an unclosed block goes on to the next line.
+    let output = lox(&[], "var a = 1;\nfun f() {\nreturn a + 1;\n}\nprint f();\n");
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("> > ... ... > 2\n> \n", stdout(&output));
+
+    // an empty line gives up on unfinished input, and errors don't end the session.
+    let output = lox(&[], "{\n\nprint x;\nprint 3;\n");
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("> ... > > 3\n> \n", stdout(&output));
+    assert_eq!(
+        "[line 2:0] Error at end: Expect '}' after block.\nUndefined variable 'x'.\n[line 0] in script\n",
+        stderr(&output)
+    );
+
+    // `:dis` shows the bytecode of the last input, which may end in the middle of a statement.
+    let output = lox(&[], "print 1;\n:dis\nprint\n");
+    assert_eq!(Some(0), output.status.code());
+    let out = stdout(&output);
+    assert!(out.starts_with("> 1\n> == script ==\n"));
+    assert!(out.contains("OP_PRINT"));
+    assert!(out.ends_with("> ... \n"));
+}
@@ -0,0 +1,27 @@
+// helpers shared by the tests of every part of the language.
+use lox::*;
+
+// run_output runs a script which has to succeed, and returns what it printed.
+pub fn run_output(vm: &mut VM, source: &str) -> String {
+    let out = OutputBuffer::new();
+    vm.set_output(out.clone());
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    out.contents()
+}
+
+// run runs a script at the optimization level, and returns how it went and what it printed.
+pub fn run(source: &str, level: OptimizationLevel) -> (InterpretResult, String) {
+    let mut vm = VM::new();
+    vm.set_optimization_level(level);
+    let out = OutputBuffer::new();
+    vm.set_output(out.clone());
+    let result = vm.interpret(source);
+    (result, out.contents())
+}
+
+pub fn runtime_error(result: InterpretResult) -> RuntimeError {
+    match result {
+        InterpretResult::RuntimeError(e) => e,
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+}
@@ -34,7 +34,45 @@ fn run_print() {
 print "foobar";
 "#;
     let mut vm = VM::new();
+    let out = OutputBuffer::new();
+    vm.set_output(out.clone());
     assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    assert_eq!("foobar\n", out.contents());
+}
+
+#[test]
+fn run_print_values() {
+    let source = r#"
+fun add(a, b) { return a + b; }
+class Point {
+    init(x) { this.x = x; }
+    get() { return this.x; }
+}
+var p = Point(1.5);
+print nil;
+print !nil;
+print add(3, 4);
+print p.get() / 2;
+print "a" + "b";
+print add;
+print Point;
+print p;
+print p.get;
+print clock;
+"#;
+    let mut vm = VM::new();
+    let out = OutputBuffer::new();
+    vm.set_output(out.clone());
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    assert_eq!(
+        "nil\ntrue\n7\n0.75\nab\n<fn add>\nPoint\nPoint instance\n<fn get>\n<native fn clock>\n",
+        out.contents()
+    );
+
+    // the buffer is shared with the VM, so clearing it here starts the next run afresh.
+    out.clear();
+    assert_eq!(InterpretResult::Ok, vm.interpret("print p.x;"));
+    assert_eq!("1.5\n", out.contents());
 }
 
 #[test]
@@ -54,13 +92,13 @@ print mut_foo;
     assert_eq!(
         "beignets with cafe au lait",
         vm.allocator
-            .deref(vm.globals.get(k).expect("no such key").as_string()),
+            .deref(&vm.globals.get(k).expect("no such key").as_string()),
     );
     let k = &vm.allocator.new_string("mut_foo".to_owned());
     assert_eq!(
         "updated foo!",
         vm.allocator
-            .deref(vm.globals.get(k).expect("no such key").as_string())
+            .deref(&vm.globals.get(k).expect("no such key").as_string())
     );
 }
 
@@ -81,13 +119,13 @@ var con = "";
     assert_eq!(
         "globalized",
         vm.allocator
-            .deref(vm.globals.get(k).expect("no such key").as_string())
+            .deref(&vm.globals.get(k).expect("no such key").as_string())
     );
     let k = &vm.allocator.new_string("con".to_owned());
     assert_eq!(
         "localized and globalized",
         vm.allocator
-            .deref(vm.globals.get(k).expect("no such key").as_string())
+            .deref(&vm.globals.get(k).expect("no such key").as_string())
     );
 }
 
@@ -113,13 +151,10 @@ if (global != "localized") {
     assert_eq!(
         "localized",
         vm.allocator
-            .deref(vm.globals.get(k).expect("no such key").as_string()),
+            .deref(&vm.globals.get(k).expect("no such key").as_string()),
     );
     let k = &vm.allocator.new_string("falsy".to_owned());
-    assert_eq!(
-        true,
-        vm.globals.get(k).expect("no such key").as_bool().clone()
-    );
+    assert!(vm.globals.get(k).expect("no such key").as_bool());
 }
 
 #[test]
@@ -135,25 +170,13 @@ var or_exp_false = ( global != "globalized" or local != "localized" );
     let mut vm = VM::new();
     assert_eq!(InterpretResult::Ok, vm.interpret(source));
     let k = &vm.allocator.new_string("and_exp_true".to_owned());
-    assert_eq!(
-        true,
-        vm.globals.get(k).expect("no such key").as_bool().clone()
-    );
+    assert!(vm.globals.get(k).expect("no such key").as_bool());
     let k = &vm.allocator.new_string("and_exp_false".to_owned());
-    assert_eq!(
-        false,
-        vm.globals.get(k).expect("no such key").as_bool().clone()
-    );
+    assert!(!vm.globals.get(k).expect("no such key").as_bool());
     let k = &vm.allocator.new_string("or_exp_true".to_owned());
-    assert_eq!(
-        true,
-        vm.globals.get(k).expect("no such key").as_bool().clone()
-    );
+    assert!(vm.globals.get(k).expect("no such key").as_bool());
     let k = &vm.allocator.new_string("or_exp_false".to_owned());
-    assert_eq!(
-        false,
-        vm.globals.get(k).expect("no such key").as_bool().clone()
-    );
+    assert!(!vm.globals.get(k).expect("no such key").as_bool());
 }
 
 #[test]
@@ -217,6 +240,112 @@ for (;d < 5;) {
     );
 }
 
+#[test]
+fn run_break_and_continue() {
+    let source = r#"
+for (var i = 0; i < 10; i = i + 1) {
+    var square = i * i;
+    if (i == 2) continue;
+    if (i == 5) break;
+    print square;
+}
+var n = 0;
+while (true) {
+    n = n + 1;
+    var a = "a";
+    {
+        var b = "b";
+        if (n == 2) continue;
+        if (n > 3) break;
+        print a + b;
+    }
+}
+print n;
+for (var x = 0; x < 2; x = x + 1) {
+    for (var y = 0; ; y = y + 1) {
+        if (y == 2) break;
+        print x * 10 + y;
+    }
+}
+var getters = [];
+for (var i = 0; i < 5; i = i + 1) {
+    var captured = i;
+    fun get() { return captured; }
+    push(getters, get);
+    if (i == 1) break;
+}
+print getters[0]() + getters[1]();
+"#;
+    let mut vm = VM::new();
+    let out = OutputBuffer::new();
+    vm.set_output(out.clone());
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    assert_eq!("0\n1\n9\n16\nab\nab\n4\n0\n1\n10\n11\n1\n", out.contents());
+    assert!(vm.stack.is_empty());
+}
+
+#[test]
+fn run_break_and_continue_through_finally() {
+    let source = r#"
+fun f() {
+    var out = "";
+    for (var i = 0; i < 5; i = i + 1) {
+        try {
+            try {
+                if (i == 1) continue;
+                if (i == 3) break;
+                out = out + "t";
+            } finally {
+                out = out + "f";
+            }
+        } finally {
+            out = out + "F";
+        }
+    }
+    return out;
+}
+print f();
+var i = 0;
+while (i < 3) {
+    i = i + 1;
+    try {
+        if (i == 2) break;
+    } catch (e) {
+    } finally {
+        print "finally " + "";
+    }
+}
+print i;
+"#;
+    let mut vm = VM::new();
+    let out = OutputBuffer::new();
+    vm.set_output(out.clone());
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    assert_eq!("tfFfFtfFfF\nfinally \nfinally \n2\n", out.contents());
+}
+
+#[test]
+fn run_break_and_continue_outside_loop() {
+    let cases = [
+        ("break;", "Can't use 'break' outside of a loop."),
+        ("{ continue; }", "Can't use 'continue' outside of a loop."),
+        (
+            "while (true) { fun f() { break; } }",
+            "Can't use 'break' outside of a loop.",
+        ),
+        ("while (true) break", "Expect ';' after 'break'."),
+    ];
+    for &(source, message) in cases.iter() {
+        let mut vm = VM::new();
+        match vm.interpret(source) {
+            InterpretResult::CompileError(errors) => {
+                assert_eq!(message, errors[0].message, "{}", source)
+            }
+            other => panic!("unexpected result for {}: {:?}", source, other),
+        }
+    }
+}
+
 #[test]
 fn run_call_function() {
     let source = r#"
@@ -308,3 +437,531 @@ a = max(1, 2);
         vm.globals.get(k).expect("no such key").as_number().clone()
     );
 }
+
+#[test]
+fn run_closure_counter() {
+    let source = r#"
+fun make_counter() {
+    var count = 0;
+    fun counter() {
+        count = count + 1;
+        return count;
+    }
+    return counter;
+}
+var counter = make_counter();
+counter();
+counter();
+var a = counter();
+var other = make_counter();
+var b = other();
+"#;
+    let mut vm = VM::new();
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    let k = &vm.allocator.new_string("a".to_owned());
+    assert_eq!(3_f64, vm.globals.get(k).expect("no such key").as_number());
+    let k = &vm.allocator.new_string("b".to_owned());
+    assert_eq!(1_f64, vm.globals.get(k).expect("no such key").as_number());
+}
+
+#[test]
+fn run_closure_adder() {
+    let source = r#"
+fun make_adder(n) {
+    fun adder(x) {
+        return x + n;
+    }
+    return adder;
+}
+var add2 = make_adder(2);
+var add10 = make_adder(10);
+var a = add2(1);
+var b = add10(add2(3));
+"#;
+    let mut vm = VM::new();
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    let k = &vm.allocator.new_string("a".to_owned());
+    assert_eq!(3_f64, vm.globals.get(k).expect("no such key").as_number());
+    let k = &vm.allocator.new_string("b".to_owned());
+    assert_eq!(15_f64, vm.globals.get(k).expect("no such key").as_number());
+}
+
+#[test]
+fn run_closure_shared_and_nested_upvalues() {
+    let source = r#"
+var get;
+var set;
+var deep;
+fun outer() {
+    var x = "before";
+    fun g() {
+        return x;
+    }
+    fun s(v) {
+        x = v;
+    }
+    fun middle() {
+        fun inner() {
+            return x;
+        }
+        return inner;
+    }
+    get = g;
+    set = s;
+    deep = middle();
+}
+outer();
+set("after");
+var a = get();
+var b = deep();
+{
+    var local = 1;
+    fun bump() {
+        local = local + 1;
+    }
+    bump();
+    bump();
+    set(local);
+}
+var c = get();
+"#;
+    let mut vm = VM::new();
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    let k = &vm.allocator.new_string("a".to_owned());
+    assert_eq!(
+        "after",
+        vm.allocator
+            .deref(&vm.globals.get(k).expect("no such key").as_string())
+    );
+    let k = &vm.allocator.new_string("b".to_owned());
+    assert_eq!(
+        "after",
+        vm.allocator
+            .deref(&vm.globals.get(k).expect("no such key").as_string())
+    );
+    let k = &vm.allocator.new_string("c".to_owned());
+    assert_eq!(3_f64, vm.globals.get(k).expect("no such key").as_number());
+}
+
+#[test]
+fn run_anonymous_functions() {
+    let source = r#"
+var add = fun (a, b) { return a + b; };
+print add(1, 2);
+print add;
+fun () { print "called right away"; }();
+fun apply(f, x) {
+    return f(x);
+}
+var k = 10;
+print apply(fun (x) { return x * k; }, 4);
+fun counter() {
+    var n = 0;
+    return fun () {
+        n = n + 1;
+        return n;
+    };
+}
+var c = counter();
+c();
+print c();
+print [fun () {}][0];
+"#;
+    let mut vm = VM::new();
+    let out = OutputBuffer::new();
+    vm.set_output(out.clone());
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    assert_eq!(
+        "3\n<fn anonymous@1>\ncalled right away\n40\n2\n<fn anonymous@20>\n",
+        out.contents()
+    );
+
+    let err = match vm.interpret("var f = fun () {\n  return nil + 1;\n};\nf();") {
+        InterpretResult::RuntimeError(err) => err,
+        other => panic!("unexpected result: {:?}", other),
+    };
+    assert_eq!(
+        "Operands must be two numbers or two strings.\n[line 1] in anonymous@0()\n[line 3] in script",
+        err.to_string()
+    );
+
+    match vm.interpret("var f = fun { };") {
+        InterpretResult::CompileError(errors) => {
+            assert_eq!("Expect '(' after function name.", errors[0].message)
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn run_class_fields() {
+    let source = r#"
+class Pair {}
+var pair = Pair();
+pair.first = 1;
+pair.second = 2;
+var sum = pair.first + pair.second;
+"#;
+    let mut vm = VM::new();
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    let k = &vm.allocator.new_string("sum".to_owned());
+    assert_eq!(3_f64, vm.globals.get(k).expect("no such key").as_number());
+}
+
+#[test]
+fn run_class_methods_and_initializer() {
+    let source = r#"
+class Counter {
+    init(start) {
+        this.count = start;
+    }
+    incr() {
+        this.count = this.count + 1;
+        return this;
+    }
+    getter() {
+        fun get() {
+            return this.count;
+        }
+        return get;
+    }
+}
+var counter = Counter(10);
+counter.incr().incr();
+var incr = counter.incr;
+incr();
+var a = counter.count;
+var b = counter.getter()();
+var c = counter.init(1).count;
+"#;
+    let mut vm = VM::new();
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    let k = &vm.allocator.new_string("a".to_owned());
+    assert_eq!(13_f64, vm.globals.get(k).expect("no such key").as_number());
+    let k = &vm.allocator.new_string("b".to_owned());
+    assert_eq!(13_f64, vm.globals.get(k).expect("no such key").as_number());
+    let k = &vm.allocator.new_string("c".to_owned());
+    assert_eq!(1_f64, vm.globals.get(k).expect("no such key").as_number());
+}
+
+#[test]
+fn run_class_errors() {
+    let mut vm = VM::new();
+    assert!(matches!(
+        vm.interpret("print this;"),
+        InterpretResult::CompileError(_)
+    ));
+
+    let mut vm = VM::new();
+    assert!(matches!(
+        vm.interpret("class Foo { init() { return 1; } }"),
+        InterpretResult::CompileError(_)
+    ));
+
+    let mut vm = VM::new();
+    assert!(matches!(
+        vm.interpret("class Foo {} Foo().bar;"),
+        InterpretResult::RuntimeError(_)
+    ));
+
+    let mut vm = VM::new();
+    assert!(matches!(
+        vm.interpret("class Foo {} Foo(1);"),
+        InterpretResult::RuntimeError(_)
+    ));
+}
+
+#[test]
+fn run_class_inheritance() {
+    let source = r#"
+class A {
+    init(name) {
+        this.name = name;
+    }
+    greet() {
+        return "A " + this.name;
+    }
+    kind() {
+        return "A";
+    }
+}
+class B < A {
+    init(name) {
+        super.init(name + "!");
+    }
+    greet() {
+        return "B " + super.greet();
+    }
+    parent_kind() {
+        var method = super.kind;
+        return method();
+    }
+}
+var b = B("bob");
+var greeting = b.greet();
+var kind = b.kind();
+var parent_kind = b.parent_kind();
+"#;
+    let mut vm = VM::new();
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    let k = &vm.allocator.new_string("greeting".to_owned());
+    assert_eq!(
+        "B A bob!",
+        vm.allocator
+            .deref(&vm.globals.get(k).expect("no such key").as_string())
+    );
+    let k = &vm.allocator.new_string("kind".to_owned());
+    assert_eq!(
+        "A",
+        vm.allocator
+            .deref(&vm.globals.get(k).expect("no such key").as_string())
+    );
+    let k = &vm.allocator.new_string("parent_kind".to_owned());
+    assert_eq!(
+        "A",
+        vm.allocator
+            .deref(&vm.globals.get(k).expect("no such key").as_string())
+    );
+}
+
+#[test]
+fn run_class_inheritance_errors() {
+    for source in &[
+        "class A < A {}",
+        "class A < 1 {}",
+        "print super.foo;",
+        "class A { foo() { return super.foo(); } }",
+    ] {
+        let mut vm = VM::new();
+        assert!(
+            matches!(vm.interpret(source), InterpretResult::CompileError(_)),
+            "{}",
+            source
+        );
+    }
+
+    let mut vm = VM::new();
+    assert!(matches!(
+        vm.interpret("var A = 1; class B < A {}"),
+        InterpretResult::RuntimeError(_)
+    ));
+}
+
+#[test]
+fn run_runtime_error_trace() {
+    let source = r#"
+fun inner() {
+    return 1 + nil;
+}
+fun outer() {
+    inner();
+}
+outer();
+"#;
+    let mut vm = VM::new();
+    let err = match vm.interpret(source) {
+        InterpretResult::RuntimeError(err) => err,
+        other => panic!("unexpected result: {:?}", other),
+    };
+    assert_eq!("Operands must be two numbers or two strings.", err.message);
+    assert_eq!(2, err.line);
+    assert_eq!(
+        vec![
+            TraceFrame {
+                function: "inner()".to_owned(),
+                line: 2
+            },
+            TraceFrame {
+                function: "outer()".to_owned(),
+                line: 5
+            },
+            TraceFrame {
+                function: "script".to_owned(),
+                line: 7
+            },
+        ],
+        err.trace
+    );
+    assert_eq!(
+        "Operands must be two numbers or two strings.\n[line 2] in inner()\n[line 5] in outer()\n[line 7] in script",
+        err.to_string()
+    );
+}
+
+#[test]
+fn run_after_runtime_error() {
+    let mut vm = VM::new();
+    assert_eq!(InterpretResult::Ok, vm.interpret("var a = 1;"));
+
+    match vm.interpret("fun f() { return b; } a = 2; f();") {
+        InterpretResult::RuntimeError(err) => {
+            assert_eq!("Undefined variable 'b'.", err.message);
+            assert_eq!(2, err.trace.len());
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(vm.stack.is_empty());
+    assert!(vm.frames.is_empty());
+
+    assert_eq!(InterpretResult::Ok, vm.interpret("var b = a + 1;"));
+    let k = &vm.allocator.new_string("b".to_owned());
+    assert_eq!(3_f64, vm.globals.get(k).expect("no such key").as_number());
+}
+
+#[test]
+fn run_arity_mismatch() {
+    let cases = [
+        ("fun f(a, b) {} f(1);", "Expected 2 arguments but got 1."),
+        ("fun f() {} f(1, 2);", "Expected 0 arguments but got 2."),
+        (
+            "class A { m(x) {} } A().m();",
+            "Expected 1 arguments but got 0.",
+        ),
+        (
+            "class A { init(x) {} } A(1, 2);",
+            "Expected 1 arguments but got 2.",
+        ),
+        (
+            "class A { m(x) {} } var m = A().m; m(1, 2);",
+            "Expected 1 arguments but got 2.",
+        ),
+    ];
+    for (source, message) in cases.iter() {
+        let mut vm = VM::new();
+        match vm.interpret(source) {
+            InterpretResult::RuntimeError(err) => assert_eq!(*message, err.message),
+            other => panic!("unexpected result for {}: {:?}", source, other),
+        }
+    }
+}
+
+#[test]
+fn run_stack_overflow() {
+    let source = r#"
+fun recurse(n) {
+    return recurse(n + 1);
+}
+recurse(0);
+"#;
+    let mut vm = VM::new();
+    vm.set_max_frames(8);
+    let err = match vm.interpret(source) {
+        InterpretResult::RuntimeError(err) => err,
+        other => panic!("unexpected result: {:?}", other),
+    };
+    assert_eq!("Stack overflow.", err.message);
+    assert_eq!(8, err.trace.len());
+    assert_eq!("recurse()", err.trace[0].function);
+    assert_eq!("script", err.trace[7].function);
+
+    // recursion within the limit still works.
+    let source = r#"
+fun fib(n) {
+    if (n < 2) return n;
+    return fib(n - 1) + fib(n - 2);
+}
+var a = fib(6);
+"#;
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    let k = &vm.allocator.new_string("a".to_owned());
+    assert_eq!(8_f64, vm.globals.get(k).expect("no such key").as_number());
+}
+
+#[test]
+fn run_compile_errors_are_all_reported() {
+    let source = r#"
+var a = ;
+print 1 +;
+fun f(x y) {
+    return 1;
+}
+var ok = 1;
+class A < A {}
+print @;
+return 2;
+"#;
+    let mut vm = VM::new();
+    let errors = match vm.interpret(source) {
+        InterpretResult::CompileError(errors) => errors,
+        other => panic!("unexpected result: {:?}", other),
+    };
+    let got: Vec<(usize, usize, &str, &str)> = errors
+        .iter()
+        .map(|e| (e.line, e.column, e.lexeme.as_str(), e.message.as_str()))
+        .collect();
+    assert_eq!(
+        vec![
+            (1, 8, ";", "Expect expression."),
+            (2, 9, ";", "Expect expression."),
+            (3, 8, "y", "Expect ')' after parameters."),
+            // the bad parameter list resynchronizes at the next statement,
+            // so the function body is then parsed at the top level.
+            (4, 4, "return", "Can't return from top-level code."),
+            (5, 0, "}", "Expect expression."),
+            (7, 10, "A", "A class can't inherit from itself."),
+            (8, 6, "@", "Unexpected character."),
+            (8, 7, ";", "Expect expression."),
+            (9, 0, "return", "Can't return from top-level code."),
+        ],
+        got
+    );
+    assert_eq!(
+        "[line 1:8] Error at ';': Expect expression.",
+        errors[0].to_string()
+    );
+}
+
+#[test]
+fn run_compile_error_at_end() {
+    let mut vm = VM::new();
+    match vm.interpret("print 1") {
+        InterpretResult::CompileError(errors) => {
+            assert_eq!(1, errors.len());
+            assert_eq!(
+                "[line 0:7] Error at end: Expect ';' after print statement.",
+                errors[0].to_string()
+            );
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn run_globals_shared_across_compilations() {
+    let mut vm = VM::new();
+    let out = OutputBuffer::new();
+    vm.set_output(out.clone());
+
+    // the slot of `later` exists from here on, but it is undefined until declared.
+    assert_eq!(
+        InterpretResult::Ok,
+        vm.interpret("fun get() { return later; } fun set() { later = 2; }")
+    );
+    for source in ["get();", "set();"].iter() {
+        match vm.interpret(source) {
+            InterpretResult::RuntimeError(e) => {
+                assert_eq!("Undefined variable 'later'.", e.message)
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    assert_eq!(
+        InterpretResult::Ok,
+        vm.interpret("var later = 1; print get(); set(); print later;")
+    );
+    assert_eq!("1\n2\n", out.contents());
+
+    // globals can still be read and written by name.
+    let name = vm.allocator.new_string("later".to_owned());
+    assert_eq!(Some(&Value::number(2.0)), vm.globals.get(&name));
+    vm.globals.insert(name, Value::number(5.0));
+    let name = vm.allocator.new_string("host".to_owned());
+    vm.globals.insert(name, Value::bool(true));
+    out.clear();
+    assert_eq!(
+        InterpretResult::Ok,
+        vm.interpret("print get(); print host;")
+    );
+    assert_eq!("5\ntrue\n", out.contents());
+}
@@ -0,0 +1,344 @@
+extern crate lox;
+use lox::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// Seen is what the debugger saw at a stop: the line, the function and the local variables.
+#[derive(Debug, PartialEq)]
+struct Seen {
+    line: usize,
+    function: String,
+    locals: Vec<(String, Value)>,
+}
+
+fn seen(stop: &Stop) -> Seen {
+    Seen {
+        line: stop.line,
+        function: stop.vm.backtrace()[0].function.clone(),
+        locals: stop.vm.locals(0),
+    }
+}
+
+// debug sets a debugger on the VM which records every stop, and carries on as `resume` says.
+fn debug<F>(vm: &mut VM, mut resume: F) -> Rc<RefCell<Vec<Seen>>>
+where
+    F: FnMut(&mut Stop) -> Resume + 'static,
+{
+    let stops = Rc::new(RefCell::new(vec![]));
+    let recorded = Rc::clone(&stops);
+    vm.set_debugger(move |stop: &mut Stop| {
+        recorded.borrow_mut().push(seen(stop));
+        resume(stop)
+    });
+    stops
+}
+
+fn locals(names: &[(&str, f64)]) -> Vec<(String, Value)> {
+    names
+        .iter()
+        .map(|&(name, n)| (name.to_owned(), Value::number(n)))
+        .collect()
+}
+
+fn lines(stops: &Rc<RefCell<Vec<Seen>>>) -> Vec<(usize, String)> {
+    stops
+        .borrow()
+        .iter()
+        .map(|seen| (seen.line, seen.function.clone()))
+        .collect()
+}
+
+#[test]
+fn stop_at_breakpoints() {
+    let source = r#"
+fun square(n) {
+    var result = n * n;
+    return result;
+}
+var total = 0;
+for (var i = 1; i <= 3; i = i + 1) {
+    total = total + square(i);
+}
+print total;
+"#;
+    let mut vm = VM::new();
+    let out = OutputBuffer::new();
+    vm.set_output(out.clone());
+    vm.set_breakpoint("", 3);
+    let stops = debug(&mut vm, |stop| {
+        assert_eq!(StopReason::Breakpoint, stop.reason);
+        let trace = stop.vm.backtrace();
+        assert_eq!(vec![3, 7], trace.iter().map(|f| f.line).collect::<Vec<_>>());
+        // the caller's locals are there too, as of the call.
+        assert_eq!(stop.vm.local(0, "n"), stop.vm.local(1, "i"));
+        // the debugger may change the breakpoints while the script is stopped.
+        if stop.vm.local(0, "n") == Some(Value::number(2.0)) {
+            stop.breakpoints.remove(&Breakpoint::new("", 3));
+        }
+        Resume::Continue
+    });
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    assert_eq!("14\n", out.contents());
+    assert_eq!(
+        vec![
+            Seen {
+                line: 3,
+                function: "square()".to_owned(),
+                locals: locals(&[("n", 1.0), ("result", 1.0)]),
+            },
+            Seen {
+                line: 3,
+                function: "square()".to_owned(),
+                locals: locals(&[("n", 2.0), ("result", 4.0)]),
+            },
+        ],
+        *stops.borrow()
+    );
+
+    // without a breakpoint, the script runs through.
+    stops.borrow_mut().clear();
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    assert!(stops.borrow().is_empty());
+    vm.set_breakpoint("", 9);
+    vm.clear_breakpoint("", 9);
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    assert!(stops.borrow().is_empty());
+}
+
+#[test]
+fn stop_at_every_iteration_of_a_loop() {
+    let source = "var i = 0;\nwhile (i < 3) i = i + 1;\nprint i;";
+    let mut vm = VM::new();
+    vm.set_output(OutputBuffer::new());
+    vm.set_breakpoint("", 1);
+    let values = Rc::new(RefCell::new(vec![]));
+    let seen_values = Rc::clone(&values);
+    debug(&mut vm, move |stop| {
+        seen_values.borrow_mut().push(stop.vm.global(0, "i"));
+        Resume::Continue
+    });
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    // the last stop is where the condition fails.
+    let expected: Vec<_> = [0.0, 1.0, 2.0, 3.0]
+        .iter()
+        .map(|&n| Some(Value::number(n)))
+        .collect();
+    assert_eq!(expected, *values.borrow());
+}
+
+const STEPS: &str = r#"
+fun inner() {
+    return 1;
+}
+fun outer() {
+    var a = inner();
+    return a + 1;
+}
+var x = outer();
+print x;
+"#;
+
+#[test]
+fn step_in_over_and_out() {
+    let mut vm = VM::new();
+    vm.set_output(OutputBuffer::new());
+    let stops = debug(&mut vm, |_| Resume::StepIn);
+    vm.pause();
+    assert_eq!(InterpretResult::Ok, vm.interpret(STEPS));
+    let script = |line: usize| (line, "script".to_owned());
+    let outer = |line: usize| (line, "outer()".to_owned());
+    let inner = |line: usize| (line, "inner()".to_owned());
+    assert_eq!(
+        vec![
+            script(3),
+            script(7),
+            script(8),
+            outer(5),
+            inner(2),
+            outer(6),
+            script(9),
+            script(10)
+        ],
+        lines(&stops)
+    );
+
+    // the last line is where the script returns.
+    // the step is forgotten once the script finishes.
+    stops.borrow_mut().clear();
+    assert_eq!(InterpretResult::Ok, vm.interpret(STEPS));
+    assert!(stops.borrow().is_empty());
+
+    let stops = debug(&mut vm, |_| Resume::StepOver);
+    vm.pause();
+    assert_eq!(InterpretResult::Ok, vm.interpret(STEPS));
+    assert_eq!(
+        vec![script(3), script(7), script(8), script(9), script(10)],
+        lines(&stops)
+    );
+
+    // stepping over stops at a breakpoint in the function it steps over,
+    // and stepping out goes back to the line after the call.
+    vm.set_breakpoint("", 2);
+    let stops = debug(&mut vm, |_| Resume::StepOut);
+    assert_eq!(InterpretResult::Ok, vm.interpret(STEPS));
+    assert_eq!(vec![inner(2), outer(6), script(9)], lines(&stops));
+}
+
+#[test]
+fn step_through_loops() {
+    let source = r#"
+var total = 0;
+for (var i = 0; i < 2; i = i + 1) {
+    total = total + i;
+}
+while (total < 3) total = total + 1;
+print total;
+"#;
+    let mut vm = VM::new();
+    vm.set_output(OutputBuffer::new());
+    let stops = debug(&mut vm, |_| Resume::StepIn);
+    vm.pause();
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    // a loop stops once per iteration, even when it all fits on one line,
+    // and the loop back to a `for` condition after the increment doesn't stop again.
+    let lines: Vec<_> = lines(&stops).into_iter().map(|(line, _)| line).collect();
+    assert_eq!(vec![1, 2, 3, 4, 2, 3, 4, 2, 4, 5, 5, 5, 6, 7], lines);
+}
+
+#[test]
+fn step_over_throws() {
+    let source = r#"
+fun fail() {
+    throw "oops";
+}
+try {
+    fail();
+} catch (e) {
+    print e;
+}
+print "done";
+"#;
+    let mut vm = VM::new();
+    vm.set_output(OutputBuffer::new());
+    vm.set_breakpoint("", 2);
+    // stepping over a throw goes on where it is caught.
+    let stops = debug(&mut vm, |_| Resume::StepOver);
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    assert_eq!(
+        vec![
+            (2, "fail()".to_owned()),
+            (7, "script".to_owned()),
+            (8, "script".to_owned()),
+            (9, "script".to_owned()),
+            (10, "script".to_owned())
+        ],
+        lines(&stops)
+    );
+}
+
+const SHADOWING: &str = r#"
+fun f(a) {
+    var b = a + 1;
+    {
+        var a = b * 10;
+        var c = a;
+        print c;
+    }
+    print a;
+}
+f(1);
+"#;
+
+// checks the locals at the breakpoints in `SHADOWING`, which the VM has to have set.
+fn check_shadowing(vm: &mut VM, result: impl FnOnce(&mut VM) -> InterpretResult) {
+    vm.set_output(OutputBuffer::new());
+    vm.set_breakpoint("", 6);
+    vm.set_breakpoint("", 8);
+    let stops = debug(vm, |stop| {
+        let vm = &stop.vm;
+        if stop.line == 6 {
+            assert_eq!(Some(Value::number(20.0)), vm.local(0, "a"));
+            assert_eq!(Some(Value::number(20.0)), vm.local(0, "c"));
+        } else {
+            assert_eq!(Some(Value::number(1.0)), vm.local(0, "a"));
+            assert_eq!(None, vm.local(0, "c"));
+        }
+        assert_eq!(None, vm.local(1, "a"));
+        assert_eq!(Vec::<(String, Value)>::new(), vm.locals(2));
+        Resume::Continue
+    });
+    assert_eq!(InterpretResult::Ok, result(vm));
+    let stops = stops.borrow();
+    assert_eq!(2, stops.len());
+    assert_eq!(
+        locals(&[("a", 1.0), ("b", 2.0), ("a", 20.0), ("c", 20.0)]),
+        stops[0].locals
+    );
+    assert_eq!(locals(&[("a", 1.0), ("b", 2.0)]), stops[1].locals);
+}
+
+#[test]
+fn inspect_shadowed_locals() {
+    for &level in [OptimizationLevel::None, OptimizationLevel::Full].iter() {
+        let mut vm = VM::new();
+        vm.set_optimization_level(level);
+        check_shadowing(&mut vm, |vm| vm.interpret(SHADOWING));
+    }
+}
+
+#[test]
+fn local_names_survive_bytecode() {
+    let mut vm = VM::new();
+    vm.set_optimization_level(OptimizationLevel::Full);
+    let func_id = vm.compile(SHADOWING).expect("failed to compile");
+    let bytes = serialize(&vm.allocator, &vm.globals, func_id);
+
+    let mut vm = VM::new();
+    check_shadowing(&mut vm, |vm| match vm.load(&bytes) {
+        Ok(func_id) => vm.execute(func_id),
+        Err(e) => panic!("failed to load: {}", e),
+    });
+}
+
+#[test]
+fn breakpoints_in_modules() {
+    let util = r#"
+var name = "util";
+fun twice(n) {
+    var result = n * 2;
+    return result;
+}
+"#;
+    let source = r#"
+var name = "main";
+import "lib/util.lox" as util;
+print util.twice(2);
+"#;
+    let mut vm = VM::new();
+    vm.set_output(OutputBuffer::new());
+    vm.set_resolver(
+        vec![("app/lib/util.lox".to_owned(), util.to_owned())]
+            .into_iter()
+            .collect::<HashMap<_, _>>(),
+    );
+    vm.set_script_path("app/main.lox");
+    // line 3 of the main script runs too, but the breakpoint is in the module.
+    vm.set_breakpoint("app/lib/util.lox", 3);
+    vm.set_breakpoint("app/other.lox", 2);
+    let paths = Rc::new(RefCell::new(vec![]));
+    let seen_paths = Rc::clone(&paths);
+    let stops = debug(&mut vm, move |stop| {
+        seen_paths.borrow_mut().push(stop.path.to_owned());
+        // globals are looked up in those of the module the frame runs the code of.
+        let name = stop.vm.global(0, "name").expect("name is defined");
+        assert_eq!(Ok("util".to_owned()), String::from_value(name, stop.vm));
+        let name = stop.vm.global(1, "name").expect("name is defined");
+        assert_eq!(Ok("main".to_owned()), String::from_value(name, stop.vm));
+        assert_eq!(None, stop.vm.global(0, "missing"));
+        Resume::Continue
+    });
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    assert_eq!(vec![(3, "twice()".to_owned())], lines(&stops));
+    assert_eq!(vec!["app/lib/util.lox".to_owned()], *paths.borrow());
+}
@@ -0,0 +1,204 @@
+extern crate lox;
+use lox::*;
+
+fn vm_with(source: &str) -> VM {
+    let mut vm = VM::new();
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    vm
+}
+
+#[test]
+fn call_functions() {
+    let mut vm = vm_with(
+        r#"
+fun add(a, b) {
+    return a + b;
+}
+fun nothing() {}
+var items = [1, 2, 3];
+class Point {
+    init(x, y) {
+        this.x = x;
+        this.y = y;
+    }
+}
+"#,
+    );
+    let sum = vm.call("add", &[Value::number(1.0), Value::number(2.0)]);
+    assert_eq!(Ok(Value::number(3.0)), sum);
+    assert_eq!(Ok(Value::NIL), vm.call("nothing", &[]));
+    assert!(vm.stack.is_empty());
+    assert!(vm.frames.is_empty());
+
+    // strings are allocated, so they are held through handles until the call.
+    let a = vm.handle("con");
+    let b = vm.handle("cat");
+    let joined = vm
+        .call("add", &[a.value(), b.value()])
+        .expect("failed to call add");
+    assert_eq!(Ok("concat".to_owned()), String::from_value(joined, &vm));
+
+    // natives and classes can be called too.
+    let items = vm.get_global::<Value>("items").expect("items is defined");
+    assert_eq!(Ok(Value::number(3.0)), vm.call("len", &[items]));
+    let point = vm
+        .call("Point", &[Value::number(1.0), Value::number(2.0)])
+        .expect("failed to create a point");
+    vm.set_global("point", point);
+    assert_eq!(
+        InterpretResult::Ok,
+        vm.interpret("var sum = point.x + point.y;")
+    );
+    assert_eq!(Ok(3.0), vm.get_global::<f64>("sum"));
+}
+
+#[test]
+fn call_errors() {
+    let mut vm = vm_with(
+        r#"
+fun fail(value) {
+    return value + 1;
+}
+fun throws() {
+    throw "oops";
+}
+var number = 1;
+"#,
+    );
+    assert_eq!(
+        Err(Error::UndefinedVariable("missing".to_owned())),
+        vm.call("missing", &[])
+    );
+    let e = match vm.call("fail", &[Value::NIL]) {
+        Err(Error::Runtime(e)) => e,
+        other => panic!("unexpected result: {:?}", other),
+    };
+    assert_eq!(
+        "Operands must be two numbers or two strings.\n[line 2] in fail()",
+        e.to_string()
+    );
+    let cases = [
+        ("fail", "Expected 1 arguments but got 0."),
+        ("throws", "Uncaught exception: oops"),
+        ("number", "Can only call functions and classes."),
+    ];
+    for &(name, message) in cases.iter() {
+        match vm.call(name, &[]) {
+            Err(Error::Runtime(e)) => assert_eq!(message, e.message),
+            other => panic!("unexpected result for {}: {:?}", name, other),
+        }
+    }
+    assert!(vm.stack.is_empty());
+    assert!(vm.frames.is_empty());
+    assert_eq!(
+        Ok(Value::number(2.0)),
+        vm.call("fail", &[Value::number(1.0)])
+    );
+}
+
+#[test]
+fn get_and_set_globals() {
+    let mut vm = VM::new();
+    vm.set_global("number", 1.5);
+    vm.set_global("flag", true);
+    vm.set_global("name", "lox".to_owned());
+    vm.set_global("missing", None::<f64>);
+    vm.set_global("present", Some("here"));
+    let out = OutputBuffer::new();
+    vm.set_output(out.clone());
+    let source = r#"
+print number;
+print flag;
+print name;
+print missing;
+print present;
+var copy = name + "!";
+"#;
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    assert_eq!("1.5\ntrue\nlox\nnil\nhere\n", out.contents());
+
+    assert_eq!(Ok(1.5), vm.get_global::<f64>("number"));
+    assert_eq!(Ok(true), vm.get_global::<bool>("flag"));
+    assert_eq!(Ok("lox!".to_owned()), vm.get_global::<String>("copy"));
+    assert_eq!(Ok(None), vm.get_global::<Option<f64>>("missing"));
+    assert_eq!(Ok(Some(1.5)), vm.get_global::<Option<f64>>("number"));
+    assert_eq!(
+        Err(Error::UndefinedVariable("nothing".to_owned())),
+        vm.get_global::<f64>("nothing")
+    );
+    let e = vm.get_global::<f64>("name").expect_err("name is a string");
+    assert_eq!(
+        Error::WrongType {
+            expected: "a number",
+            found: "a string"
+        },
+        e
+    );
+    assert_eq!("Expected a number but got a string.", e.to_string());
+    assert_eq!(
+        "Expected a boolean but got nil.",
+        vm.get_global::<bool>("missing")
+            .expect_err("missing is nil")
+            .to_string()
+    );
+}
+
+#[test]
+fn handles_keep_values_alive() {
+    let mut vm = vm_with(
+        r#"
+fun make_counter() {
+    var count = 0;
+    return fun () {
+        count = count + 1;
+        return count;
+    };
+}
+"#,
+    );
+    vm.allocator.set_stress_gc(true);
+
+    let counter = vm
+        .call("make_counter", &[])
+        .expect("failed to make a counter");
+    let counter = vm.handle(counter);
+    let kept = vm.handle("kept");
+    let copy = kept.clone();
+    drop(kept);
+
+    // only the handles hold onto the counter and the string from here on.
+    assert_eq!(
+        InterpretResult::Ok,
+        vm.interpret(
+            "var garbage = []; for (var i = 0; i < 10; i = i + 1) push(garbage, [i, \"x\" + \"y\"]);"
+        )
+    );
+    assert_eq!(Ok(Value::number(1.0)), vm.apply(counter.value(), &[]));
+    assert_eq!(Ok(Value::number(2.0)), vm.apply(counter.value(), &[]));
+    assert_eq!(Ok("kept".to_owned()), String::from_value(copy.value(), &vm));
+
+    // a handle can be taken straight from a global too.
+    vm.set_global("counter", &counter);
+    let global: Handle = vm.get_global("counter").expect("counter is defined");
+    vm.set_global("counter", None::<f64>);
+    assert_eq!(Ok(Value::number(3.0)), vm.apply(global.value(), &[]));
+}
+
+#[test]
+fn calls_are_limited() {
+    let mut vm = vm_with("fun spin() { while (true) {} }\nfun one() { return 1; }");
+    vm.set_limits(Limits {
+        fuel: Some(1000),
+        ..Limits::default()
+    });
+    assert_eq!(Err(Error::LimitExceeded(Limit::Fuel)), vm.call("spin", &[]));
+    assert!(vm.frames.is_empty());
+
+    // each call gets its own fuel, and abandons a suspended script.
+    assert_eq!(
+        InterpretResult::LimitExceeded(Limit::Fuel),
+        vm.interpret("spin();")
+    );
+    assert_eq!(Ok(Value::number(1.0)), vm.call("one", &[]));
+    assert_eq!(InterpretResult::Ok, vm.resume());
+}
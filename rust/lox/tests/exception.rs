@@ -0,0 +1,294 @@
+extern crate lox;
+use crate::common::run;
+use lox::*;
+
+fn expect_output(source: &str, expected: &str) {
+    for &level in [OptimizationLevel::None, OptimizationLevel::Full].iter() {
+        assert_eq!(
+            (InterpretResult::Ok, expected.to_owned()),
+            run(source, level),
+            "{}",
+            source
+        );
+    }
+}
+
+#[test]
+fn run_throw_and_catch() {
+    let source = r#"
+try {
+    print "before";
+    throw "oops";
+    print "after";
+} catch (e) {
+    print "caught " + e;
+}
+var list = [1];
+try {
+    throw list;
+} catch (e) {
+    print e == list;
+}
+print "done";
+"#;
+    expect_output(source, "before\ncaught oops\ntrue\ndone\n");
+}
+
+#[test]
+fn run_catch_runtime_errors() {
+    let source = r#"
+try {
+    print 1 + nil;
+} catch (e) {
+    print e.message;
+    print e.line;
+}
+try {
+    print missing;
+} catch (e) {
+    print e.message;
+    print e;
+}
+try {
+    nil.field;
+} catch (e) {
+    print e.message;
+}
+"#;
+    expect_output(
+        source,
+        "Operands must be two numbers or two strings.\n2\nUndefined variable 'missing'.\n\
+         Error instance\nOnly instances have properties.\n",
+    );
+}
+
+#[test]
+fn run_unwind_through_frames() {
+    let source = r#"
+fun inner(n) {
+    var local = "local";
+    if (n == 0) throw "bottom";
+    inner(n - 1);
+}
+fun outer() {
+    var a = "a";
+    try {
+        inner(3);
+    } catch (e) {
+        print a + " " + e;
+    }
+    return "returned";
+}
+print outer();
+var b = "b";
+print b;
+"#;
+    expect_output(source, "a bottom\nreturned\nb\n");
+}
+
+#[test]
+fn run_finally() {
+    let source = r#"
+try {
+    print "try";
+} finally {
+    print "finally";
+}
+try {
+    try {
+        throw "inner";
+    } finally {
+        print "cleanup";
+    }
+} catch (e) {
+    print "outer " + e;
+}
+fun f() {
+    try {
+        return "from try";
+    } finally {
+        print "f finally";
+    }
+    return "unreachable";
+}
+print f();
+fun g() {
+    try {
+        throw "error";
+    } catch (e) {
+        return "from catch " + e;
+    } finally {
+        print "g finally";
+    }
+}
+print g();
+fun h() {
+    var x = "h";
+    try {
+        try {
+            var y = "y";
+            return x + y;
+        } finally {
+            print "first";
+        }
+    } finally {
+        print "second";
+    }
+}
+print h();
+"#;
+    expect_output(
+        source,
+        "try\nfinally\ncleanup\nouter inner\nf finally\nfrom try\n\
+         g finally\nfrom catch error\nfirst\nsecond\nhy\n",
+    );
+}
+
+#[test]
+fn run_throw_from_catch_and_finally() {
+    let source = r#"
+try {
+    try {
+        throw "first";
+    } catch (e) {
+        throw e + " again";
+    } finally {
+        print "finally";
+    }
+} catch (e) {
+    print e;
+}
+try {
+    try {
+        throw "lost";
+    } finally {
+        throw "replaced";
+    }
+} catch (e) {
+    print e;
+}
+"#;
+    expect_output(source, "finally\nfirst again\nreplaced\n");
+}
+
+#[test]
+fn run_closures_captured_in_try() {
+    let source = r#"
+fun make() {
+    var count = 0;
+    try {
+        var step = 2;
+        fun add() {
+            count = count + step;
+            return count;
+        }
+        return add;
+    } finally {
+        count = 10;
+    }
+}
+var add = make();
+print add();
+fun fail() {
+    try {
+        var captured = "captured";
+        fun get() { return captured; }
+        throw get;
+    } catch (e) {
+        return e;
+    }
+}
+print fail()();
+"#;
+    expect_output(source, "12\ncaptured\n");
+}
+
+#[test]
+fn run_uncaught_exceptions() {
+    let cases = [
+        ("throw \"oops\";", "Uncaught exception: oops", 0),
+        ("\nthrow 1 + 2;", "Uncaught exception: 3", 1),
+        (
+            "try { 1 + nil; } catch (e) { throw e; }",
+            "Operands must be two numbers or two strings.",
+            0,
+        ),
+        (
+            "fun f() {\n  try { throw \"inner\"; } finally { print 1; }\n}\nf();",
+            "Uncaught exception: inner",
+            1,
+        ),
+    ];
+    for &(source, message, line) in cases.iter() {
+        match run(source, OptimizationLevel::None).0 {
+            InterpretResult::RuntimeError(e) => {
+                assert_eq!(message, e.message, "{}", source);
+                assert_eq!(line, e.line, "{}", source);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    // the VM can go on interpreting afterwards.
+    let mut vm = VM::new();
+    assert!(matches!(
+        vm.interpret("fun f() { throw 1; } f();"),
+        InterpretResult::RuntimeError(_)
+    ));
+    assert!(vm.stack.is_empty());
+    assert!(vm.frames.is_empty());
+    assert_eq!(
+        InterpretResult::Ok,
+        vm.interpret("try { f(); } catch (e) {}")
+    );
+}
+
+#[test]
+fn run_try_compile_errors() {
+    let cases = [
+        ("try { }", "Expect 'catch' or 'finally' after try block."),
+        ("try print 1;", "Expect '{' after 'try'."),
+        ("try { } catch e { }", "Expect '(' after 'catch'."),
+        ("try { } catch () { }", "Expect exception variable name."),
+        ("throw;", "Expect expression."),
+        ("throw 1", "Expect ';' after thrown value."),
+    ];
+    for &(source, message) in cases.iter() {
+        let mut vm = VM::new();
+        match vm.interpret(source) {
+            InterpretResult::CompileError(errors) => {
+                assert_eq!(message, errors[0].message, "{}", source);
+            }
+            other => panic!("unexpected result for {}: {:?}", source, other),
+        }
+    }
+}
+
+#[test]
+fn handlers_survive_loading() {
+    let source = r#"
+fun f(n) {
+    try {
+        if (n > 1) throw n * 2;
+        return n;
+    } catch (e) {
+        return -e;
+    } finally {
+        print "f";
+    }
+}
+print f(1);
+print f(2);
+"#;
+    let mut vm = VM::new();
+    vm.set_optimization_level(OptimizationLevel::Full);
+    let func_id = vm.compile(source).expect("failed to compile");
+    let bytes = serialize(&vm.allocator, &vm.globals, func_id);
+
+    let mut vm = VM::new();
+    let out = OutputBuffer::new();
+    vm.set_output(out.clone());
+    let func_id = vm.load(&bytes).expect("failed to load");
+    assert_eq!(InterpretResult::Ok, vm.execute(func_id));
+    assert_eq!("f\n1\nf\n-4\n", out.contents());
+}
@@ -0,0 +1,97 @@
+extern crate lox;
+use lox::*;
+
+#[test]
+fn run_stress_gc() {
+    let source = r#"
+fun make_counter() {
+    var count = 0;
+    fun counter() {
+        count = count + 1;
+        return count;
+    }
+    return counter;
+}
+class Greeter {
+    init(name) {
+        this.name = name;
+    }
+    greet() {
+        return "hello " + this.name;
+    }
+}
+class LoudGreeter < Greeter {
+    greet() {
+        return super.greet() + "!";
+    }
+}
+var counter = make_counter();
+var greeting = "";
+for (var i = 0; i < 10; i = i + 1) {
+    counter();
+    greeting = LoudGreeter("lox" + "!").greet();
+}
+var count = counter();
+"#;
+    let mut vm = VM::new();
+    vm.allocator.set_stress_gc(true);
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    let k = &vm.allocator.new_string("count".to_owned());
+    assert_eq!(11_f64, vm.globals.get(k).expect("no such key").as_number());
+    let k = &vm.allocator.new_string("greeting".to_owned());
+    assert_eq!(
+        "hello lox!!",
+        vm.allocator
+            .deref(&vm.globals.get(k).expect("no such key").as_string())
+    );
+}
+
+#[test]
+fn run_gc_frees_unreachable_strings() {
+    let source = r#"
+var s = "";
+for (var i = 0; i < 3000; i = i + 1) {
+    s = s + "x";
+}
+"#;
+    let mut vm = VM::new();
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    // keeping every intermediate string alive would take about 4.5MB.
+    assert!(vm.allocator.bytes_allocated() < 4 * 1024 * 1024);
+
+    let mut vm = VM::new();
+    vm.allocator.set_stress_gc(true);
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    assert!(vm.allocator.bytes_allocated() < 64 * 1024);
+    let k = &vm.allocator.new_string("s".to_owned());
+    assert_eq!(
+        3000,
+        vm.allocator
+            .deref(&vm.globals.get(k).expect("no such key").as_string())
+            .len()
+    );
+}
+
+#[test]
+fn run_gc_self_referencing_objects() {
+    let source = r#"
+class Node {}
+var node = Node();
+node.next = node;
+var xs = [1];
+push(xs, xs);
+for (var i = 0; i < 3; i = i + 1) {
+    node.name = "node" + "!";
+}
+var name = node.next.next.name;
+"#;
+    let mut vm = VM::new();
+    vm.allocator.set_stress_gc(true);
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    let k = &vm.allocator.new_string("name".to_owned());
+    assert_eq!(
+        "node!",
+        vm.allocator
+            .deref(&vm.globals.get(k).expect("no such key").as_string())
+    );
+}
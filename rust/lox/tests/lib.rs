@@ -1,2 +1,17 @@
+mod bytecode;
+mod chunk;
+mod cli;
+mod common;
 mod compiler;
+mod debugger;
+mod embed;
+mod exception;
+mod gc;
+mod limits;
+mod list;
+mod map;
+mod module;
+mod native;
+mod optimizer;
 mod scanner;
+mod value;
@@ -0,0 +1,154 @@
+extern crate lox;
+use lox::*;
+
+fn vm_with_limits(limits: Limits) -> (VM, OutputBuffer) {
+    let mut vm = VM::new();
+    vm.set_limits(limits);
+    let out = OutputBuffer::new();
+    vm.set_output(out.clone());
+    (vm, out)
+}
+
+#[test]
+fn run_out_of_fuel() {
+    let (mut vm, out) = vm_with_limits(Limits {
+        fuel: Some(1000),
+        ..Limits::default()
+    });
+    let source = "try { while (true) {} } catch (e) { print \"caught\"; }";
+    assert_eq!(
+        InterpretResult::LimitExceeded(Limit::Fuel),
+        vm.interpret(source)
+    );
+    assert_eq!(Some(0), vm.fuel());
+    // a limit isn't an exception, so scripts can't catch it.
+    assert_eq!("", out.contents());
+
+    // the fuel is refilled for every script, abandoning the suspended one.
+    assert_eq!(InterpretResult::Ok, vm.interpret("print \"next\";"));
+    assert_eq!("next\n", out.contents());
+    assert!(vm.stack.is_empty());
+    assert!(vm.frames.is_empty());
+    assert_eq!(Some(996), vm.fuel());
+}
+
+#[test]
+fn resume_with_more_fuel() {
+    let (mut vm, out) = vm_with_limits(Limits {
+        fuel: Some(100),
+        ..Limits::default()
+    });
+    let source = r#"
+var total = 0;
+for (var i = 0; i < 1000; i = i + 1) {
+    total = total + i;
+}
+print total;
+"#;
+    let mut result = vm.interpret(source);
+    let mut suspensions = 0;
+    while result == InterpretResult::LimitExceeded(Limit::Fuel) {
+        suspensions += 1;
+        vm.add_fuel(100);
+        result = vm.resume();
+    }
+    assert_eq!(InterpretResult::Ok, result);
+    assert!(suspensions > 10);
+    assert_eq!("499500\n", out.contents());
+
+    // there is nothing left to resume.
+    assert_eq!(InterpretResult::Ok, vm.resume());
+    assert_eq!("499500\n", out.contents());
+
+    // without a fuel limit, there is no fuel to add.
+    vm.set_limits(Limits::default());
+    vm.add_fuel(100);
+    assert_eq!(None, vm.fuel());
+}
+
+#[test]
+fn run_out_of_heap() {
+    let mut vm = VM::new();
+    let max_heap = vm.allocator.bytes_allocated() + 64 * 1024;
+    vm.set_limits(Limits {
+        max_heap: Some(max_heap),
+        ..Limits::default()
+    });
+    // garbage doesn't count against the limit.
+    let source = r#"
+var last;
+for (var i = 0; i < 10000; i = i + 1) {
+    last = [i, "garbage " + "string", {"key": i}];
+}
+"#;
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    assert!(vm.allocator.bytes_allocated() <= max_heap);
+
+    // a list growing counts against it, though it allocates nothing new.
+    let source = r#"
+var list = [];
+while (true) {
+    push(list, "item");
+}
+"#;
+    assert_eq!(
+        InterpretResult::LimitExceeded(Limit::Heap),
+        vm.interpret(source)
+    );
+    assert!(vm.allocator.bytes_allocated() > max_heap);
+}
+
+#[test]
+fn run_out_of_heap_inheriting_methods() {
+    let mut vm = VM::new();
+    vm.allocator.set_stress_gc(true);
+    let methods: Vec<String> = (0..200).map(|i| format!("m{}() {{}}", i)).collect();
+    let source = format!("class Base {{ {} }}", methods.join(" "));
+    assert_eq!(InterpretResult::Ok, vm.interpret(&source));
+    // another script lets the collector free the first one.
+    assert_eq!(InterpretResult::Ok, vm.interpret(""));
+
+    // the methods copied into a subclass count against the limit,
+    // though only the subclass itself is allocated.
+    vm.set_limits(Limits {
+        max_heap: Some(vm.allocator.bytes_allocated() + 1024),
+        ..Limits::default()
+    });
+    assert_eq!(
+        InterpretResult::LimitExceeded(Limit::Heap),
+        vm.interpret("class Derived < Base {}")
+    );
+}
+
+#[test]
+fn run_out_of_stack() {
+    let (mut vm, out) = vm_with_limits(Limits {
+        max_stack: Some(40),
+        ..Limits::default()
+    });
+    let source = r#"
+fun depth(n) {
+    if (n == 0) return 0;
+    return 1 + depth(n - 1);
+}
+print depth(50);
+"#;
+    assert_eq!(
+        InterpretResult::LimitExceeded(Limit::Stack),
+        vm.interpret(source)
+    );
+
+    // raising the limit lets the script carry on where it was suspended.
+    vm.set_limits(Limits {
+        max_stack: Some(1000),
+        ..Limits::default()
+    });
+    assert_eq!(InterpretResult::Ok, vm.resume());
+    assert_eq!("50\n", out.contents());
+
+    // the depth of calls is still limited by the frames on their own.
+    match vm.interpret("fun f() { f(); } f();") {
+        InterpretResult::RuntimeError(e) => assert_eq!("Stack overflow.", e.message),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
@@ -0,0 +1,129 @@
+extern crate lox;
+use crate::common::{run_output, runtime_error};
+use lox::*;
+
+#[test]
+fn run_list_literal() {
+    let source = r#"
+var xs = [1, "two", [3, nil], true];
+print xs;
+print [];
+print xs[1];
+print xs[2][0];
+print len(xs);
+"#;
+    let mut vm = VM::new();
+    assert_eq!(
+        "[1, two, [3, nil], true]\n[]\ntwo\n3\n4\n",
+        run_output(&mut vm, source)
+    );
+}
+
+#[test]
+fn run_list_set_index() {
+    let source = r#"
+var xs = [1, 2, 3];
+xs[0] = xs[1] = 5;
+xs[2] = xs[2] * 10;
+print xs;
+
+class Box {}
+var b = Box();
+b.items = [0];
+b.items[0] = "field";
+print b.items;
+"#;
+    let mut vm = VM::new();
+    assert_eq!("[5, 5, 30]\n[field]\n", run_output(&mut vm, source));
+}
+
+#[test]
+fn run_list_natives() {
+    let source = r#"
+var xs = [];
+for (var i = 0; i < 3; i = i + 1) {
+    push(xs, i);
+}
+print xs;
+print pop(xs);
+insert(xs, 0, "a");
+insert(xs, 3, "z");
+print xs;
+print remove(xs, 1);
+print xs;
+print len(xs);
+"#;
+    let mut vm = VM::new();
+    assert_eq!(
+        "[0, 1, 2]\n2\n[a, 0, 1, z]\n0\n[a, 1, z]\n3\n",
+        run_output(&mut vm, source)
+    );
+}
+
+#[test]
+fn run_list_errors() {
+    let mut vm = VM::new();
+    let cases = [
+        ("[1, 2][-1];", "List index -1 is negative."),
+        (
+            "[1, 2][2];",
+            "List index 2 is out of range for a list of length 2.",
+        ),
+        ("[1, 2][0.5];", "List index must be an integer."),
+        (r#"[1, 2]["0"];"#, "List index must be an integer."),
+        (
+            "var xs = [1]; xs[1] = 2;",
+            "List index 1 is out of range for a list of length 1.",
+        ),
+        ("1[0];", "Only lists and maps can be indexed."),
+        ("pop([]);", "Can't pop from an empty list."),
+        (
+            "insert([1], 2, 0);",
+            "List index 2 is out of range for a list of length 1.",
+        ),
+        (
+            "remove([], 0);",
+            "List index 0 is out of range for a list of length 0.",
+        ),
+        (r#"push("a", 1);"#, "Argument 1 must be a list."),
+    ];
+    for (source, message) in cases.iter() {
+        assert_eq!(
+            *message,
+            runtime_error(vm.interpret(source)).message,
+            "{}",
+            source
+        );
+    }
+}
+
+#[test]
+fn run_list_syntax_errors() {
+    let mut vm = VM::new();
+    match vm.interpret("var xs = [1, 2;\nxs[0;") {
+        InterpretResult::CompileError(errors) => {
+            let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+            assert_eq!(
+                vec!["Expect ']' after list items.", "Expect ']' after index."],
+                messages
+            );
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn run_list_survives_gc() {
+    let source = r#"
+var xs = [];
+for (var i = 0; i < 50; i = i + 1) {
+    push(xs, [to_string(i)]);
+}
+var nested = xs;
+push(nested, nested);
+print xs[49][0] + xs[0][0];
+"#;
+    let mut vm = VM::new();
+    vm.allocator.set_stress_gc(true);
+    assert_eq!("490\n", run_output(&mut vm, source));
+}
@@ -0,0 +1,152 @@
+extern crate lox;
+use crate::common::{run_output, runtime_error};
+use lox::*;
+
+#[test]
+fn run_map_literal() {
+    let source = r#"
+var m = {"b": 1, "a": [2], 3: "three", true: nil, nil: false};
+print m;
+print {};
+print m["a"][0];
+print m[3];
+print m[nil];
+print len(m);
+"#;
+    let mut vm = VM::new();
+    assert_eq!(
+        "{b: 1, a: [2], 3: three, true: nil, nil: false}\n{}\n2\nthree\nfalse\n5\n",
+        run_output(&mut vm, source)
+    );
+}
+
+#[test]
+fn run_map_set_index() {
+    let source = r#"
+var m = {"x": 1};
+m["y"] = m["x"] = 10;
+m["z" + ""] = 3;
+m[0] = "zero";
+m[-0] = "still zero";
+print m;
+
+{
+    var local = {};
+    local["k"] = "block";
+    print local["k"];
+}
+"#;
+    let mut vm = VM::new();
+    assert_eq!(
+        "{x: 10, y: 10, z: 3, 0: still zero}\nblock\n",
+        run_output(&mut vm, source)
+    );
+}
+
+#[test]
+fn run_map_natives() {
+    let source = r#"
+var m = {"one": 1, "two": 2, "three": 3};
+print has(m, "two");
+print has(m, "four");
+print remove(m, "two");
+print remove(m, "two");
+m["two"] = 22;
+print keys(m);
+print values(m);
+
+var total = 0;
+var ks = keys(m);
+for (var i = 0; i < len(ks); i = i + 1) {
+    total = total + m[ks[i]];
+}
+print total;
+"#;
+    let mut vm = VM::new();
+    assert_eq!(
+        "true\nfalse\n2\nnil\n[one, three, two]\n[1, 3, 22]\n26\n",
+        run_output(&mut vm, source)
+    );
+}
+
+#[test]
+fn remove_keeps_insertion_order() {
+    let mut map = Map::new();
+    for i in 0..1000 {
+        map.insert(Value::number(i as f64), Value::number(i as f64 * 2.0))
+            .expect("failed to insert");
+    }
+    for i in (0..1000).step_by(2) {
+        let removed = map.remove(Value::number(i as f64));
+        assert_eq!(Ok(Some(Value::number(i as f64 * 2.0))), removed);
+    }
+    assert_eq!(Ok(None), map.remove(Value::number(0.0)));
+    assert_eq!(500, map.len());
+    let odd: Vec<Value> = (1..1000)
+        .step_by(2)
+        .map(|i| Value::number(i as f64))
+        .collect();
+    assert_eq!(odd, map.keys().collect::<Vec<_>>());
+    assert_eq!(Ok(Some(Value::number(2.0))), map.get(Value::number(1.0)));
+
+    // a key inserted again goes last, however many entries were removed before it.
+    map.insert(Value::number(0.0), Value::NIL)
+        .expect("failed to insert");
+    assert_eq!(Some(Value::number(0.0)), map.keys().last());
+    for i in 0..1000 {
+        map.remove(Value::number(i as f64))
+            .expect("failed to remove");
+    }
+    assert!(map.is_empty());
+    assert_eq!(0, map.iter().count());
+}
+
+#[test]
+fn run_map_errors() {
+    let mut vm = VM::new();
+    let cases = [
+        (r#"var v = {"a": 1}["b"];"#, "Undefined key 'b'."),
+        (
+            "var m = {}; m[[]] = 1;",
+            "Map keys must be strings, numbers, booleans or nil.",
+        ),
+        ("({})[0/0];", "Map key can't be NaN."),
+        (
+            "var l = []; var m = {l: 1};",
+            "Map keys must be strings, numbers, booleans or nil.",
+        ),
+        (r#"has([], "a");"#, "Argument 1 must be a map."),
+        ("keys(1);", "Argument 1 must be a map."),
+    ];
+    for (source, message) in cases.iter() {
+        assert_eq!(
+            *message,
+            runtime_error(vm.interpret(source)).message,
+            "{}",
+            source
+        );
+    }
+
+    match vm.interpret(r#"var m = {"a" 1};"#) {
+        InterpretResult::CompileError(errors) => {
+            assert_eq!("Expect ':' after map key.", errors[0].message)
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn run_map_survives_gc() {
+    let source = r#"
+var m = {};
+for (var i = 0; i < 50; i = i + 1) {
+    m[to_string(i)] = {"value": to_string(i * 2)};
+}
+m["self"] = m;
+print m["49"]["value"] + m["self"]["0"]["value"];
+print len(keys(m));
+"#;
+    let mut vm = VM::new();
+    vm.allocator.set_stress_gc(true);
+    assert_eq!("980\n51\n", run_output(&mut vm, source));
+}
@@ -0,0 +1,249 @@
+extern crate lox;
+use crate::common::runtime_error;
+use lox::*;
+use std::collections::HashMap;
+use std::fs;
+
+fn modules(files: &[(&str, &str)]) -> HashMap<String, String> {
+    files
+        .iter()
+        .map(|&(path, source)| (path.to_owned(), source.to_owned()))
+        .collect()
+}
+
+fn run(source: &str, files: &[(&str, &str)]) -> (InterpretResult, String) {
+    let mut vm = VM::new();
+    vm.set_resolver(modules(files));
+    let out = OutputBuffer::new();
+    vm.set_output(out.clone());
+    let result = vm.interpret(source);
+    (result, out.contents())
+}
+
+const UTIL: &str = r#"
+print "loading util";
+var name = "util";
+var count = 0;
+fun bump() {
+    count = count + 1;
+    return count;
+}
+class Point {
+    init(x, y) {
+        this.x = x;
+        this.y = y;
+    }
+    sum() {
+        return this.x + this.y;
+    }
+}
+"#;
+
+#[test]
+fn run_import() {
+    let source = r#"
+import "util.lox" as util;
+print util;
+print util.name;
+print util.bump();
+print util.Point(1, 2).sum();
+var bump = util.bump;
+print bump();
+print util.count;
+"#;
+    let (result, out) = run(source, &[("util.lox", UTIL)]);
+    assert_eq!(InterpretResult::Ok, result);
+    assert_eq!("loading util\n<module util.lox>\nutil\n1\n3\n2\n2\n", out);
+}
+
+#[test]
+fn run_imported_once() {
+    let source = r#"
+import "util.lox" as a;
+fun f() {
+    import "util.lox" as b;
+    return b;
+}
+print a == f();
+a.bump();
+print f().count;
+"#;
+    let (result, out) = run(source, &[("util.lox", UTIL)]);
+    assert_eq!(InterpretResult::Ok, result);
+    assert_eq!("loading util\ntrue\n1\n", out);
+}
+
+#[test]
+fn run_modules_have_own_globals() {
+    let source = r#"
+var name = "main";
+var count = 100;
+import "util.lox" as util;
+util.bump();
+print name;
+print count;
+print util.name;
+print util.count;
+"#;
+    let (result, out) = run(source, &[("util.lox", UTIL)]);
+    assert_eq!(InterpretResult::Ok, result);
+    assert_eq!("loading util\nmain\n100\nutil\n1\n", out);
+
+    // a module sees the natives, but none of the importing script's globals.
+    let files = [("peek.lox", "var has_clock = clock() >= 0;\nprint secret;")];
+    let e = runtime_error(run("var secret = 1;\nimport \"peek.lox\" as peek;", &files).0);
+    assert_eq!("Undefined variable 'secret'.", e.message);
+    assert_eq!(1, e.line);
+    assert_eq!(
+        "Undefined variable 'secret'.\n[line 1] in peek.lox\n[line 1] in script",
+        e.to_string()
+    );
+}
+
+#[test]
+fn run_relative_imports() {
+    let files = [
+        ("app/main.lox", "import \"lib/a.lox\" as a;\nvar value = a.value;"),
+        (
+            "app/lib/a.lox",
+            "import \"./b.lox\" as b;\nimport \"../../shared.lox\" as shared;\nvar value = b.value + shared.value;",
+        ),
+        ("app/lib/b.lox", "var value = \"b\";"),
+        ("shared.lox", "var value = \"shared\";"),
+    ];
+    let (result, out) = run(
+        "import \"app/main.lox\" as main;\nprint main.value;",
+        &files,
+    );
+    assert_eq!(InterpretResult::Ok, result);
+    assert_eq!("bshared\n", out);
+
+    // the main script's imports are relative to its own path.
+    let mut vm = VM::new();
+    vm.set_resolver(modules(&files));
+    vm.set_script_path("app/run.lox");
+    assert_eq!(
+        InterpretResult::Ok,
+        vm.interpret("import \"main.lox\" as m; import \"lib/b.lox\" as b;")
+    );
+}
+
+#[test]
+fn run_import_cycle() {
+    let files = [
+        ("a.lox", "import \"b.lox\" as b;"),
+        ("b.lox", "import \"c.lox\" as c;"),
+        ("c.lox", "import \"b.lox\" as b;"),
+    ];
+    let e = runtime_error(run("import \"a.lox\" as a;", &files).0);
+    assert_eq!("Import cycle: b.lox -> c.lox -> b.lox.", e.message);
+    assert_eq!(4, e.trace.len());
+}
+
+#[test]
+fn run_import_errors() {
+    let e = runtime_error(run("import \"missing.lox\" as m;", &[]).0);
+    assert_eq!(
+        "Could not import \"missing.lox\": no such module.",
+        e.message
+    );
+
+    let e = runtime_error(run("import \"bad.lox\" as m;", &[("bad.lox", "var = 1;")]).0);
+    assert_eq!(
+        "Could not compile \"bad.lox\":\n[line 0:4] Error at '=': Expect variable name",
+        e.message
+    );
+
+    let e = runtime_error(
+        run(
+            "import \"util.lox\" as m; m.nothing;",
+            &[("util.lox", UTIL)],
+        )
+        .0,
+    );
+    assert_eq!("Undefined property 'nothing'.", e.message);
+
+    let mut vm = VM::new();
+    match vm.interpret("import \"a.lox\" b;\nimport c as d;\n") {
+        InterpretResult::CompileError(errors) => {
+            let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+            assert_eq!(
+                vec![
+                    "Expect 'as' after module path.",
+                    "Expect module path after 'import'."
+                ],
+                messages
+            );
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    let e = runtime_error(run("import \"r.lox\" as r;", &[("r.lox", "return 1;")]).0);
+    assert!(e.message.ends_with("Can't return from top-level code."));
+}
+
+#[test]
+fn run_failed_import_runs_again() {
+    let files = [("flaky.lox", "print \"running\";\nthrow \"failed\";")];
+    let source = r#"
+for (var i = 0; i < 2; i = i + 1) {
+    try {
+        import "flaky.lox" as flaky;
+    } catch (e) {
+        print e;
+    }
+}
+"#;
+    let (result, out) = run(source, &files);
+    assert_eq!(InterpretResult::Ok, result);
+    assert_eq!("running\nfailed\nrunning\nfailed\n", out);
+
+    // nor is a module cached when the script importing it fails.
+    let mut vm = VM::new();
+    vm.set_resolver(modules(&files));
+    let out = OutputBuffer::new();
+    vm.set_output(out.clone());
+    runtime_error(vm.interpret("import \"flaky.lox\" as flaky;"));
+    runtime_error(vm.interpret("import \"flaky.lox\" as flaky;"));
+    assert_eq!("running\nrunning\n", out.contents());
+}
+
+#[test]
+fn run_import_with_stress_gc() {
+    let source = r#"
+import "util.lox" as util;
+var total = 0;
+for (var i = 0; i < 10; i = i + 1) {
+    total = total + util.Point(i, util.bump()).sum();
+}
+print total;
+print util.name + "!";
+"#;
+    let mut vm = VM::new();
+    vm.allocator.set_stress_gc(true);
+    vm.set_resolver(modules(&[("util.lox", UTIL)]));
+    let out = OutputBuffer::new();
+    vm.set_output(out.clone());
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    assert_eq!("loading util\n100\nutil!\n", out.contents());
+}
+
+#[test]
+fn run_import_from_files() {
+    let dir = std::env::temp_dir().join(format!("lox-module-test-{}", std::process::id()));
+    fs::create_dir_all(dir.join("lib")).expect("failed to create the test directory");
+    fs::write(
+        dir.join("lib/greet.lox"),
+        "fun greet(name) { return \"hello \" + name; }",
+    )
+    .expect("failed to write the module");
+
+    let mut vm = VM::new();
+    let out = OutputBuffer::new();
+    vm.set_output(out.clone());
+    vm.set_script_path(dir.join("main.lox").to_str().expect("non UTF-8 path"));
+    let result = vm.interpret("import \"lib/greet.lox\" as g;\nprint g.greet(\"files\");");
+    fs::remove_dir_all(&dir).expect("failed to remove the test directory");
+
+    assert_eq!(InterpretResult::Ok, result);
+    assert_eq!("hello files\n", out.contents());
+}
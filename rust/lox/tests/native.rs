@@ -0,0 +1,189 @@
+extern crate lox;
+use crate::common::{run_output, runtime_error};
+use lox::*;
+use std::cell::Cell;
+use std::rc::Rc;
+
+#[test]
+fn run_native_returns_string() {
+    let source = r#"
+print greet("lox") + "!";
+"#;
+    let mut vm = VM::new();
+    let out = OutputBuffer::new();
+    vm.set_output(out.clone());
+    vm.define_native("greet", 1, |allocator, args| match args[0].kind() {
+        ValueKind::String(name) => {
+            let greeting = format!("hello, {}", allocator.deref(&name));
+            Ok(Value::from(allocator.new_string(greeting)))
+        }
+        _ => Err("Argument must be a string.".to_owned()),
+    });
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    assert_eq!("hello, lox!\n", out.contents());
+}
+
+#[test]
+fn run_native_with_state() {
+    let source = r#"
+for (var i = 0; i < 3; i = i + 1) {
+    tick();
+}
+print tick();
+"#;
+    let mut vm = VM::new();
+    let out = OutputBuffer::new();
+    vm.set_output(out.clone());
+    let ticks = Rc::new(Cell::new(0));
+    let counter = Rc::clone(&ticks);
+    vm.define_native("tick", 0, move |_, _| {
+        counter.set(counter.get() + 1);
+        Ok(Value::number(counter.get() as f64))
+    });
+    assert_eq!(InterpretResult::Ok, vm.interpret(source));
+    assert_eq!("4\n", out.contents());
+    assert_eq!(4, ticks.get());
+}
+
+#[test]
+fn run_native_error() {
+    let source = r#"
+fun check(x) {
+    return fail(x);
+}
+check(1);
+"#;
+    let mut vm = VM::new();
+    vm.define_native("fail", 1, |allocator, args| {
+        Err(format!("failed with {}.", args[0].display(allocator)))
+    });
+    let e = runtime_error(vm.interpret(source));
+    assert_eq!("failed with 1.", e.message);
+    assert_eq!(
+        "failed with 1.\n[line 2] in check()\n[line 4] in script",
+        e.to_string()
+    );
+}
+
+#[test]
+fn run_builtin_native_errors() {
+    let mut vm = VM::new();
+    let e = runtime_error(vm.interpret(r#"max(1, "2");"#));
+    assert_eq!("Argument 2 must be a number.", e.message);
+
+    let e = runtime_error(vm.interpret(r#"panic("boom");"#));
+    assert_eq!("panic: boom", e.message);
+
+    let e = runtime_error(vm.interpret("max(1);"));
+    assert_eq!("Expected 2 arguments but got 1.", e.message);
+
+    // the VM is still usable after a native failed.
+    assert_eq!(
+        InterpretResult::Ok,
+        vm.interpret("var a = max(3, 4) + max(1, 2);")
+    );
+    let k = &vm.allocator.new_string("a".to_owned());
+    assert_eq!(6_f64, vm.globals.get(k).expect("no such key").as_number());
+}
+
+#[test]
+fn run_stdlib_math() {
+    let source = r#"
+print sqrt(16);
+print floor(2.7);
+print ceil(2.2);
+print abs(-3);
+print pow(2, 10);
+print min(1, 2);
+"#;
+    let mut vm = VM::new();
+    assert_eq!("4\n2\n3\n3\n1024\n1\n", run_output(&mut vm, source));
+
+    let source = r#"
+seed(42);
+var a = random();
+var b = random();
+seed(42);
+print a == random() and b == random();
+print a != b;
+print 0 <= a and a < 1;
+"#;
+    assert_eq!("true\ntrue\ntrue\n", run_output(&mut vm, source));
+}
+
+#[test]
+fn run_stdlib_strings() {
+    let source = r#"
+print len("héllo");
+print substring("héllo", 1, 3);
+print index_of("héllo", "llo");
+print index_of("héllo", "x");
+print upper("lox") + lower("LOX");
+print to_string(1.5) + to_string(nil);
+print to_number(" 42 ") + 1;
+print to_number("forty");
+"#;
+    let mut vm = VM::new();
+    assert_eq!(
+        "5\nél\n2\n-1\nLOXlox\n1.5nil\n43\nnil\n",
+        run_output(&mut vm, source)
+    );
+
+    let e = runtime_error(vm.interpret(r#"substring("abc", 2, 4);"#));
+    assert_eq!(
+        "Substring range 2..4 is out of bounds for a string of length 3.",
+        e.message
+    );
+    let e = runtime_error(vm.interpret(r#"substring("abc", 0.5, 1);"#));
+    assert_eq!("Argument 2 must be a non-negative integer.", e.message);
+    let e = runtime_error(vm.interpret("len(1);"));
+    assert_eq!("Argument 1 must be a string, a list or a map.", e.message);
+}
+
+#[test]
+fn run_stdlib_time() {
+    let source = r#"
+var start = clock();
+print clock() >= start;
+print time() > 1600000000;
+"#;
+    let mut vm = VM::new();
+    assert_eq!("true\ntrue\n", run_output(&mut vm, source));
+}
+
+#[test]
+fn run_stdlib_files() {
+    let path = std::env::temp_dir().join(format!("lox-stdlib-{}.txt", std::process::id()));
+    let source = format!(
+        r#"
+var path = "{}";
+write_file(path, "line one
+line two");
+print read_file(path);
+"#,
+        path.display()
+    );
+    let mut vm = VM::new();
+    let output = run_output(&mut vm, &source);
+    std::fs::remove_file(&path).expect("failed to remove the file");
+    assert_eq!("line one\nline two\n", output);
+
+    let e = runtime_error(vm.interpret(r#"read_file("/no/such/lox/file");"#));
+    assert!(e
+        .message
+        .starts_with("Could not read file \"/no/such/lox/file\""));
+}
+
+#[test]
+fn run_stdlib_modules() {
+    let mut vm = VM::with_modules(&[Module::Math]);
+    assert_eq!("3\n", run_output(&mut vm, "print sqrt(9);"));
+    for name in &["read_file", "write_file", "read_line", "clock", "upper"] {
+        let e = runtime_error(vm.interpret(&format!("{}();", name)));
+        assert_eq!(format!("Undefined variable '{}'.", name), e.message);
+    }
+
+    // a module can still be loaded later on.
+    vm.load_module(Module::Strings);
+    assert_eq!("ABC\n", run_output(&mut vm, r#"print upper("abc");"#));
+}
@@ -0,0 +1,235 @@
+extern crate lox;
+use crate::common::run;
+use lox::*;
+
+const SCRIPTS: [&str; 12] = [
+    "print 1 + 2 * 3 - 4 / 8; print -(2 - 5); print (1 + 2) * (3 + 4);",
+    "print 1 < 2; print 2 <= 1; print 3 > 3; print 3 >= 3; print 1 == 1; print 1 != 2;",
+    "print 0 / 0 >= 1; print 0 / 0 == 0 / 0; print -0 == 0; print 1 / 0;",
+    r#"print "a" == "a"; print "a" != "b"; print nil == false; print !nil; print !!true;"#,
+    "print (nil and 1); print (false or 2); print (1 and nil) == nil; print (2 >= 1 and 1);",
+    r#"
+var a = 3;
+var b = 5;
+if (a >= b) print "ge"; else print "lt";
+if (a <= b) print "le"; else print "gt";
+if (a != b) { if (a > 1) print "inner"; } else print "outer";
+if (!(a == 3)) print "no"; else print "yes";
+"#,
+    r#"
+var total = 0;
+for (var i = 0; i <= 10; i = i + 1) {
+    if (i >= 5) total = total + i * 2;
+    else total = total - 1;
+}
+while (total != 0 and total >= 80) total = total - 7;
+print total;
+"#,
+    r#"
+fun f(n) {
+    if (n > 2) {
+        return "big";
+    } else {
+        return "small";
+    }
+    print "unreachable";
+}
+fun g() {
+    for (;;) {
+        return 1 + 1;
+    }
+}
+print f(1);
+print f(3);
+print g();
+"#,
+    r#"
+fun make_counter() {
+    var count = 10 * 10;
+    fun counter() {
+        count = count + 2 - 1;
+        return count;
+    }
+    return counter;
+}
+var c = make_counter();
+c();
+print c();
+"#,
+    r#"
+class A {
+    init() { this.x = 2 * 21; }
+    get() { return this.x >= 40; }
+}
+print A().get();
+print [1 + 1, 2 * 2][1 - 1];
+print {"k": 3 - 1}["k"];
+"#,
+    "print 1 + nil;",
+    "print !(1 + 1);",
+];
+
+// the instructions of the compiled function, and of every function declared inside it.
+fn instructions(source: &str, level: OptimizationLevel) -> Vec<Vec<OpCode>> {
+    fn collect(vm: &VM, func_id: Reference<Function>, functions: &mut Vec<Vec<OpCode>>) {
+        let chunk = &vm.allocator.deref(&func_id).chunk;
+        let mut ops = Vec::new();
+        let mut offset = 0;
+        while offset < chunk.code.len() {
+            let (op, next) = chunk.read(offset);
+            ops.push(op);
+            offset = next;
+        }
+        functions.push(ops);
+        for value in &chunk.values {
+            if let ValueKind::Function(id) = value.kind() {
+                collect(vm, id, functions);
+            }
+        }
+    }
+
+    let mut vm = VM::new();
+    vm.set_optimization_level(level);
+    let func_id = vm.compile(source).expect("failed to compile");
+    let mut functions = Vec::new();
+    collect(&vm, func_id, &mut functions);
+    functions
+}
+
+#[test]
+fn optimized_output_is_unchanged() {
+    for source in SCRIPTS.iter() {
+        assert_eq!(
+            run(source, OptimizationLevel::None),
+            run(source, OptimizationLevel::Full),
+            "{}",
+            source
+        );
+    }
+}
+
+#[test]
+fn optimized_bytecode_runs_after_loading() {
+    for source in SCRIPTS.iter() {
+        let mut vm = VM::new();
+        vm.set_optimization_level(OptimizationLevel::Full);
+        let func_id = vm.compile(source).expect("failed to compile");
+        let bytes = serialize(&vm.allocator, &vm.globals, func_id);
+
+        let mut vm = VM::new();
+        let out = OutputBuffer::new();
+        vm.set_output(out.clone());
+        let func_id = vm.load(&bytes).expect("failed to load");
+        let result = vm.execute(func_id);
+        assert_eq!(
+            run(source, OptimizationLevel::None),
+            (result, out.contents())
+        );
+    }
+}
+
+#[test]
+fn fold_constants() {
+    let ops = instructions(
+        "print 1 + 2 * 3; print -(4 - 5) < 2; print !nil;",
+        OptimizationLevel::Full,
+    );
+    assert_eq!(
+        vec![
+            OpCode::Constant(0),
+            OpCode::Print,
+            OpCode::True,
+            OpCode::Print,
+            OpCode::True,
+            OpCode::Print,
+            OpCode::Nil,
+            OpCode::Return,
+        ],
+        ops[0]
+    );
+
+    // operands which would raise an error are left for the VM.
+    let ops = instructions("print 1 + nil; print !1;", OptimizationLevel::Full);
+    assert!(ops[0].contains(&OpCode::Add));
+    assert!(ops[0].contains(&OpCode::Not));
+}
+
+#[test]
+fn unused_constants_are_removed() {
+    let mut vm = VM::new();
+    vm.set_optimization_level(OptimizationLevel::Full);
+    let func_id = vm.compile("print 1 + 2 + 3;").expect("failed to compile");
+    assert_eq!(
+        vec![Value::number(6.0)],
+        vm.allocator.deref(&func_id).chunk.values
+    );
+}
+
+#[test]
+fn collapse_negated_conditions() {
+    let source = "var a = 1; if (a >= 2) print a; while (a != 3) a = a + 1;";
+    let unoptimized = instructions(source, OptimizationLevel::None);
+    assert!(unoptimized[0].contains(&OpCode::Not));
+
+    let ops = &instructions(source, OptimizationLevel::Full)[0];
+    assert!(!ops.contains(&OpCode::Not));
+    assert!(!ops.iter().any(|op| matches!(op, OpCode::JumpIfFalse(_))));
+    assert_eq!(
+        2,
+        ops.iter()
+            .filter(|op| matches!(op, OpCode::JumpIfTrue(_)))
+            .count()
+    );
+}
+
+#[test]
+fn remove_dead_code() {
+    let source = r#"
+fun f() {
+    return 1;
+    print "unreachable";
+}
+"#;
+    let ops = instructions(source, OptimizationLevel::Full);
+    assert_eq!(vec![OpCode::Constant(0), OpCode::Return], ops[1]);
+}
+
+#[test]
+fn thread_jumps() {
+    let source = r#"
+var a = true;
+if (a) {
+    if (a) print 1; else print 2;
+} else {
+    print 3;
+}
+print 4;
+"#;
+    let mut vm = VM::new();
+    vm.set_optimization_level(OptimizationLevel::Full);
+    let func_id = vm.compile(source).expect("failed to compile");
+    let chunk = &vm.allocator.deref(&func_id).chunk;
+
+    let mut offset = 0;
+    let mut jumps = 0;
+    while offset < chunk.code.len() {
+        let (op, next) = chunk.read(offset);
+        if let OpCode::Jump(jump) = op {
+            // no jump goes to another unconditional jump.
+            let (target, _) = chunk.read(next + jump);
+            assert!(!matches!(target, OpCode::Jump(_)), "{:?}", target);
+            jumps += 1;
+        }
+        offset = next;
+    }
+    assert_eq!(2, jumps);
+}
+
+#[test]
+fn optimized_lines_are_kept() {
+    let source = "var a = 1;\nvar b = 2 * 3;\n\nprint a + nil;\n";
+    match run(source, OptimizationLevel::Full).0 {
+        InterpretResult::RuntimeError(e) => assert_eq!(3, e.line),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
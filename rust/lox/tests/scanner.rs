@@ -7,27 +7,27 @@ fn run_arithmetic() {
     let mut sc = Scanner::new(source);
 
     let tok = sc.scan_token();
-    assert_eq!(true, tok.is_ok());
+    assert!(tok.is_ok());
     let tok = tok.unwrap();
     assert_eq!(TokenType::Number, tok.typ);
     assert_eq!("1.2", tok.source);
     let tok = sc.scan_token();
-    assert_eq!(true, tok.is_ok());
+    assert!(tok.is_ok());
     let tok = tok.unwrap();
     assert_eq!(TokenType::Plus, tok.typ);
     assert_eq!("+", tok.source);
     let tok = sc.scan_token();
-    assert_eq!(true, tok.is_ok());
+    assert!(tok.is_ok());
     let tok = tok.unwrap();
     assert_eq!(TokenType::Number, tok.typ);
     assert_eq!("3.8", tok.source);
     let tok = sc.scan_token();
-    assert_eq!(true, tok.is_ok());
+    assert!(tok.is_ok());
     let tok = tok.unwrap();
     assert_eq!(TokenType::EqualEqual, tok.typ);
     assert_eq!("==", tok.source);
     let tok = sc.scan_token();
-    assert_eq!(true, tok.is_ok());
+    assert!(tok.is_ok());
     let tok = tok.unwrap();
     assert_eq!(TokenType::Number, tok.typ);
     assert_eq!("5", tok.source);
@@ -42,34 +42,75 @@ print 1 + 2;
 "#;
     let mut sc = Scanner::new(source);
     let tok = sc.scan_token();
-    assert_eq!(true, tok.is_ok());
+    assert!(tok.is_ok());
     let tok = tok.unwrap();
     assert_eq!(TokenType::Print, tok.typ);
     assert_eq!("print", tok.source);
     assert_eq!(2, tok.line);
     let tok = sc.scan_token();
-    assert_eq!(true, tok.is_ok());
+    assert!(tok.is_ok());
     let tok = tok.unwrap();
     assert_eq!(TokenType::Number, tok.typ);
     assert_eq!("1", tok.source);
     let tok = sc.scan_token();
-    assert_eq!(true, tok.is_ok());
+    assert!(tok.is_ok());
     let tok = tok.unwrap();
     assert_eq!(TokenType::Plus, tok.typ);
     assert_eq!("+", tok.source);
     let tok = sc.scan_token();
-    assert_eq!(true, tok.is_ok());
+    assert!(tok.is_ok());
     let tok = tok.unwrap();
     assert_eq!(TokenType::Number, tok.typ);
     assert_eq!("2", tok.source);
     let tok = sc.scan_token();
-    assert_eq!(true, tok.is_ok());
+    assert!(tok.is_ok());
     let tok = tok.unwrap();
     assert_eq!(TokenType::SemiColon, tok.typ);
     assert_eq!(";", tok.source);
     let tok = sc.scan_token();
-    assert_eq!(true, tok.is_ok());
+    assert!(tok.is_ok());
     let tok = tok.unwrap();
     assert_eq!(TokenType::Eof, tok.typ);
     assert_eq!(4, tok.line);
 }
+
+#[test]
+fn run_scan_errors() {
+    let source = "var a = 1 / 2;\nvar b = # \"open";
+    let (tokens, errors) = Scanner::new(source).scan_tokens();
+
+    let slash = tokens[4];
+    assert_eq!(TokenType::Slash, slash.typ);
+    assert_eq!((0, 10), (slash.line, slash.column));
+    let b = tokens[8];
+    assert_eq!("b", b.source);
+    assert_eq!((1, 4), (b.line, b.column));
+    assert_eq!(TokenType::Eof, tokens.last().unwrap().typ);
+
+    assert_eq!(2, errors.len());
+    assert_eq!("Unexpected character.", errors[0].message);
+    assert_eq!("#", errors[0].lexeme);
+    assert_eq!((1, 8), (errors[0].line, errors[0].column));
+    assert_eq!("Unterminated string.", errors[1].message);
+    assert_eq!((1, 10), (errors[1].line, errors[1].column));
+}
+
+#[test]
+fn run_scan_non_ascii() {
+    let source = "print \"héllo\" + ü;";
+    let (tokens, errors) = Scanner::new(source).scan_tokens();
+    assert_eq!("\"héllo\"", tokens[1].source);
+    assert_eq!(TokenType::Plus, tokens[2].typ);
+    assert_eq!(TokenType::SemiColon, tokens[3].typ);
+    assert_eq!(1, errors.len());
+    assert_eq!("ü", errors[0].lexeme);
+
+    // a slash at the very end used to look past the source.
+    let (tokens, _) = Scanner::new("1 /").scan_tokens();
+    assert_eq!(TokenType::Slash, tokens[1].typ);
+
+    // a string spanning lines is positioned where it starts.
+    let (tokens, _) = Scanner::new("var s = \"a\nb\"; s").scan_tokens();
+    assert_eq!((0, 8), (tokens[3].line, tokens[3].column));
+    assert_eq!((1, 4), (tokens[5].line, tokens[5].column));
+}
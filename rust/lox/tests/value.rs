@@ -0,0 +1,85 @@
+extern crate lox;
+use lox::*;
+
+#[test]
+fn value_kinds_round_trip() {
+    let mut allocator = Allocator::default();
+    let s = allocator.new_string("s".to_owned());
+    let list = allocator.alloc(List::new(vec![]));
+    let map = allocator.alloc(Map::new());
+
+    let kinds = [
+        ValueKind::Nil,
+        ValueKind::Bool(true),
+        ValueKind::Bool(false),
+        ValueKind::Number(0.0),
+        ValueKind::Number(-1.5),
+        ValueKind::Number(f64::INFINITY),
+        ValueKind::Number(f64::NEG_INFINITY),
+        ValueKind::Number(f64::MAX),
+        ValueKind::Number(f64::MIN_POSITIVE),
+        ValueKind::String(s),
+        ValueKind::List(list),
+        ValueKind::Map(map),
+    ];
+    for &kind in kinds.iter() {
+        assert_eq!(kind, Value::from(kind).kind());
+    }
+    assert_eq!(ValueKind::Number(2.0), Value::number(2.0).kind());
+    assert_eq!(ValueKind::Bool(true), Value::bool(true).kind());
+    assert_eq!(ValueKind::Nil, Value::NIL.kind());
+    assert_eq!(ValueKind::String(s), Value::from(s).kind());
+    assert_ne!(Value::from(list), Value::from(map));
+}
+
+#[test]
+fn nan_is_a_number() {
+    // whatever bits a NaN has, it stays a number rather than turning into another value.
+    for &bits in [
+        0x7ff8_0000_0000_0000u64,
+        0xfff8_0000_0000_0000,
+        0x7fff_ffff_ffff_ffff,
+    ]
+    .iter()
+    {
+        let n = f64::from_bits(bits);
+        match Value::number(n).kind() {
+            ValueKind::Number(n) => assert!(n.is_nan()),
+            other => panic!("NaN became {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn value_equality() {
+    assert_eq!(Value::number(1.0), Value::number(1.0));
+    assert_eq!(Value::number(0.0), Value::number(-0.0));
+    assert_ne!(Value::number(f64::NAN), Value::number(f64::NAN));
+    assert_ne!(Value::number(0.0), Value::bool(false));
+    assert_ne!(Value::NIL, Value::bool(false));
+    assert_eq!(Value::bool(true), Value::bool(true));
+}
+
+#[test]
+fn value_api() {
+    assert!(Value::NIL.is_falsy());
+    assert!(Value::bool(false).is_falsy());
+    assert!(!Value::bool(true).is_falsy());
+    assert!(!Value::number(0.0).is_falsy());
+    assert_eq!(2.5, Value::number(2.5).as_number());
+    assert!(Value::bool(true).as_bool());
+    assert_eq!("nil", Value::NIL.to_string());
+    assert_eq!("-3", Value::number(-3.0).to_string());
+    assert_eq!("false", Value::bool(false).to_string());
+
+    let mut allocator = Allocator::default();
+    let s = allocator.new_string("hello".to_owned());
+    assert_eq!(s, Value::from(s).as_string());
+    assert_eq!("hello", Value::from(s).display(&allocator).to_string());
+}
+
+#[cfg(feature = "nan_boxing")]
+#[test]
+fn nan_boxed_value_size() {
+    assert_eq!(8, std::mem::size_of::<Value>());
+}